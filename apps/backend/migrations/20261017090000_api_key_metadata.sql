@@ -0,0 +1,16 @@
+-- Migration: api_key_metadata
+-- Created: 2026-10-17 09:00:00
+-- Track key prefixes, usage and revocation for API keys
+
+-- UP
+alter table api_keys add column key_prefix varchar(32) not null default '';
+alter table api_keys add column last_used_at timestamptz;
+alter table api_keys add column revoked_at timestamptz;
+
+create unique index idx_api_keys_key_hash_unique on api_keys(key_hash);
+
+-- DOWN
+drop index if exists idx_api_keys_key_hash_unique;
+alter table api_keys drop column if exists revoked_at;
+alter table api_keys drop column if exists last_used_at;
+alter table api_keys drop column if exists key_prefix;
@@ -0,0 +1,73 @@
+-- Migration: feature_flag_environments
+-- Created: 2026-10-17 10:00:00
+-- Move flag state and overrides from the project level to each environment
+
+-- UP
+create table feature_flag_environments (
+    id uuid default uuid_generate_v4() primary key,
+    feature_flag_id uuid not null references feature_flags(id) on delete cascade,
+    environment_id uuid not null references environments(id) on delete cascade,
+    is_enabled boolean not null default false,
+    value jsonb,
+    created_at timestamptz default current_timestamp,
+    updated_at timestamptz default current_timestamp,
+    unique (feature_flag_id, environment_id)
+);
+
+create index idx_feature_flag_environments_environment_id on feature_flag_environments(environment_id);
+
+-- Every existing flag starts out with the same state in every environment of its project
+insert into feature_flag_environments (feature_flag_id, environment_id, is_enabled, value)
+select f.id, e.id, coalesce(f.is_enabled, false), f.value
+from feature_flags f
+join environments e on e.project_id = f.project_id;
+
+alter table feature_flag_overrides add column environment_id uuid references environments(id) on delete cascade;
+alter table feature_flag_overrides drop constraint feature_flag_overrides_feature_flag_id_audience_id_key;
+
+insert into feature_flag_overrides (feature_flag_id, audience_id, is_enabled, type, value, created_at, updated_at, environment_id)
+select o.feature_flag_id, o.audience_id, o.is_enabled, o.type, o.value, o.created_at, o.updated_at, e.id
+from feature_flag_overrides o
+join feature_flags f on f.id = o.feature_flag_id
+join environments e on e.project_id = f.project_id
+where o.environment_id is null;
+
+delete from feature_flag_overrides where environment_id is null;
+
+alter table feature_flag_overrides alter column environment_id set not null;
+alter table feature_flag_overrides add constraint feature_flag_overrides_flag_audience_environment_key
+    unique (feature_flag_id, audience_id, environment_id);
+create index idx_feature_flag_overrides_environment_id on feature_flag_overrides(environment_id);
+
+alter table feature_flags drop column is_enabled;
+alter table feature_flags drop column value;
+
+-- DOWN
+alter table feature_flags add column is_enabled boolean default false;
+alter table feature_flags add column value jsonb;
+
+-- Environments are collapsed back into a single state, the oldest environment wins
+update feature_flags f
+set is_enabled = s.is_enabled, value = s.value
+from (
+    select distinct on (fe.feature_flag_id) fe.feature_flag_id, fe.is_enabled, fe.value
+    from feature_flag_environments fe
+    join environments e on e.id = fe.environment_id
+    order by fe.feature_flag_id, e.created_at, e.id
+) s
+where s.feature_flag_id = f.id;
+
+drop index if exists idx_feature_flag_overrides_environment_id;
+alter table feature_flag_overrides drop constraint if exists feature_flag_overrides_flag_audience_environment_key;
+
+delete from feature_flag_overrides o
+using feature_flag_overrides other
+where o.feature_flag_id = other.feature_flag_id
+  and o.audience_id = other.audience_id
+  and o.id > other.id;
+
+alter table feature_flag_overrides drop column if exists environment_id;
+alter table feature_flag_overrides add constraint feature_flag_overrides_feature_flag_id_audience_id_key
+    unique (feature_flag_id, audience_id);
+
+drop table if exists feature_flag_environments;
@@ -0,0 +1,11 @@
+-- Migration: rollouts
+-- Created: 2026-10-17 11:00:00
+-- Percentage and weighted variant rollouts on flag states and overrides
+
+-- UP
+alter table feature_flag_environments add column rollout jsonb;
+alter table feature_flag_overrides add column rollout jsonb;
+
+-- DOWN
+alter table feature_flag_overrides drop column if exists rollout;
+alter table feature_flag_environments drop column if exists rollout;
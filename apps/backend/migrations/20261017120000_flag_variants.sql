@@ -0,0 +1,22 @@
+-- Migration: flag_variants
+-- Created: 2026-10-17 12:00:00
+-- Named, typed variants for multivariate flags and JSON Schemas for JSON flags
+
+-- UP
+create table feature_flag_variants (
+    id uuid default uuid_generate_v4() primary key,
+    feature_flag_id uuid not null references feature_flags(id) on delete cascade,
+    name varchar(100) not null,
+    value_type value_type not null,
+    value jsonb not null,
+    description text,
+    created_at timestamptz default current_timestamp,
+    updated_at timestamptz default current_timestamp,
+    unique (feature_flag_id, name)
+);
+
+alter table feature_flags add column json_schema jsonb;
+
+-- DOWN
+alter table feature_flags drop column if exists json_schema;
+drop table if exists feature_flag_variants;
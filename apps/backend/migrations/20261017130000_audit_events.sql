@@ -0,0 +1,27 @@
+-- Migration: audit_events
+-- Created: 2026-10-17 13:00:00
+-- Append-only history of configuration changes. Ids are kept without foreign keys
+-- so events outlive the entities and actors they describe.
+
+-- UP
+create table audit_events (
+    id uuid default uuid_generate_v4() primary key,
+    actor_user_id uuid,
+    actor_api_key_id uuid,
+    action varchar(100) not null,
+    entity_type varchar(100) not null,
+    entity_id uuid not null,
+    project_id uuid,
+    environment_id uuid,
+    before jsonb,
+    after jsonb,
+    diff jsonb not null default '{}',
+    created_at timestamptz not null default current_timestamp
+);
+
+create index idx_audit_events_entity on audit_events(entity_type, entity_id, created_at desc);
+create index idx_audit_events_project on audit_events(project_id, created_at desc);
+create index idx_audit_events_created_at on audit_events(created_at desc);
+
+-- DOWN
+drop table if exists audit_events;
@@ -0,0 +1,33 @@
+-- Migration: project_roles
+-- Created: 2026-10-17 14:00:00
+-- Per-project roles for project members. Every project has at most one owner.
+
+-- UP
+create type project_role as enum ('owner', 'editor', 'viewer');
+
+alter table project_owners
+    add column role project_role not null default 'owner',
+    add column created_at timestamptz default current_timestamp;
+
+-- Existing rows all became owners, keep one per project and demote the rest.
+-- created_at was only just added and is the same for every row, so the lowest user_id stays owner.
+update project_owners po
+set role = 'editor'
+where exists (
+    select 1 from project_owners other
+    where other.project_id = po.project_id
+      and other.user_id < po.user_id
+);
+
+create unique index idx_project_owners_owner on project_owners(project_id) where role = 'owner';
+create index idx_project_owners_user_id on project_owners(user_id);
+
+-- DOWN
+drop index if exists idx_project_owners_user_id;
+drop index if exists idx_project_owners_owner;
+
+alter table project_owners
+    drop column if exists created_at,
+    drop column if exists role;
+
+drop type if exists project_role;
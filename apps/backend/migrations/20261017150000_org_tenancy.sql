@@ -0,0 +1,57 @@
+-- Migration: org_tenancy
+-- Created: 2026-10-17 15:00:00
+-- Every user and project belongs to an org, users join orgs through invitations,
+-- and audit events are attributed to the org they happened in.
+
+-- UP
+-- Both slug and name are unique. An existing default-org slug is reused, and another org
+-- already named 'Default Org' only changes the name, so the select by slug below always finds one.
+insert into orgs (name, slug, region)
+select case when exists (select 1 from orgs where name = 'Default Org')
+            then 'Default Org (default-org)' else 'Default Org' end,
+       'default-org', 'us-east-1'
+on conflict do nothing;
+
+update users set org_id = (select id from orgs where slug = 'default-org') where org_id is null;
+update projects set org_id = (select id from orgs where slug = 'default-org') where org_id is null;
+
+alter table users alter column org_id set not null;
+alter table projects alter column org_id set not null;
+
+create index idx_users_org_id on users(org_id);
+create index idx_projects_org_id on projects(org_id);
+
+create table org_invitations (
+    id uuid default uuid_generate_v4() primary key,
+    org_id uuid not null references orgs(id) on delete cascade,
+    email varchar(255) not null,
+    role user_role not null default 'viewer',
+    token_hash varchar(255) not null unique,
+    invited_by uuid references users(id) on delete set null,
+    expires_at timestamptz not null default (current_timestamp + interval '7 days'),
+    accepted_at timestamptz,
+    created_at timestamptz default current_timestamp
+);
+
+create index idx_org_invitations_org_id on org_invitations(org_id);
+
+alter table audit_events add column org_id uuid;
+
+update audit_events a set org_id = coalesce(
+    (select org_id from projects where id = a.project_id),
+    (select org_id from users where id = a.actor_user_id)
+);
+
+create index idx_audit_events_org_id on audit_events(org_id, created_at desc);
+
+-- DOWN
+drop index if exists idx_audit_events_org_id;
+alter table audit_events drop column if exists org_id;
+
+drop table if exists org_invitations;
+
+drop index if exists idx_projects_org_id;
+drop index if exists idx_users_org_id;
+
+alter table projects alter column org_id drop not null;
+alter table users alter column org_id drop not null;
@@ -0,0 +1,29 @@
+-- Migration: unique_project_names
+-- Created: 2026-10-17 16:00:00
+-- Project names are unique within an org, environment names within a project.
+
+-- UP
+-- Legacy projects were all moved into default-org, so names may already repeat.
+-- The oldest keeps its name, later ones get ' (2)', ' (3)' and so on, within the 100 characters.
+update projects p
+set name = left(d.name, 100 - length(d.n::text) - 3) || ' (' || d.n || ')'
+from (
+    select id, name, row_number() over (partition by org_id, name order by created_at, id) as n
+    from projects
+) d
+where d.id = p.id and d.n > 1;
+
+update environments e
+set name = left(d.name, 100 - length(d.n::text) - 3) || ' (' || d.n || ')'
+from (
+    select id, name, row_number() over (partition by project_id, name order by created_at, id) as n
+    from environments
+) d
+where d.id = e.id and d.n > 1;
+
+create unique index idx_projects_org_id_name on projects(org_id, name);
+create unique index idx_environments_project_id_name on environments(project_id, name);
+
+-- DOWN
+drop index if exists idx_environments_project_id_name;
+drop index if exists idx_projects_org_id_name;
@@ -0,0 +1,22 @@
+-- Migration: sessions
+-- Created: 2026-10-17 17:00:00
+-- Server-side sessions for refresh tokens. A session is one family of rotated refresh tokens,
+-- only the token carrying its current jti can be exchanged.
+
+-- UP
+create table sessions (
+    id uuid default uuid_generate_v4() primary key,
+    user_id uuid not null references users(id) on delete cascade,
+    refresh_jti uuid not null,
+    user_agent text,
+    expires_at timestamptz not null,
+    revoked_at timestamptz,
+    revoked_reason varchar(50),
+    last_used_at timestamptz default current_timestamp,
+    created_at timestamptz default current_timestamp
+);
+
+create index idx_sessions_user_id on sessions(user_id, created_at desc);
+
+-- DOWN
+drop table if exists sessions;
@@ -0,0 +1,19 @@
+-- Migration: password_resets
+-- Created: 2026-10-17 18:00:00
+-- Tokens for resetting a forgotten password, stored as SHA-256 hashes.
+-- A token can be used once and only until it expires.
+
+-- UP
+create table password_resets (
+    id uuid default uuid_generate_v4() primary key,
+    user_id uuid not null references users(id) on delete cascade,
+    token_hash varchar(64) not null unique,
+    expires_at timestamptz not null,
+    used_at timestamptz,
+    created_at timestamptz default current_timestamp
+);
+
+create index idx_password_resets_user_id on password_resets(user_id);
+
+-- DOWN
+drop table if exists password_resets;
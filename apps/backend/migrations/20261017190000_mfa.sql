@@ -0,0 +1,30 @@
+-- Migration: mfa
+-- Created: 2026-10-17 19:00:00
+-- TOTP second factor with hashed one-time recovery codes, and orgs that require it.
+-- A TOTP secret only counts once enrollment is confirmed with a code.
+
+-- UP
+create table user_mfa (
+    user_id uuid primary key references users(id) on delete cascade,
+    totp_secret varchar(64) not null,
+    enabled_at timestamptz,
+    last_used_step bigint,
+    created_at timestamptz default current_timestamp
+);
+
+create table mfa_recovery_codes (
+    id uuid default uuid_generate_v4() primary key,
+    user_id uuid not null references users(id) on delete cascade,
+    code_hash varchar(64) not null,
+    used_at timestamptz,
+    created_at timestamptz default current_timestamp
+);
+
+create index idx_mfa_recovery_codes_user_id on mfa_recovery_codes(user_id);
+
+alter table orgs add column require_mfa boolean not null default false;
+
+-- DOWN
+alter table orgs drop column if exists require_mfa;
+drop table if exists mfa_recovery_codes;
+drop table if exists user_mfa;
@@ -0,0 +1,31 @@
+-- Migration: oidc
+-- Created: 2026-10-17 20:00:00
+-- Single sign-on through an OIDC identity provider. Identities link a provider's subject to a user,
+-- logins keep the state, nonce and PKCE verifier of an authorization request until its callback.
+
+-- UP
+create table user_identities (
+    id uuid default uuid_generate_v4() primary key,
+    user_id uuid not null references users(id) on delete cascade,
+    issuer varchar(255) not null,
+    subject varchar(255) not null,
+    email varchar(255),
+    created_at timestamptz default current_timestamp,
+    last_login_at timestamptz,
+    unique (issuer, subject)
+);
+
+create index idx_user_identities_user_id on user_identities(user_id);
+
+create table oidc_logins (
+    id uuid default uuid_generate_v4() primary key,
+    state_hash varchar(64) not null unique,
+    nonce varchar(128) not null,
+    code_verifier varchar(128) not null,
+    expires_at timestamptz not null,
+    created_at timestamptz default current_timestamp
+);
+
+-- DOWN
+drop table if exists oidc_logins;
+drop table if exists user_identities;
@@ -0,0 +1,11 @@
+-- Migration: login_lockout
+-- Created: 2026-10-17 21:00:00
+-- Failed password attempts per user, locking the account for progressively longer after too many.
+
+-- UP
+alter table users add column failed_login_attempts integer not null default 0;
+alter table users add column locked_until timestamptz;
+
+-- DOWN
+alter table users drop column if exists locked_until;
+alter table users drop column if exists failed_login_attempts;
@@ -0,0 +1,33 @@
+-- Migration: registration_controls
+-- Created: 2026-10-17 22:00:00
+-- Email verification of new accounts, with single-use tokens stored as SHA-256 hashes,
+-- and per-org control over who may sign up to join without an invitation.
+-- Existing accounts are treated as verified.
+
+-- UP
+create type registration_mode as enum ('open', 'invite_only', 'allowed_domains');
+
+alter table users add column email_verified_at timestamptz;
+update users set email_verified_at = coalesce(created_at, current_timestamp);
+
+create table email_verifications (
+    id uuid default uuid_generate_v4() primary key,
+    user_id uuid not null references users(id) on delete cascade,
+    token_hash varchar(64) not null unique,
+    expires_at timestamptz not null,
+    used_at timestamptz,
+    created_at timestamptz default current_timestamp
+);
+
+create index idx_email_verifications_user_id on email_verifications(user_id);
+
+alter table orgs
+    add column registration_mode registration_mode not null default 'invite_only',
+    add column allowed_email_domains jsonb not null default '[]';
+
+-- DOWN
+alter table orgs drop column if exists allowed_email_domains;
+alter table orgs drop column if exists registration_mode;
+drop table if exists email_verifications;
+alter table users drop column if exists email_verified_at;
+drop type if exists registration_mode;
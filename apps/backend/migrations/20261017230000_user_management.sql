@@ -0,0 +1,20 @@
+-- Migration: user_management
+-- Created: 2026-10-17 23:00:00
+-- Lets admins deactivate accounts and require a password reset before the next password sign in.
+-- API keys outlive the user who issued them, deleting a user no longer deletes their keys.
+
+-- UP
+alter table users add column deactivated_at timestamptz;
+alter table users add column password_reset_required boolean not null default false;
+
+alter table api_keys drop constraint api_keys_user_id_fkey;
+alter table api_keys add constraint api_keys_user_id_fkey
+    foreign key (user_id) references users(id) on delete set null;
+
+-- DOWN
+alter table api_keys drop constraint api_keys_user_id_fkey;
+alter table api_keys add constraint api_keys_user_id_fkey
+    foreign key (user_id) references users(id) on delete cascade;
+
+alter table users drop column if exists password_reset_required;
+alter table users drop column if exists deactivated_at;
@@ -143,6 +143,9 @@ fn generate_struct(table_name: &str, columns: &[Column]) -> String {
             println!("{:?}", column);
         }
         let rust_type = pg_type_to_rust(&column.data_type, column.is_nullable);
+        if is_secret_column(&column.name) {
+            output.push_str("    #[serde(skip_serializing)]\n");
+        }
         output.push_str(&format!(
             "    pub {}: {},\n",
             key_sanitizer(&column.name),
@@ -154,10 +157,21 @@ fn generate_struct(table_name: &str, columns: &[Column]) -> String {
     output
 }
 
+/// Password and token hashes and MFA secrets never leave the server, whatever serializes the row
+fn is_secret_column(column_name: &str) -> bool {
+    column_name.ends_with("_hash") || column_name.ends_with("_secret")
+}
+
 fn is_custom_enum(pg_type: &str) -> bool {
     matches!(
         pg_type,
-        "user_role" | "feature_flag_type" | "value_type" | "match_operator" | "audience_scope"
+        "user_role"
+            | "feature_flag_type"
+            | "value_type"
+            | "match_operator"
+            | "audience_scope"
+            | "project_role"
+            | "registration_mode"
     )
 }
 
@@ -168,6 +182,8 @@ fn pg_type_to_rust_enum(pg_type: &str) -> &'static str {
         "value_type" => "ValueType",
         "match_operator" => "MatchOperator",
         "audience_scope" => "AudienceScope",
+        "project_role" => "ProjectRole",
+        "registration_mode" => "RegistrationMode",
         _ => "String",
     }
 }
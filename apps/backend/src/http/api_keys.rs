@@ -0,0 +1,290 @@
+use crate::models::db::ApiKeys;
+use crate::pkg::audit::{self, AuditEvent};
+use crate::pkg::auth::{self, AuthUser, ProjectMember};
+use crate::pkg::cache;
+use crate::pkg::error::AppError;
+use crate::pkg::permissions::require;
+use crate::pkg::response::DataResponse;
+use crate::pkg::state::AppState;
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+};
+use axum_extra::extract::WithRejection;
+use chrono::{DateTime, Utc};
+use pgmap::FromRow;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(utoipa::OpenApi)]
+#[openapi(
+    paths(
+        list_api_keys,
+        create_api_key,
+        revoke_api_key,
+    ),
+    components(
+        schemas(
+            CreateApiKeyRequest,
+            ApiKeySummary,
+            CreatedApiKey,
+        ),
+    ),
+    tags(
+        (name = "API Keys", description = "Environment API key management endpoints"),
+    ),
+)]
+#[allow(dead_code)]
+pub struct ApiKeysApi;
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CreateApiKeyRequest {
+    #[serde(default)]
+    pub is_server_key: bool,
+}
+
+/// An API key as shown in listings, never includes the key or its hash
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct ApiKeySummary {
+    pub id: Uuid,
+    pub environment_id: Option<Uuid>,
+    pub user_id: Option<Uuid>,
+    pub is_server_key: bool,
+    /// First characters of the key followed by an ellipsis
+    pub masked_key: String,
+    pub created_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl From<ApiKeys> for ApiKeySummary {
+    fn from(key: ApiKeys) -> Self {
+        ApiKeySummary {
+            id: key.id,
+            environment_id: key.environment_id,
+            user_id: key.user_id,
+            is_server_key: key.is_server_key.unwrap_or(false),
+            masked_key: format!("{}…", key.key_prefix),
+            created_at: key.created_at,
+            last_used_at: key.last_used_at,
+            revoked_at: key.revoked_at,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CreatedApiKey {
+    /// The full key, only returned once at creation time
+    pub key: String,
+    pub api_key: ApiKeySummary,
+}
+
+fn parse_api_key(row: &tokio_postgres::Row) -> Result<ApiKeys, AppError> {
+    ApiKeys::from_row(row)
+        .map_err(|_| AppError::InternalError("Failed to parse API key data".to_string()))
+}
+
+async fn ensure_environment(
+    client: &deadpool_postgres::Client,
+    project_id: Uuid,
+    environment_id: Uuid,
+) -> Result<(), AppError> {
+    client
+        .query_opt(
+            "SELECT id FROM environments WHERE id = $1 AND project_id = $2",
+            &[&environment_id, &project_id],
+        )
+        .await?
+        .ok_or(AppError::NotFound("Environment not found".to_string()))?;
+
+    Ok(())
+}
+
+/// Issue a key for an environment and record it, the plaintext key is only returned here
+pub(crate) async fn insert_api_key(
+    client: &impl deadpool_postgres::GenericClient,
+    auth_user: &AuthUser,
+    project_id: Uuid,
+    environment_id: Uuid,
+    is_server_key: bool,
+) -> Result<CreatedApiKey, AppError> {
+    let (key, key_prefix) = auth::generate_api_key(is_server_key)?;
+
+    let row = client
+        .query_one(
+            "INSERT INTO api_keys (user_id, environment_id, is_server_key, key_hash, key_prefix)
+             VALUES ($1, $2, $3, $4, $5)
+             RETURNING *",
+            &[
+                &auth_user.id,
+                &environment_id,
+                &is_server_key,
+                &auth::hash_api_key(&key),
+                &key_prefix,
+            ],
+        )
+        .await?;
+    let api_key = ApiKeySummary::from(parse_api_key(&row)?);
+
+    audit::record(
+        client,
+        AuditEvent::new(auth_user, "api_key.created", "api_key", api_key.id)
+            .project(project_id)
+            .environment(environment_id)
+            .after(&api_key),
+    )
+    .await?;
+
+    Ok(CreatedApiKey { key, api_key })
+}
+
+/// List the API keys of an environment
+#[utoipa::path(
+    get,
+    path = "/v1/projects/{project_id}/environments/{environment_id}/api-keys",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+        ("environment_id" = Uuid, Path, description = "Environment ID"),
+    ),
+    responses(
+        (status = 200, description = "API keys", body = DataResponse<Vec<ApiKeySummary>>),
+        (status = 404, description = "Environment not found", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "API Keys"
+)]
+async fn list_api_keys(
+    State(state): State<AppState>,
+    ProjectMember(_auth_user, _): ProjectMember<require::ApiKeyManage>,
+    Path((project_id, environment_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<DataResponse<Vec<ApiKeySummary>>>, AppError> {
+    let client = state.db_pool.get().await?;
+
+    ensure_environment(&client, project_id, environment_id).await?;
+
+    let rows = client
+        .query(
+            "SELECT * FROM api_keys WHERE environment_id = $1 ORDER BY created_at DESC",
+            &[&environment_id],
+        )
+        .await?;
+
+    let keys = rows
+        .iter()
+        .map(|row| parse_api_key(row).map(ApiKeySummary::from))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Json(DataResponse::new().data(keys).build()))
+}
+
+/// Issue a new API key for an environment
+#[utoipa::path(
+    post,
+    path = "/v1/projects/{project_id}/environments/{environment_id}/api-keys",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+        ("environment_id" = Uuid, Path, description = "Environment ID"),
+    ),
+    request_body = CreateApiKeyRequest,
+    responses(
+        (status = 200, description = "API key created, the key is only shown once", body = DataResponse<CreatedApiKey>),
+        (status = 404, description = "Environment not found", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "API Keys"
+)]
+async fn create_api_key(
+    State(state): State<AppState>,
+    ProjectMember(auth_user, _): ProjectMember<require::ApiKeyManage>,
+    Path((project_id, environment_id)): Path<(Uuid, Uuid)>,
+    WithRejection(Json(payload), _): WithRejection<Json<CreateApiKeyRequest>, AppError>,
+) -> Result<Json<DataResponse<CreatedApiKey>>, AppError> {
+    let mut client = state.db_pool.get().await?;
+
+    ensure_environment(&client, project_id, environment_id).await?;
+
+    let transaction = client.transaction().await?;
+    let created = insert_api_key(
+        &transaction,
+        &auth_user,
+        project_id,
+        environment_id,
+        payload.is_server_key,
+    )
+    .await?;
+    transaction.commit().await?;
+
+    Ok(Json(DataResponse::new().data(created).build()))
+}
+
+/// Revoke an API key, it stops working immediately
+#[utoipa::path(
+    delete,
+    path = "/v1/projects/{project_id}/environments/{environment_id}/api-keys/{key_id}",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+        ("environment_id" = Uuid, Path, description = "Environment ID"),
+        ("key_id" = Uuid, Path, description = "API key ID"),
+    ),
+    responses(
+        (status = 200, description = "API key revoked", body = DataResponse<ApiKeySummary>),
+        (status = 404, description = "API key not found or already revoked", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "API Keys"
+)]
+async fn revoke_api_key(
+    State(state): State<AppState>,
+    ProjectMember(auth_user, _): ProjectMember<require::ApiKeyManage>,
+    Path((project_id, environment_id, key_id)): Path<(Uuid, Uuid, Uuid)>,
+) -> Result<Json<DataResponse<ApiKeySummary>>, AppError> {
+    let mut client = state.db_pool.get().await?;
+
+    ensure_environment(&client, project_id, environment_id).await?;
+
+    let transaction = client.transaction().await?;
+
+    let row = transaction
+        .query_opt(
+            "UPDATE api_keys SET revoked_at = current_timestamp, updated_at = current_timestamp
+             WHERE id = $1 AND environment_id = $2 AND revoked_at IS NULL
+             RETURNING *",
+            &[&key_id, &environment_id],
+        )
+        .await?
+        .ok_or(AppError::NotFound("API key not found".to_string()))?;
+    let revoked = parse_api_key(&row)?;
+    let key_hash = revoked.key_hash.clone();
+    let api_key = ApiKeySummary::from(revoked);
+
+    let before = ApiKeySummary {
+        revoked_at: None,
+        ..api_key.clone()
+    };
+    audit::record(
+        &transaction,
+        AuditEvent::new(&auth_user, "api_key.revoked", "api_key", api_key.id)
+            .project(project_id)
+            .environment(environment_id)
+            .before(&before)
+            .after(&api_key),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    if let Err(e) = cache::forget_api_key(&state.redis_pool, &key_hash).await {
+        eprintln!("Failed to drop cached API key: {}", e);
+    }
+
+    Ok(Json(DataResponse::new().data(api_key).build()))
+}
+
+pub fn router() -> Router<AppState> {
+    let api_key_routes = Router::new()
+        .route("/", axum::routing::get(list_api_keys).post(create_api_key))
+        .route("/{key_id}", axum::routing::delete(revoke_api_key));
+
+    Router::new().nest(
+        "/v1/projects/{project_id}/environments/{environment_id}/api-keys",
+        api_key_routes,
+    )
+}
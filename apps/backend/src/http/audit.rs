@@ -0,0 +1,177 @@
+use crate::models::db::AuditEvents;
+use crate::models::enums::UserRole;
+use crate::pkg::auth::Authorized;
+use crate::pkg::error::AppError;
+use crate::pkg::permissions::require;
+use crate::pkg::response::DataResponse;
+use crate::pkg::state::AppState;
+use axum::{
+    Json, Router,
+    extract::{Query, State},
+};
+use chrono::{DateTime, Utc};
+use pgmap::FromRow;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+#[derive(utoipa::OpenApi)]
+#[openapi(
+    paths(
+        list_audit_events,
+    ),
+    components(
+        schemas(
+            AuditPage,
+            AuditEvents,
+        ),
+    ),
+    tags(
+        (name = "Audit", description = "History of configuration changes"),
+    ),
+)]
+#[allow(dead_code)]
+pub struct AuditApi;
+
+const DEFAULT_PER_PAGE: i64 = 50;
+const MAX_PER_PAGE: i64 = 200;
+
+/// Filters shared by the count and page queries, in the order of `AuditQuery`,
+/// then the user whose projects events are limited to and the org of the caller
+const AUDIT_FILTER: &str = "WHERE ($1::uuid IS NULL OR project_id = $1)
+   AND ($2::uuid IS NULL OR environment_id = $2)
+   AND ($3::text IS NULL OR entity_type = $3)
+   AND ($4::uuid IS NULL OR entity_id = $4)
+   AND ($5::text IS NULL OR action = $5)
+   AND ($6::uuid IS NULL OR actor_user_id = $6)
+   AND ($7::uuid IS NULL OR actor_api_key_id = $7)
+   AND ($8::timestamptz IS NULL OR created_at >= $8)
+   AND ($9::timestamptz IS NULL OR created_at < $9)
+   AND ($10::uuid IS NULL OR project_id IN (SELECT project_id FROM project_owners WHERE user_id = $10))
+   AND org_id = $11";
+
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditQuery {
+    pub project_id: Option<Uuid>,
+    pub environment_id: Option<Uuid>,
+    /// e.g. `flag`, `flag_state`, `override`, `api_key`
+    pub entity_type: Option<String>,
+    /// Flag states are recorded under the id of their flag
+    pub entity_id: Option<Uuid>,
+    /// e.g. `flag_state.updated`
+    pub action: Option<String>,
+    pub actor_user_id: Option<Uuid>,
+    pub actor_api_key_id: Option<Uuid>,
+    /// Only events at or after this time
+    pub since: Option<DateTime<Utc>>,
+    /// Only events before this time
+    pub until: Option<DateTime<Utc>>,
+    /// Page number, starting at 1
+    pub page: Option<i64>,
+    /// Events per page, at most 200
+    pub per_page: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct AuditPage {
+    /// Matching events, newest first
+    pub events: Vec<AuditEvents>,
+    pub page: i64,
+    pub per_page: i64,
+    /// Number of matching events across all pages
+    pub total: i64,
+}
+
+/// List audit events, newest first.
+/// Admins see every event of their org, other users only the events of projects they are a member of.
+#[utoipa::path(
+    get,
+    path = "/v1/audit",
+    params(AuditQuery),
+    responses(
+        (status = 200, description = "Audit events", body = DataResponse<AuditPage>),
+        (status = 400, description = "Invalid pagination", body = DataResponse<serde_json::Value>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "Forbidden", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Audit"
+)]
+async fn list_audit_events(
+    State(state): State<AppState>,
+    Authorized(auth_user, _): Authorized<require::AuditRead>,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<DataResponse<AuditPage>>, AppError> {
+    let page = query.page.unwrap_or(1);
+    let per_page = query.per_page.unwrap_or(DEFAULT_PER_PAGE);
+    if page < 1 || !(1..=MAX_PER_PAGE).contains(&per_page) {
+        return Err(AppError::BadRequest(format!(
+            "page must be at least 1 and per_page between 1 and {}",
+            MAX_PER_PAGE
+        )));
+    }
+
+    let client = state.db_pool.get().await?;
+
+    let member_id = match auth_user.role {
+        UserRole::Admin => None,
+        _ => Some(auth_user.id),
+    };
+
+    let filters: [&(dyn tokio_postgres::types::ToSql + Sync); 11] = [
+        &query.project_id,
+        &query.environment_id,
+        &query.entity_type,
+        &query.entity_id,
+        &query.action,
+        &query.actor_user_id,
+        &query.actor_api_key_id,
+        &query.since,
+        &query.until,
+        &member_id,
+        &auth_user.org_id,
+    ];
+
+    let total: i64 = client
+        .query_one(
+            &format!("SELECT count(*) FROM audit_events {}", AUDIT_FILTER),
+            &filters,
+        )
+        .await?
+        .get(0);
+
+    let offset = (page - 1) * per_page;
+    let mut params = filters.to_vec();
+    params.push(&per_page);
+    params.push(&offset);
+
+    let rows = client
+        .query(
+            &format!(
+                "SELECT * FROM audit_events {}
+                 ORDER BY created_at DESC, id DESC
+                 LIMIT $12 OFFSET $13",
+                AUDIT_FILTER
+            ),
+            &params,
+        )
+        .await?;
+
+    let events = AuditEvents::from_rows(&rows)
+        .map_err(|_| AppError::InternalError("Failed to parse audit data".to_string()))?;
+
+    Ok(Json(
+        DataResponse::new()
+            .data(AuditPage {
+                events,
+                page,
+                per_page,
+                total,
+            })
+            .build(),
+    ))
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/v1/audit", axum::routing::get(list_audit_events))
+}
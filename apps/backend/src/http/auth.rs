@@ -1,17 +1,35 @@
+use crate::http::orgs;
+use crate::http::users::{UserSummary, parse_user};
+use crate::models::db::MagicLinks;
+use crate::models::enums::UserRole;
+use crate::pkg::audit::{self, Actor, AuditEvent};
+use crate::pkg::auth::{AuthUser, generate_token, hash_token};
+use crate::pkg::emails::Template;
 use crate::pkg::error::AppError;
-use crate::pkg::jwt::Claims;
+use crate::pkg::jwt::{API_AUDIENCE, Claims, MFA_CHALLENGE_EXPIRY, TokenType};
+use crate::pkg::lockout;
+use crate::pkg::mfa;
+use crate::pkg::rate_limit;
+use crate::pkg::registration;
 use crate::pkg::response::DataResponse;
+use crate::pkg::sessions::{self, SessionTokens};
 use crate::pkg::state::AppState;
-use crate::{models::db::MagicLinks, pkg::auth::AuthUser};
 use argon2::{
     PasswordVerifier, password_hash::PasswordHash, password_hash::PasswordHasher,
     password_hash::SaltString,
 };
-use axum::{Json, Router, extract::State};
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
 use axum_extra::extract::{
     WithRejection,
     cookie::{Cookie, SameSite},
 };
+use chrono::{DateTime, Utc};
+use jsonwebtoken::jwk::JwkSet;
 use pgmap::FromRow;
 use rand::TryRngCore;
 use serde::{Deserialize, Serialize};
@@ -25,10 +43,17 @@ use uuid::Uuid;
         register,
         request_magic_link,
         verify_magic_link,
+        forgot_password,
+        reset_password,
+        verify_email,
+        resend_verification,
         refresh_token,
         logout,
         get_current_user,
+        list_sessions,
+        revoke_session,
         get_keys,
+        get_jwks,
         decode_token,
     ),
     components(
@@ -37,9 +62,16 @@ use uuid::Uuid;
             RegisterRequest,
             MagicLinkRequest,
             MagicLinkVerifyRequest,
+            ForgotPasswordRequest,
+            ResetPasswordRequest,
+            VerifyEmailRequest,
+            ResendVerificationRequest,
+            VerificationPendingResponse,
             AuthResponse,
+            MfaChallengeResponse,
+            SessionSummary,
             PublicKeyResponse,
-            crate::models::db::Users,
+            UserSummary,
             Claims,
             DecodeRequest,
         ),
@@ -68,6 +100,12 @@ pub struct DecodeRequest {
 pub struct RegisterRequest {
     pub email: String,
     pub password: String,
+    /// Token of an invitation to join an existing organization
+    pub invitation_token: Option<String>,
+    /// Slug of an existing organization to join without an invitation, if its registration mode allows
+    pub org: Option<String>,
+    /// Name of the organization to create when not joining one, defaults to the email
+    pub organization: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, ToSchema)]
@@ -80,18 +118,213 @@ pub struct MagicLinkVerifyRequest {
     pub token: String,
 }
 
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct VerifyEmailRequest {
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ResendVerificationRequest {
+    pub email: String,
+}
+
+/// Answer to signing up when the email has to be verified before signing in
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct VerificationPendingResponse {
+    /// Where the verification link was sent
+    pub email: String,
+}
+
 #[derive(Serialize, Deserialize, ToSchema)]
 pub struct AuthResponse {
     pub access_token: String,
     pub refresh_token: Option<String>,
 }
 
+/// Returned instead of a session to users with MFA, exchange it at `/v1/auth/mfa/verify`
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct MfaChallengeResponse {
+    pub challenge_token: String,
+    /// Seconds until the challenge token expires
+    pub expires_in: i64,
+}
+
+/// A signed in device or browser
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct SessionSummary {
+    pub id: Uuid,
+    pub user_agent: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub expires_at: DateTime<Utc>,
+    /// Whether this is the session making the request
+    pub current: bool,
+}
+
 #[derive(Serialize, Deserialize, ToSchema)]
 pub struct PublicKeyResponse {
     pub public_key: String,
 }
 
+pub(crate) type SessionResponse = (
+    [(&'static str, String); 1],
+    Json<DataResponse<AuthResponse>>,
+);
+
+fn refresh_cookie(token: &str) -> String {
+    Cookie::build(("refresh_token", token.to_string()))
+        .path("/")
+        .http_only(true)
+        .secure(true) // Only send over HTTPS in production
+        .same_site(SameSite::Strict)
+        .build()
+        .to_string()
+}
+
+/// Hand out a session's tokens, the refresh token both in the body and as a cookie
+pub(crate) fn session_response(tokens: SessionTokens) -> SessionResponse {
+    (
+        [("Set-Cookie", refresh_cookie(&tokens.refresh_token))],
+        Json(
+            DataResponse::new()
+                .data(AuthResponse {
+                    access_token: tokens.access_token,
+                    refresh_token: Some(tokens.refresh_token),
+                })
+                .build(),
+        ),
+    )
+}
+
+pub(crate) fn user_agent(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(axum::http::header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+}
+
+/// Start a session for a user who proved their first factor,
+/// or challenge them for their second one when they enrolled in MFA
+pub(crate) async fn sign_in(
+    client: &impl deadpool_postgres::GenericClient,
+    state: &AppState,
+    user_id: Uuid,
+    refresh_type: TokenType,
+    headers: &HeaderMap,
+) -> Result<Response, AppError> {
+    let row = client
+        .query_opt("SELECT * FROM users WHERE id = $1", &[&user_id])
+        .await?
+        .ok_or(AppError::Unauthorized("User not found".to_string()))?;
+    let user = parse_user(&row)?;
+
+    // Whichever way the user proved who they are, a lockout or a required reset still applies
+    lockout::check(&user)?;
+    if user.password_reset_required {
+        return Err(AppError::Forbidden(
+            "Your password has to be reset, use the link emailed to you".to_string(),
+        ));
+    }
+
+    if mfa::is_enabled(client, user_id).await? {
+        let challenge_token = state.jwt.encode(
+            &state
+                .jwt
+                .claims(user_id, TokenType::Mfa, API_AUDIENCE)
+                .refresh_type(refresh_type),
+        )?;
+
+        return Ok((
+            StatusCode::ACCEPTED,
+            Json(
+                DataResponse::new()
+                    .data(MfaChallengeResponse {
+                        challenge_token,
+                        expires_in: MFA_CHALLENGE_EXPIRY,
+                    })
+                    .build(),
+            ),
+        )
+            .into_response());
+    }
+
+    let tokens = sessions::create(
+        client,
+        &state.jwt,
+        user_id,
+        refresh_type,
+        user_agent(headers),
+    )
+    .await?;
+
+    Ok(session_response(tokens).into_response())
+}
+
+fn session_summary(session: crate::models::db::Sessions, current: Option<Uuid>) -> SessionSummary {
+    SessionSummary {
+        current: current == Some(session.id),
+        id: session.id,
+        user_agent: session.user_agent,
+        created_at: session.created_at,
+        last_used_at: session.last_used_at,
+        expires_at: session.expires_at,
+    }
+}
+
 // Auth handlers
+/// How long a password reset link stays valid
+const PASSWORD_RESET_TTL_MINUTES: i64 = 60;
+
+/// Create a password reset token for a user, replacing any earlier one so only the latest link works.
+/// Send it with `Template::PasswordReset` once the token is committed.
+pub(crate) async fn create_password_reset(
+    client: &impl deadpool_postgres::GenericClient,
+    user_id: Uuid,
+) -> Result<String, AppError> {
+    let token = generate_token()?;
+    let expires_at = chrono::Utc::now() + chrono::Duration::minutes(PASSWORD_RESET_TTL_MINUTES);
+
+    client
+        .execute(
+            "DELETE FROM password_resets WHERE user_id = $1 AND used_at IS NULL",
+            &[&user_id],
+        )
+        .await?;
+    client
+        .execute(
+            "INSERT INTO password_resets (user_id, token_hash, expires_at) VALUES ($1, $2, $3)",
+            &[&user_id, &hash_token(&token), &expires_at],
+        )
+        .await?;
+
+    Ok(token)
+}
+
+pub(crate) fn hash_password(state: &AppState, password: &str) -> Result<String, AppError> {
+    let salt = rand::rngs::OsRng
+        .try_next_u64()
+        .ok()
+        .and_then(|num| SaltString::encode_b64(&num.to_le_bytes()).ok())
+        .ok_or(AppError::InternalError(
+            "Failed to generate salt".to_string(),
+        ))?;
+
+    Ok(state
+        .argon2
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string())
+}
+
 /// User login with email and password
 #[utoipa::path(
     post,
@@ -99,21 +332,19 @@ pub struct PublicKeyResponse {
     request_body = LoginRequest,
     responses(
         (status = 200, description = "Login successful", body = DataResponse<AuthResponse>),
+        (status = 202, description = "Password verified, a second factor is required", body = DataResponse<MfaChallengeResponse>),
         (status = 401, description = "Invalid credentials", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "The email address is not verified yet, a password reset is required or the account is deactivated", body = DataResponse<serde_json::Value>),
+        (status = 429, description = "Too many attempts or the account is temporarily locked", body = DataResponse<serde_json::Value>),
     ),
     tag = "Authentication"
 )]
 #[axum_macros::debug_handler]
 async fn login(
     State(state): State<AppState>,
+    headers: HeaderMap,
     WithRejection(Json(payload), _): WithRejection<Json<LoginRequest>, AppError>,
-) -> Result<
-    (
-        [(&'static str, String); 1],
-        Json<DataResponse<AuthResponse>>,
-    ),
-    AppError,
-> {
+) -> Result<Response, AppError> {
     let client = state.db_pool.get().await?;
 
     let row = client
@@ -123,73 +354,73 @@ async fn login(
             "Invalid email or password".to_string(),
         ))?;
 
-    let user = crate::models::db::Users::from_row(&row)
-        .map_err(|_| AppError::InternalError("Failed to parse user data".to_string()))?;
+    let user = parse_user(&row)?;
+
+    lockout::check(&user)?;
 
     // Verify password
     let parsed_hash = PasswordHash::new(&user.password_hash)?;
 
-    state
+    if state
         .argon2
         .verify_password(payload.password.as_bytes(), &parsed_hash)
-        .map_err(|_| AppError::Unauthorized("Invalid email or password".to_string()))?;
-
-    // Generate tokens using JWT service
-    let access_token = state.jwt.generate_token(
-        user.id,
-        state.jwt.get_length(&crate::pkg::jwt::TokenType::Access),
-    )?;
-    let refresh_token = state.jwt.generate_token(
-        user.id,
-        match payload.remember_me {
-            true => state.jwt.get_length(&crate::pkg::jwt::TokenType::Long),
-            false => state.jwt.get_length(&crate::pkg::jwt::TokenType::Refresh),
-        },
-    )?;
-
-    // Create refresh token cookie
-    let cookie = Cookie::build(("refresh_token", refresh_token.clone()))
-        .path("/")
-        .http_only(true)
-        .secure(true) // Only send over HTTPS in production
-        .same_site(SameSite::Strict)
-        .build();
-    Ok((
-        [("Set-Cookie", cookie.to_string())],
-        Json(
-            DataResponse::new()
-                .data(AuthResponse {
-                    access_token,
-                    refresh_token: Some(refresh_token),
-                })
-                .build(),
-        ),
-    ))
+        .is_err()
+    {
+        lockout::record_failure(&client, &state.config, user.id).await?;
+        return Err(AppError::Unauthorized(
+            "Invalid email or password".to_string(),
+        ));
+    }
+
+    // With MFA the sign in completes once the code is verified, failures keep counting until then
+    if !mfa::is_enabled(&client, user.id).await? {
+        lockout::reset(&client, &user).await?;
+    }
+
+    if registration::verification_pending(&client, &state.config, &user).await? {
+        return Err(AppError::Forbidden(
+            "Verify your email address before signing in".to_string(),
+        ));
+    }
+
+    let refresh_type = match payload.remember_me {
+        true => TokenType::Long,
+        false => TokenType::Refresh,
+    };
+
+    sign_in(&client, &state, user.id, refresh_type, &headers).await
 }
 
-/// User registration with email and password
+/// User registration with email and password.
+/// With an invitation the user joins its organization with the invited role, naming an
+/// organization that accepts sign ups joins it as a viewer, otherwise a new organization
+/// is created with the user as its admin. Unless the email was proven by an invitation,
+/// the user may have to verify it before signing in.
 #[utoipa::path(
     post,
     path = "/v1/auth/register",
     request_body = RegisterRequest,
     responses(
         (status = 200, description = "Registration successful", body = DataResponse<AuthResponse>),
-        (status = 409, description = "User already exists", body = DataResponse<serde_json::Value>),
+        (status = 202, description = "Registered, the email has to be verified before signing in", body = DataResponse<VerificationPendingResponse>),
+        (status = 400, description = "Invalid or expired invitation", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "The organization does not accept sign ups from this email, or sign ups are closed", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "Organization not found", body = DataResponse<serde_json::Value>),
+        (status = 409, description = "User or organization already exists", body = DataResponse<serde_json::Value>),
+        (status = 422, description = "Invalid email or a password not meeting the policy", body = DataResponse<serde_json::Value>),
     ),
     tag = "Authentication"
 )]
 #[axum_macros::debug_handler]
 async fn register(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(payload): Json<RegisterRequest>,
-) -> Result<
-    (
-        [(&'static str, String); 1],
-        Json<DataResponse<AuthResponse>>,
-    ),
-    AppError,
-> {
-    let client = state.db_pool.get().await?;
+) -> Result<Response, AppError> {
+    registration::validate_email(&payload.email)?;
+    registration::check_password(&state.config, &payload.password, &payload.email)?;
+
+    let mut client = state.db_pool.get().await?;
 
     // Check if user already exists
     let existing = client
@@ -200,62 +431,144 @@ async fn register(
         return Err(AppError::Conflict("User already exists".to_string()));
     }
 
-    // Hash password
-    let salt = rand::rngs::OsRng
-        .try_next_u64()
-        .ok()
-        .and_then(|num| SaltString::encode_b64(&num.to_le_bytes()).ok())
-        .ok_or(AppError::InternalError(
-            "Failed to generate salt".to_string(),
-        ))?;
+    let hashed_password = hash_password(&state, &payload.password)?;
 
-    let hashed_password = state
-        .argon2
-        .hash_password(payload.password.as_bytes(), &salt)?
-        .to_string();
+    let user_id = Uuid::new_v4();
+    let transaction = client.transaction().await?;
+
+    // Join the inviting org, an org accepting sign ups, or start a new one
+    let require_verification = state.config.require_email_verification;
+    let (org_id, role, invitation, org, verify) = match (&payload.invitation_token, &payload.org) {
+        // The invitation link already proved the email
+        (Some(token), _) => {
+            let invitation = orgs::accept_invitation(&transaction, token, &payload.email).await?;
+            (
+                invitation.org_id,
+                invitation.role,
+                Some(invitation),
+                None,
+                false,
+            )
+        }
+        (None, Some(slug)) => {
+            let row = transaction
+                .query_opt(
+                    "SELECT id, registration_mode, allowed_email_domains FROM orgs WHERE slug = $1",
+                    &[slug],
+                )
+                .await?
+                .ok_or(AppError::NotFound("Organization not found".to_string()))?;
+            let by_domain = registration::may_join(&row.get(1), &row.get(2), &payload.email)
+                .ok_or(AppError::Forbidden(
+                    "The organization only accepts invited members".to_string(),
+                ))?;
+            (
+                row.get(0),
+                UserRole::Viewer,
+                None,
+                None,
+                require_verification || by_domain,
+            )
+        }
+        (None, None) => {
+            if !registration::may_create_org(&transaction, &state.config).await? {
+                return Err(AppError::Forbidden(
+                    "Sign ups need an invitation or an organization to join".to_string(),
+                ));
+            }
+            let name = payload.organization.as_deref().unwrap_or(&payload.email);
+            let org = orgs::create_org(&transaction, name).await?;
+            (
+                org.id,
+                UserRole::Admin,
+                None,
+                Some(org),
+                require_verification,
+            )
+        }
+    };
 
     // Insert new user
-    let user_id = Uuid::new_v4();
-    client
+    transaction
         .execute(
-            "INSERT INTO users (id, email, password_hash, role) VALUES ($1, $2, $3, $4)",
+            "INSERT INTO users (id, email, password_hash, role, org_id, email_verified_at)
+             VALUES ($1, $2, $3, $4, $5, CASE WHEN $6 THEN NULL ELSE current_timestamp END)",
             &[
                 &user_id,
                 &payload.email,
                 &hashed_password,
-                &crate::models::enums::UserRole::Viewer,
+                &role,
+                &org_id,
+                &verify,
             ],
         )
         .await?;
 
-    // Generate tokens using JWT service
-    let access_token = state.jwt.generate_token(
-        user_id,
-        state.jwt.get_length(&crate::pkg::jwt::TokenType::Access),
-    )?;
-    let refresh_token = state.jwt.generate_token(
-        user_id,
-        state.jwt.get_length(&crate::pkg::jwt::TokenType::Refresh),
-    )?;
-    // Create refresh token cookie
-    let cookie = Cookie::build(("refresh_token", refresh_token.clone()))
-        .path("/")
-        .http_only(true)
-        .secure(true) // Only send over HTTPS in production
-        .same_site(SameSite::Strict)
-        .build();
+    if let Some(org) = &org {
+        audit::record(
+            &transaction,
+            AuditEvent::new(Actor::User(user_id), "org.created", "org", org.id).after(org),
+        )
+        .await?;
+    }
 
-    Ok((
-        [("Set-Cookie", cookie.to_string())],
-        Json(
-            DataResponse::new()
-                .data(AuthResponse {
-                    access_token,
-                    refresh_token: Some(refresh_token),
-                })
-                .build(),
+    if let Some(invitation) = &invitation {
+        audit::record(
+            &transaction,
+            AuditEvent::new(
+                Actor::User(user_id),
+                "invitation.accepted",
+                "invitation",
+                invitation.id,
+            )
+            .after(invitation),
+        )
+        .await?;
+    }
+
+    // Never record the password hash
+    audit::record(
+        &transaction,
+        AuditEvent::new(Actor::User(user_id), "user.registered", "user", user_id).after(
+            &serde_json::json!({ "id": user_id, "email": payload.email, "role": role, "org_id": org_id }),
         ),
-    ))
+    )
+    .await?;
+
+    if verify {
+        let token = registration::create_verification(&transaction, user_id).await?;
+        transaction.commit().await?;
+
+        state.mailer.send(
+            &payload.email,
+            Template::EmailVerification { token: &token },
+        );
+
+        return Ok((
+            StatusCode::ACCEPTED,
+            Json(
+                DataResponse::new()
+                    .data(VerificationPendingResponse {
+                        email: payload.email,
+                    })
+                    .build(),
+            ),
+        )
+            .into_response());
+    }
+
+    let tokens = sessions::create(
+        &transaction,
+        &state.jwt,
+        user_id,
+        TokenType::Refresh,
+        user_agent(&headers),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(session_response(tokens).into_response())
 }
 
 /// Request a magic link for passwordless authentication
@@ -300,7 +613,16 @@ async fn request_magic_link(
         )
         .await?;
 
-    // TODO: Send email with magic link
+    // Only known users get an email, the response is the same either way
+    if user_id.is_some() {
+        state.mailer.send(
+            &payload.email,
+            Template::MagicLink {
+                token: &token.to_string(),
+            },
+        );
+    }
+
     Ok(Json(serde_json::json!({
         "message": "Magic link sent to email"
     })))
@@ -313,31 +635,26 @@ async fn request_magic_link(
     request_body = MagicLinkVerifyRequest,
     responses(
         (status = 200, description = "Magic link verified", body = DataResponse<AuthResponse>),
+        (status = 202, description = "Magic link verified, a second factor is required", body = DataResponse<MfaChallengeResponse>),
         (status = 401, description = "Invalid or expired magic link", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "A password reset is required or the account is deactivated", body = DataResponse<serde_json::Value>),
+        (status = 429, description = "The account is temporarily locked", body = DataResponse<serde_json::Value>),
     ),
     tag = "Authentication"
 )]
 async fn verify_magic_link(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(payload): Json<MagicLinkVerifyRequest>,
-) -> Result<
-    (
-        [(&'static str, String); 1],
-        Json<DataResponse<AuthResponse>>,
-    ),
-    AppError,
-> {
+) -> Result<Response, AppError> {
     let client = state.db_pool.get().await?;
 
     // Find and validate magic link token
     let magic_link_row = client
         .query_opt(
-            "SELECT user_id, expires_at FROM magic_links WHERE token = $1 ",
-            &[
-                &uuid::Uuid::parse_str(&payload.token)
-                    .map_err(|_| AppError::BadRequest("Invalid token format".to_string()))?,
-                &chrono::Utc::now(),
-            ],
+            "SELECT * FROM magic_links WHERE token = $1",
+            &[&uuid::Uuid::parse_str(&payload.token)
+                .map_err(|_| AppError::BadRequest("Invalid token format".to_string()))?],
         )
         .await?
         .ok_or(AppError::Unauthorized(
@@ -361,51 +678,224 @@ async fn verify_magic_link(
         .execute("DELETE FROM magic_links WHERE id = $1", &[&magic_link.id])
         .await?;
 
-    // Generate tokens
-    let access_token = state.jwt.generate_token(
-        user_id,
-        state.jwt.get_length(&crate::pkg::jwt::TokenType::Access),
-    )?;
-    let refresh_token = state.jwt.generate_token(
-        user_id,
-        state.jwt.get_length(&crate::pkg::jwt::TokenType::Refresh),
-    )?;
+    // Receiving the link proves the email is theirs
+    registration::mark_verified(&client, user_id).await?;
 
-    // Create refresh token cookie
-    let cookie = Cookie::build(("refresh_token", refresh_token.clone()))
-        .path("/")
-        .http_only(true)
-        .secure(true)
-        .same_site(SameSite::Strict)
-        .build();
+    sign_in(&client, &state, user_id, TokenType::Refresh, &headers).await
+}
 
-    Ok((
-        [("Set-Cookie", cookie.to_string())],
-        Json(
-            DataResponse::new()
-                .data(AuthResponse {
-                    access_token,
-                    refresh_token: Some(refresh_token),
-                })
-                .build(),
-        ),
-    ))
+/// Email a password reset link. The response is the same whether or not the email belongs to an account.
+#[utoipa::path(
+    post,
+    path = "/v1/auth/password/forgot",
+    request_body = ForgotPasswordRequest,
+    responses(
+        (status = 200, description = "Reset link sent if the account exists", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Authentication"
+)]
+async fn forgot_password(
+    State(state): State<AppState>,
+    WithRejection(Json(payload), _): WithRejection<Json<ForgotPasswordRequest>, AppError>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let mut client = state.db_pool.get().await?;
+
+    let user = client
+        .query_opt("SELECT id FROM users WHERE email = $1", &[&payload.email])
+        .await?;
+
+    if let Some(user) = user {
+        let transaction = client.transaction().await?;
+        let token = create_password_reset(&transaction, user.get(0)).await?;
+        transaction.commit().await?;
+
+        state
+            .mailer
+            .send(&payload.email, Template::PasswordReset { token: &token });
+    }
+
+    Ok(Json(serde_json::json!({
+        "message": "If the email belongs to an account, a reset link has been sent"
+    })))
+}
+
+/// Set a new password with a reset token. The token works once, and every session of the user is revoked.
+#[utoipa::path(
+    post,
+    path = "/v1/auth/password/reset",
+    request_body = ResetPasswordRequest,
+    responses(
+        (status = 200, description = "Password reset", body = DataResponse<serde_json::Value>),
+        (status = 400, description = "Invalid, expired or already used reset token", body = DataResponse<serde_json::Value>),
+        (status = 422, description = "The password does not meet the policy", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Authentication"
+)]
+async fn reset_password(
+    State(state): State<AppState>,
+    WithRejection(Json(payload), _): WithRejection<Json<ResetPasswordRequest>, AppError>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let mut client = state.db_pool.get().await?;
+    let transaction = client.transaction().await?;
+
+    // A rejected password leaves the token unused, the transaction is rolled back
+    let user = transaction
+        .query_opt(
+            "UPDATE password_resets SET used_at = current_timestamp
+             FROM users
+             WHERE token_hash = $1 AND used_at IS NULL AND expires_at > current_timestamp
+               AND users.id = password_resets.user_id
+             RETURNING users.id, users.email",
+            &[&hash_token(&payload.token)],
+        )
+        .await?
+        .ok_or(AppError::BadRequest(
+            "Invalid or expired reset token".to_string(),
+        ))?;
+    let (user_id, email): (Uuid, String) = (user.get(0), user.get(1));
+
+    registration::check_password(&state.config, &payload.password, &email)?;
+    let hashed_password = hash_password(&state, &payload.password)?;
+
+    transaction
+        .execute(
+            "UPDATE users SET password_hash = $2, failed_login_attempts = 0, locked_until = NULL,
+                password_reset_required = false, updated_at = current_timestamp
+             WHERE id = $1",
+            &[&user_id, &hashed_password],
+        )
+        .await?;
+
+    // Whoever knew the old password loses access, including through pending links
+    transaction
+        .execute(
+            "DELETE FROM password_resets WHERE user_id = $1 AND used_at IS NULL",
+            &[&user_id],
+        )
+        .await?;
+    transaction
+        .execute("DELETE FROM magic_links WHERE user_id = $1", &[&user_id])
+        .await?;
+    let revoked =
+        sessions::revoke_all(&transaction, user_id, sessions::REVOKED_PASSWORD_RESET).await?;
+
+    // The reset link reached them, so the email is theirs
+    registration::mark_verified(&transaction, user_id).await?;
+
+    audit::record(
+        &transaction,
+        AuditEvent::new(Actor::User(user_id), "user.password_reset", "user", user_id)
+            .after(&serde_json::json!({ "revoked_sessions": revoked })),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(serde_json::json!({
+        "message": "Password has been reset"
+    })))
+}
+
+/// Verify the email of an account with the token from the verification link, and sign in.
+/// The token works once.
+#[utoipa::path(
+    post,
+    path = "/v1/auth/email/verify",
+    request_body = VerifyEmailRequest,
+    responses(
+        (status = 200, description = "Email verified and signed in", body = DataResponse<AuthResponse>),
+        (status = 202, description = "Email verified, a second factor is required", body = DataResponse<MfaChallengeResponse>),
+        (status = 400, description = "Invalid, expired or already used verification token", body = DataResponse<serde_json::Value>),
+        (status = 429, description = "The account is temporarily locked", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Authentication"
+)]
+async fn verify_email(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    WithRejection(Json(payload), _): WithRejection<Json<VerifyEmailRequest>, AppError>,
+) -> Result<Response, AppError> {
+    let mut client = state.db_pool.get().await?;
+    let transaction = client.transaction().await?;
+
+    let user_id: Uuid = transaction
+        .query_opt(
+            "UPDATE email_verifications SET used_at = current_timestamp
+             WHERE token_hash = $1 AND used_at IS NULL AND expires_at > current_timestamp
+             RETURNING user_id",
+            &[&hash_token(&payload.token)],
+        )
+        .await?
+        .ok_or(AppError::BadRequest(
+            "Invalid or expired verification token".to_string(),
+        ))?
+        .get(0);
+
+    registration::mark_verified(&transaction, user_id).await?;
+
+    audit::record(
+        &transaction,
+        AuditEvent::new(Actor::User(user_id), "user.email_verified", "user", user_id),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    sign_in(&client, &state, user_id, TokenType::Refresh, &headers).await
+}
+
+/// Email a new verification link. The response is the same whether or not the email
+/// belongs to an account waiting for verification.
+#[utoipa::path(
+    post,
+    path = "/v1/auth/email/resend",
+    request_body = ResendVerificationRequest,
+    responses(
+        (status = 200, description = "Verification link sent if the account needs one", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Authentication"
+)]
+async fn resend_verification(
+    State(state): State<AppState>,
+    WithRejection(Json(payload), _): WithRejection<Json<ResendVerificationRequest>, AppError>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let client = state.db_pool.get().await?;
+
+    let user = client
+        .query_opt(
+            "SELECT id FROM users WHERE email = $1 AND email_verified_at IS NULL",
+            &[&payload.email],
+        )
+        .await?;
+
+    if let Some(user) = user {
+        let token = registration::create_verification(&client, user.get(0)).await?;
+        state.mailer.send(
+            &payload.email,
+            Template::EmailVerification { token: &token },
+        );
+    }
+
+    Ok(Json(serde_json::json!({
+        "message": "If the email belongs to an account waiting for verification, a link has been sent"
+    })))
 }
 
-/// Refresh access token using refresh token cookie
+/// Exchange the refresh token cookie for a new access and refresh token.
+/// Every refresh token can be used once, reusing one revokes its whole session.
 #[utoipa::path(
     post,
     path = "/v1/auth/refresh",
     responses(
         (status = 200, description = "Token refreshed", body = DataResponse<AuthResponse>),
-        (status = 401, description = "Refresh token not found or invalid", body = DataResponse<serde_json::Value>),
+        (status = 401, description = "Refresh token not found, invalid, already used or revoked", body = DataResponse<serde_json::Value>),
     ),
     tag = "Authentication"
 )]
 async fn refresh_token(
     State(state): State<AppState>,
     jar: axum_extra::extract::CookieJar,
-) -> Result<Json<DataResponse<AuthResponse>>, AppError> {
+) -> Result<SessionResponse, AppError> {
     // Extract refresh token from cookies
     let refresh_token_str = jar
         .get("refresh_token")
@@ -414,30 +904,13 @@ async fn refresh_token(
             "Refresh token not found".to_string(),
         ))?;
 
-    // Validate the refresh token
-    let claims = state.jwt.validate_token(&refresh_token_str)?;
-
-    // Generate new access token
-    let user_id = uuid::Uuid::parse_str(&claims.sub)
-        .map_err(|_| AppError::InternalError("Invalid user ID in token".to_string()))?;
-
-    let access_token = state.jwt.generate_token(
-        user_id,
-        state.jwt.get_length(&crate::pkg::jwt::TokenType::Access),
-    )?;
+    let client = state.db_pool.get().await?;
+    let tokens = sessions::rotate(&client, &state.jwt, &refresh_token_str).await?;
 
-    // Create refresh token cookie
-    Ok(Json(
-        DataResponse::new()
-            .data(AuthResponse {
-                access_token,
-                refresh_token: None,
-            })
-            .build(),
-    ))
+    Ok(session_response(tokens))
 }
 
-/// Logout user by clearing refresh token
+/// Logout user by revoking the session of the refresh token cookie and clearing it
 #[utoipa::path(
     post,
     path = "/v1/auth/logout",
@@ -446,13 +919,21 @@ async fn refresh_token(
     ),
     tag = "Authentication"
 )]
-async fn logout() -> Result<
+async fn logout(
+    State(state): State<AppState>,
+    jar: axum_extra::extract::CookieJar,
+) -> Result<
     (
         [(&'static str, String); 1],
         Json<DataResponse<serde_json::Value>>,
     ),
     AppError,
 > {
+    if let Some(cookie) = jar.get("refresh_token") {
+        let client = state.db_pool.get().await?;
+        sessions::revoke_by_token(&client, &state.jwt, cookie.value()).await?;
+    }
+
     // Create an empty refresh token cookie to clear it
     let cookie = Cookie::build(("refresh_token", ""))
         .path("/")
@@ -478,7 +959,7 @@ async fn logout() -> Result<
     get,
     path = "/v1/auth/me",
     responses(
-        (status = 200, description = "Current user data", body = DataResponse<crate::models::db::Users>),
+        (status = 200, description = "Current user data", body = DataResponse<UserSummary>),
         (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
     ),
     tag = "Authentication"
@@ -486,7 +967,7 @@ async fn logout() -> Result<
 async fn get_current_user(
     State(state): State<AppState>,
     auth_user: AuthUser,
-) -> Result<Json<DataResponse<crate::models::db::Users>>, AppError> {
+) -> Result<Json<DataResponse<UserSummary>>, AppError> {
     let client = state.db_pool.get().await?;
 
     let row = client
@@ -494,13 +975,70 @@ async fn get_current_user(
         .await?
         .ok_or(AppError::NotFound("User not found".to_string()))?;
 
-    let user = crate::models::db::Users::from_row(&row)
-        .map_err(|_| AppError::InternalError("Failed to parse user data".to_string()))?;
+    Ok(Json(
+        DataResponse::new()
+            .data(UserSummary::from(parse_user(&row)?))
+            .build(),
+    ))
+}
+
+/// List the current user's active sessions
+#[utoipa::path(
+    get,
+    path = "/v1/auth/sessions",
+    responses(
+        (status = 200, description = "Active sessions", body = DataResponse<Vec<SessionSummary>>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Authentication"
+)]
+async fn list_sessions(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<DataResponse<Vec<SessionSummary>>>, AppError> {
+    let client = state.db_pool.get().await?;
+
+    let active = sessions::list_active(&client, auth_user.id)
+        .await?
+        .into_iter()
+        .map(|session| session_summary(session, auth_user.session_id))
+        .collect();
 
-    Ok(Json(DataResponse::new().data(user).build()))
+    Ok(Json(DataResponse::new().data(active).build()))
 }
 
-/// Get public JWT key for token verification
+/// Revoke one of the current user's sessions, signing that device out
+#[utoipa::path(
+    delete,
+    path = "/v1/auth/sessions/{session_id}",
+    params(
+        ("session_id" = Uuid, Path, description = "Session ID"),
+    ),
+    responses(
+        (status = 200, description = "Session revoked", body = DataResponse<SessionSummary>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "Session not found", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Authentication"
+)]
+async fn revoke_session(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(session_id): Path<Uuid>,
+) -> Result<Json<DataResponse<SessionSummary>>, AppError> {
+    let client = state.db_pool.get().await?;
+
+    let session = sessions::revoke(&client, auth_user.id, session_id).await?;
+
+    Ok(Json(
+        DataResponse::new()
+            .data(session_summary(session, auth_user.session_id))
+            .build(),
+    ))
+}
+
+/// Get the public key new tokens are signed with, in PEM format.
+/// Keys rotate, verifiers should prefer `/.well-known/jwks.json`.
 #[utoipa::path(
     get,
     path = "/v1/auth/keys",
@@ -525,6 +1063,19 @@ async fn get_keys(
     ))
 }
 
+/// JSON Web Key Set of every key our tokens may be signed with, matched by the token's `kid`
+#[utoipa::path(
+    get,
+    path = "/.well-known/jwks.json",
+    responses(
+        (status = 200, description = "JSON Web Key Set", body = serde_json::Value),
+    ),
+    tag = "Authentication"
+)]
+async fn get_jwks(State(state): State<AppState>) -> Json<JwkSet> {
+    Json(state.jwt.jwks())
+}
+
 /// Decode the claims from a JWT token string
 #[utoipa::path(
     post,
@@ -542,13 +1093,14 @@ pub async fn decode_token(
 ) -> Result<Json<DataResponse<Claims>>, AppError> {
     // Extract refresh token from cookies
     let token_str = payload.token;
-    // Validate the refresh token
-    let claims = state.jwt.validate_token(&token_str)?;
+    // Any of our tokens, whatever its type and audience
+    let claims = state.jwt.inspect_token(&token_str)?;
 
     Ok(Json(DataResponse::new().data(claims).build()))
 }
-pub fn router() -> Router<AppState> {
-    let auth_routes = Router::new()
+pub fn router(state: &AppState) -> Router<AppState> {
+    // Endpoints taking credentials, limited per client and per account
+    let credential_routes = Router::new()
         .route("/login", axum::routing::post(login))
         .route("/register", axum::routing::post(register))
         .route(
@@ -556,11 +1108,32 @@ pub fn router() -> Router<AppState> {
             axum::routing::post(request_magic_link),
         )
         .route("/magic-link/verify", axum::routing::post(verify_magic_link))
+        .route("/password/forgot", axum::routing::post(forgot_password))
+        .route("/password/reset", axum::routing::post(reset_password))
+        .route("/email/verify", axum::routing::post(verify_email))
+        .route("/email/resend", axum::routing::post(resend_verification))
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            rate_limit::limit,
+        ));
+
+    let auth_routes = Router::new()
+        .merge(credential_routes)
         .route("/refresh", axum::routing::post(refresh_token))
         .route("/logout", axum::routing::post(logout))
         .route("/me", axum::routing::get(get_current_user))
+        .route("/sessions", axum::routing::get(list_sessions))
+        .route(
+            "/sessions/{session_id}",
+            axum::routing::delete(revoke_session),
+        )
         .route("/keys", axum::routing::get(get_keys))
         .route("/decode", axum::routing::post(decode_token));
 
     Router::new().nest("/v1/auth", auth_routes)
 }
+
+/// Routes served at the root, where off-the-shelf verifiers look for them
+pub fn well_known_router() -> Router<AppState> {
+    Router::new().route("/.well-known/jwks.json", axum::routing::get(get_jwks))
+}
@@ -0,0 +1,486 @@
+use crate::http::api_keys::{self, CreatedApiKey};
+use crate::models::db::Environments;
+use crate::pkg::audit::{self, AuditEvent};
+use crate::pkg::auth::{AuthUser, ProjectMember};
+use crate::pkg::error::AppError;
+use crate::pkg::permissions::require;
+use crate::pkg::response::DataResponse;
+use crate::pkg::state::AppState;
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+};
+use axum_extra::extract::WithRejection;
+use pgmap::FromRow;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(utoipa::OpenApi)]
+#[openapi(
+    paths(
+        list_environments,
+        get_environment,
+        create_environment,
+        update_environment,
+        delete_environment,
+        clone_environment,
+    ),
+    components(
+        schemas(
+            Environments,
+            CreateEnvironmentRequest,
+            UpdateEnvironmentRequest,
+            CreatedEnvironment,
+        ),
+    ),
+    tags(
+        (name = "Environments", description = "Environments of a project and their flag states"),
+    ),
+)]
+#[allow(dead_code)]
+pub struct EnvironmentsApi;
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CreateEnvironmentRequest {
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct UpdateEnvironmentRequest {
+    pub name: Option<String>,
+}
+
+/// A new environment with the keys issued for it, the keys are only shown once
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CreatedEnvironment {
+    pub environment: Environments,
+    /// A server key and a client key
+    pub api_keys: Vec<CreatedApiKey>,
+}
+
+fn parse_environment(row: &tokio_postgres::Row) -> Result<Environments, AppError> {
+    Environments::from_row(row)
+        .map_err(|_| AppError::InternalError("Failed to parse environment data".to_string()))
+}
+
+fn validate_name(name: &str) -> Result<(), AppError> {
+    if name.trim().is_empty() || name.len() > 100 {
+        return Err(AppError::UnprocessableEntity(
+            "name: must be between 1 and 100 characters".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+async fn ensure_name_available(
+    client: &impl deadpool_postgres::GenericClient,
+    project_id: Uuid,
+    name: &str,
+    except: Option<Uuid>,
+) -> Result<(), AppError> {
+    let existing = client
+        .query_opt(
+            "SELECT id FROM environments
+             WHERE project_id = $1 AND name = $2 AND ($3::uuid IS NULL OR id <> $3)",
+            &[&project_id, &name, &except],
+        )
+        .await?;
+
+    if existing.is_some() {
+        return Err(AppError::Conflict(
+            "Environment name already exists".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+async fn find_environment(
+    client: &impl deadpool_postgres::GenericClient,
+    project_id: Uuid,
+    environment_id: Uuid,
+) -> Result<Environments, AppError> {
+    let row = client
+        .query_opt(
+            "SELECT * FROM environments WHERE id = $1 AND project_id = $2",
+            &[&environment_id, &project_id],
+        )
+        .await?
+        .ok_or(AppError::NotFound("Environment not found".to_string()))?;
+
+    parse_environment(&row)
+}
+
+/// Create an environment with a server and a client key.
+/// Flag states and overrides are copied from `source` when given,
+/// otherwise every flag of the project starts out disabled.
+pub(crate) async fn insert_environment(
+    client: &impl deadpool_postgres::GenericClient,
+    auth_user: &AuthUser,
+    project_id: Uuid,
+    name: &str,
+    source: Option<Uuid>,
+) -> Result<CreatedEnvironment, AppError> {
+    validate_name(name)?;
+    ensure_name_available(client, project_id, name, None).await?;
+
+    let row = client
+        .query_one(
+            "INSERT INTO environments (project_id, name) VALUES ($1, $2) RETURNING *",
+            &[&project_id, &name],
+        )
+        .await?;
+    let environment = parse_environment(&row)?;
+
+    match source {
+        Some(source) => {
+            client
+                .execute(
+                    "INSERT INTO feature_flag_environments
+                        (feature_flag_id, environment_id, is_enabled, value, rollout)
+                     SELECT feature_flag_id, $2, is_enabled, value, rollout
+                     FROM feature_flag_environments WHERE environment_id = $1",
+                    &[&source, &environment.id],
+                )
+                .await?;
+
+            client
+                .execute(
+                    "INSERT INTO feature_flag_overrides
+                        (feature_flag_id, audience_id, is_enabled, type, value, rollout, environment_id)
+                     SELECT feature_flag_id, audience_id, is_enabled, type, value, rollout, $2
+                     FROM feature_flag_overrides WHERE environment_id = $1",
+                    &[&source, &environment.id],
+                )
+                .await?;
+        }
+        None => {
+            client
+                .execute(
+                    "INSERT INTO feature_flag_environments (feature_flag_id, environment_id)
+                     SELECT id, $2 FROM feature_flags WHERE project_id = $1",
+                    &[&project_id, &environment.id],
+                )
+                .await?;
+        }
+    }
+
+    let action = match source {
+        Some(_) => "environment.cloned",
+        None => "environment.created",
+    };
+    let mut event = AuditEvent::new(auth_user, action, "environment", environment.id)
+        .project(project_id)
+        .environment(environment.id)
+        .after(&environment);
+    if let Some(source) = source {
+        event = event.before(&serde_json::json!({ "source_environment_id": source }));
+    }
+    audit::record(client, event).await?;
+
+    let mut keys = Vec::new();
+    for is_server_key in [true, false] {
+        keys.push(
+            api_keys::insert_api_key(client, auth_user, project_id, environment.id, is_server_key)
+                .await?,
+        );
+    }
+
+    Ok(CreatedEnvironment {
+        environment,
+        api_keys: keys,
+    })
+}
+
+/// List the environments of a project, oldest first
+#[utoipa::path(
+    get,
+    path = "/v1/projects/{project_id}/environments",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+    ),
+    responses(
+        (status = 200, description = "Environments", body = DataResponse<Vec<Environments>>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "Project not found", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Environments"
+)]
+async fn list_environments(
+    State(state): State<AppState>,
+    ProjectMember(_auth_user, _): ProjectMember<require::FlagRead>,
+    Path(project_id): Path<Uuid>,
+) -> Result<Json<DataResponse<Vec<Environments>>>, AppError> {
+    let client = state.db_pool.get().await?;
+
+    let rows = client
+        .query(
+            "SELECT * FROM environments WHERE project_id = $1 ORDER BY created_at, name",
+            &[&project_id],
+        )
+        .await?;
+
+    let environments = rows
+        .iter()
+        .map(parse_environment)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Json(DataResponse::new().data(environments).build()))
+}
+
+/// Get an environment
+#[utoipa::path(
+    get,
+    path = "/v1/projects/{project_id}/environments/{environment_id}",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+        ("environment_id" = Uuid, Path, description = "Environment ID"),
+    ),
+    responses(
+        (status = 200, description = "Environment", body = DataResponse<Environments>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "Environment not found", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Environments"
+)]
+async fn get_environment(
+    State(state): State<AppState>,
+    ProjectMember(_auth_user, _): ProjectMember<require::FlagRead>,
+    Path((project_id, environment_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<DataResponse<Environments>>, AppError> {
+    let client = state.db_pool.get().await?;
+
+    let environment = find_environment(&client, project_id, environment_id).await?;
+
+    Ok(Json(DataResponse::new().data(environment).build()))
+}
+
+/// Create an environment, every flag starts out disabled in it.
+/// The issued keys are only shown once.
+#[utoipa::path(
+    post,
+    path = "/v1/projects/{project_id}/environments",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+    ),
+    request_body = CreateEnvironmentRequest,
+    responses(
+        (status = 200, description = "Environment created", body = DataResponse<CreatedEnvironment>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "Forbidden", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "Project not found", body = DataResponse<serde_json::Value>),
+        (status = 409, description = "Environment name already exists", body = DataResponse<serde_json::Value>),
+        (status = 422, description = "Invalid environment", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Environments"
+)]
+async fn create_environment(
+    State(state): State<AppState>,
+    ProjectMember(auth_user, _): ProjectMember<require::ProjectManage>,
+    Path(project_id): Path<Uuid>,
+    WithRejection(Json(payload), _): WithRejection<Json<CreateEnvironmentRequest>, AppError>,
+) -> Result<Json<DataResponse<CreatedEnvironment>>, AppError> {
+    let mut client = state.db_pool.get().await?;
+
+    let transaction = client.transaction().await?;
+    let created =
+        insert_environment(&transaction, &auth_user, project_id, &payload.name, None).await?;
+    transaction.commit().await?;
+
+    Ok(Json(DataResponse::new().data(created).build()))
+}
+
+/// Copy an environment with all of its flag states and overrides into a new one.
+/// API keys are not copied, the new environment gets its own.
+#[utoipa::path(
+    post,
+    path = "/v1/projects/{project_id}/environments/{environment_id}/clone",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+        ("environment_id" = Uuid, Path, description = "Environment to copy"),
+    ),
+    request_body = CreateEnvironmentRequest,
+    responses(
+        (status = 200, description = "Environment cloned", body = DataResponse<CreatedEnvironment>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "Forbidden", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "Environment not found", body = DataResponse<serde_json::Value>),
+        (status = 409, description = "Environment name already exists", body = DataResponse<serde_json::Value>),
+        (status = 422, description = "Invalid environment", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Environments"
+)]
+async fn clone_environment(
+    State(state): State<AppState>,
+    ProjectMember(auth_user, _): ProjectMember<require::ProjectManage>,
+    Path((project_id, environment_id)): Path<(Uuid, Uuid)>,
+    WithRejection(Json(payload), _): WithRejection<Json<CreateEnvironmentRequest>, AppError>,
+) -> Result<Json<DataResponse<CreatedEnvironment>>, AppError> {
+    let mut client = state.db_pool.get().await?;
+
+    find_environment(&client, project_id, environment_id).await?;
+
+    let transaction = client.transaction().await?;
+    let created = insert_environment(
+        &transaction,
+        &auth_user,
+        project_id,
+        &payload.name,
+        Some(environment_id),
+    )
+    .await?;
+    transaction.commit().await?;
+
+    Ok(Json(DataResponse::new().data(created).build()))
+}
+
+/// Rename an environment
+#[utoipa::path(
+    patch,
+    path = "/v1/projects/{project_id}/environments/{environment_id}",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+        ("environment_id" = Uuid, Path, description = "Environment ID"),
+    ),
+    request_body = UpdateEnvironmentRequest,
+    responses(
+        (status = 200, description = "Environment updated", body = DataResponse<Environments>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "Forbidden", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "Environment not found", body = DataResponse<serde_json::Value>),
+        (status = 409, description = "Environment name already exists", body = DataResponse<serde_json::Value>),
+        (status = 422, description = "Invalid environment", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Environments"
+)]
+async fn update_environment(
+    State(state): State<AppState>,
+    ProjectMember(auth_user, _): ProjectMember<require::ProjectManage>,
+    Path((project_id, environment_id)): Path<(Uuid, Uuid)>,
+    WithRejection(Json(payload), _): WithRejection<Json<UpdateEnvironmentRequest>, AppError>,
+) -> Result<Json<DataResponse<Environments>>, AppError> {
+    let mut client = state.db_pool.get().await?;
+
+    if let Some(name) = &payload.name {
+        validate_name(name)?;
+        ensure_name_available(&client, project_id, name, Some(environment_id)).await?;
+    }
+
+    let transaction = client.transaction().await?;
+
+    let before = find_environment(&transaction, project_id, environment_id).await?;
+
+    let row = transaction
+        .query_one(
+            "UPDATE environments SET
+                name = COALESCE($2, name),
+                updated_at = current_timestamp
+             WHERE id = $1
+             RETURNING *",
+            &[&environment_id, &payload.name],
+        )
+        .await?;
+    let environment = parse_environment(&row)?;
+
+    audit::record(
+        &transaction,
+        AuditEvent::new(
+            &auth_user,
+            "environment.updated",
+            "environment",
+            environment_id,
+        )
+        .project(project_id)
+        .environment(environment_id)
+        .before(&before)
+        .after(&environment),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(DataResponse::new().data(environment).build()))
+}
+
+/// Delete an environment with its flag states, overrides and API keys
+#[utoipa::path(
+    delete,
+    path = "/v1/projects/{project_id}/environments/{environment_id}",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+        ("environment_id" = Uuid, Path, description = "Environment ID"),
+    ),
+    responses(
+        (status = 200, description = "Environment deleted", body = DataResponse<Environments>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "Forbidden", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "Environment not found", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Environments"
+)]
+async fn delete_environment(
+    State(state): State<AppState>,
+    ProjectMember(auth_user, _): ProjectMember<require::ProjectManage>,
+    Path((project_id, environment_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<DataResponse<Environments>>, AppError> {
+    let mut client = state.db_pool.get().await?;
+    let transaction = client.transaction().await?;
+
+    let environment = find_environment(&transaction, project_id, environment_id).await?;
+
+    transaction
+        .execute("DELETE FROM environments WHERE id = $1", &[&environment_id])
+        .await?;
+
+    audit::record(
+        &transaction,
+        AuditEvent::new(
+            &auth_user,
+            "environment.deleted",
+            "environment",
+            environment_id,
+        )
+        .project(project_id)
+        .environment(environment_id)
+        .before(&environment),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(DataResponse::new().data(environment).build()))
+}
+
+pub fn router() -> Router<AppState> {
+    let environment_routes = Router::new()
+        .route(
+            "/",
+            axum::routing::get(list_environments).post(create_environment),
+        )
+        .route(
+            "/{environment_id}",
+            axum::routing::get(get_environment)
+                .patch(update_environment)
+                .delete(delete_environment),
+        )
+        .route(
+            "/{environment_id}/clone",
+            axum::routing::post(clone_environment),
+        );
+
+    Router::new().nest("/v1/projects/{project_id}/environments", environment_routes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_name() {
+        assert!(validate_name("production").is_ok());
+        assert!(validate_name("  ").is_err());
+        assert!(validate_name(&"x".repeat(101)).is_err());
+    }
+}
@@ -0,0 +1,1206 @@
+use crate::http::sdk;
+use crate::http::variants::{self, CreateVariantRequest};
+use crate::models::db::{FeatureFlagEnvironments, FeatureFlagOverrides, FeatureFlags};
+use crate::models::enums::FeatureFlagType;
+use crate::pkg::audit::{self, AuditEvent};
+use crate::pkg::auth::ProjectMember;
+use crate::pkg::error::AppError;
+use crate::pkg::evaluation::{Evaluation, EvaluationContext, EvaluationReason};
+use crate::pkg::permissions::require;
+use crate::pkg::response::DataResponse;
+use crate::pkg::rollout::{Rollout, RolloutVariant};
+use crate::pkg::state::AppState;
+use crate::pkg::variants::compile_schema;
+use axum::{
+    Json, Router,
+    extract::{Path, Query, State},
+};
+use axum_extra::extract::WithRejection;
+use pgmap::FromRow;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+#[derive(utoipa::OpenApi)]
+#[openapi(
+    paths(
+        list_flags,
+        get_flag,
+        create_flag,
+        update_flag,
+        delete_flag,
+        list_flag_states,
+        update_flag_state,
+        list_overrides,
+        create_override,
+        update_override,
+        delete_override,
+        evaluate_flag,
+    ),
+    components(
+        schemas(
+            CreateFlagRequest,
+            UpdateFlagRequest,
+            UpdateFlagStateRequest,
+            CreateOverrideRequest,
+            UpdateOverrideRequest,
+            Rollout,
+            RolloutVariant,
+            EvaluateFlagRequest,
+            Evaluation,
+            EvaluationReason,
+            FeatureFlags,
+            FeatureFlagEnvironments,
+            FeatureFlagOverrides,
+            FeatureFlagType,
+        ),
+    ),
+    tags(
+        (name = "Flags", description = "Feature flag management endpoints"),
+    ),
+)]
+#[allow(dead_code)]
+pub struct FlagsApi;
+
+const MAX_KEY_LENGTH: usize = 100;
+
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListFlagsQuery {
+    /// Only return flags whose key starts with this prefix
+    pub key_prefix: Option<String>,
+    /// Only return flags of this type
+    pub r#type: Option<FeatureFlagType>,
+    /// Environment the `is_enabled` filter applies to
+    pub environment_id: Option<Uuid>,
+    /// Only return flags with this enabled state, requires `environment_id`
+    pub is_enabled: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CreateFlagRequest {
+    pub key: String,
+    pub r#type: FeatureFlagType,
+    /// Initial enabled state in every environment of the project
+    #[serde(default)]
+    pub is_enabled: bool,
+    /// Initial value in every environment of the project
+    pub value: Option<serde_json::Value>,
+    /// Variants of a multivariate flag, the initial value must be one of them
+    #[serde(default)]
+    pub variants: Vec<CreateVariantRequest>,
+    /// JSON Schema every value of a JSON flag must match
+    pub json_schema: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct UpdateFlagRequest {
+    pub key: Option<String>,
+    pub r#type: Option<FeatureFlagType>,
+    /// JSON Schema for values, every existing value of the flag must match it
+    pub json_schema: Option<serde_json::Value>,
+    /// Remove the JSON Schema, accepting any JSON value again
+    #[serde(default)]
+    pub clear_json_schema: bool,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct UpdateFlagStateRequest {
+    pub is_enabled: Option<bool>,
+    pub value: Option<serde_json::Value>,
+    /// Only serve the flag to part of the environment
+    pub rollout: Option<Rollout>,
+    /// Remove the rollout, serving the flag to everyone again
+    #[serde(default)]
+    pub clear_rollout: bool,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CreateOverrideRequest {
+    pub audience_id: Uuid,
+    #[serde(default)]
+    pub is_enabled: bool,
+    pub value: Option<serde_json::Value>,
+    /// Only apply the override to part of the audience
+    pub rollout: Option<Rollout>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct UpdateOverrideRequest {
+    pub is_enabled: Option<bool>,
+    pub value: Option<serde_json::Value>,
+    pub rollout: Option<Rollout>,
+    /// Remove the rollout, applying the override to the whole audience again
+    #[serde(default)]
+    pub clear_rollout: bool,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct EvaluateFlagRequest {
+    /// Environment to evaluate the flag in
+    pub environment_id: Uuid,
+    /// Attributes of the subject to evaluate the flag for
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    pub context: EvaluationContext,
+}
+
+/// Flag keys are used by SDKs as identifiers, so keep them short and URL safe
+fn validate_key(key: &str) -> Result<(), AppError> {
+    if key.is_empty() || key.len() > MAX_KEY_LENGTH {
+        return Err(AppError::UnprocessableEntity(format!(
+            "Flag key must be between 1 and {} characters",
+            MAX_KEY_LENGTH
+        )));
+    }
+
+    if !key
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        return Err(AppError::UnprocessableEntity(
+            "Flag key may only contain letters, digits, '-', '_' and '.'".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+fn parse_flag(row: &tokio_postgres::Row) -> Result<FeatureFlags, AppError> {
+    FeatureFlags::from_row(row)
+        .map_err(|_| AppError::InternalError("Failed to parse flag data".to_string()))
+}
+
+fn parse_flag_state(row: &tokio_postgres::Row) -> Result<FeatureFlagEnvironments, AppError> {
+    FeatureFlagEnvironments::from_row(row)
+        .map_err(|_| AppError::InternalError("Failed to parse flag state data".to_string()))
+}
+
+fn parse_override(row: &tokio_postgres::Row) -> Result<FeatureFlagOverrides, AppError> {
+    FeatureFlagOverrides::from_row(row)
+        .map_err(|_| AppError::InternalError("Failed to parse override data".to_string()))
+}
+
+/// Validate a rollout against the flag it applies to and convert it for storage
+fn rollout_column(
+    flag: &FeatureFlags,
+    rollout: Option<&Rollout>,
+) -> Result<Option<serde_json::Value>, AppError> {
+    rollout
+        .map(|rollout| {
+            rollout.validate(matches!(flag.r#type, FeatureFlagType::Multivariate))?;
+            serde_json::to_value(rollout)
+                .map_err(|_| AppError::InternalError("Failed to serialize rollout".to_string()))
+        })
+        .transpose()
+}
+
+/// JSON Schemas only apply to JSON flags and must be valid themselves
+fn validate_json_schema(
+    r#type: FeatureFlagType,
+    json_schema: Option<&serde_json::Value>,
+) -> Result<(), AppError> {
+    if let Some(json_schema) = json_schema {
+        if !matches!(r#type, FeatureFlagType::Json) {
+            return Err(AppError::UnprocessableEntity(
+                "json_schema: only supported on json flags".to_string(),
+            ));
+        }
+        compile_schema(json_schema)?;
+    }
+
+    Ok(())
+}
+
+/// Check a written value and rollout against the flag's type, variants and JSON Schema
+async fn validate_values(
+    client: &deadpool_postgres::Client,
+    flag: &FeatureFlags,
+    value: Option<&serde_json::Value>,
+    rollout: Option<&Rollout>,
+) -> Result<(), AppError> {
+    if value.is_none() && rollout.is_none() {
+        return Ok(());
+    }
+
+    let validator = variants::value_validator(client, flag).await?;
+    if let Some(value) = value {
+        validator.validate("value", value)?;
+    }
+    if let Some(rollout) = rollout {
+        validator.validate_rollout("rollout", rollout)?;
+    }
+
+    Ok(())
+}
+
+/// Check every stored state, override and rollout of a flag still fits its type, variants
+/// and JSON Schema, used when those change under existing values
+async fn revalidate_stored_values(
+    client: &impl deadpool_postgres::GenericClient,
+    flag: &FeatureFlags,
+) -> Result<(), AppError> {
+    let mut stored = Vec::new();
+
+    let rows = client
+        .query(
+            "SELECT * FROM feature_flag_environments WHERE feature_flag_id = $1",
+            &[&flag.id],
+        )
+        .await?;
+    for row in &rows {
+        let state = parse_flag_state(row)?;
+        stored.push((
+            format!("environments[{}]", state.environment_id),
+            state.value,
+            state.rollout,
+        ));
+    }
+
+    let rows = client
+        .query(
+            "SELECT * FROM feature_flag_overrides WHERE feature_flag_id = $1",
+            &[&flag.id],
+        )
+        .await?;
+    for row in &rows {
+        let flag_override = parse_override(row)?;
+        stored.push((
+            format!("overrides[{}]", flag_override.id),
+            flag_override.value,
+            flag_override.rollout,
+        ));
+    }
+
+    let validator = variants::value_validator(client, flag).await?;
+    let multivariate = matches!(flag.r#type, FeatureFlagType::Multivariate);
+    for (path, value, rollout) in &stored {
+        if let Some(value) = value {
+            validator.validate(&format!("{}.value", path), value)?;
+        }
+        if let Some(rollout) = Rollout::from_column(rollout.as_ref()) {
+            rollout.validate(multivariate)?;
+            validator.validate_rollout(&format!("{}.rollout", path), &rollout)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn ensure_flag(
+    client: &deadpool_postgres::Client,
+    project_id: Uuid,
+    flag_id: Uuid,
+) -> Result<FeatureFlags, AppError> {
+    let row = client
+        .query_opt(
+            "SELECT * FROM feature_flags WHERE id = $1 AND project_id = $2",
+            &[&flag_id, &project_id],
+        )
+        .await?
+        .ok_or(AppError::NotFound("Feature flag not found".to_string()))?;
+
+    parse_flag(&row)
+}
+
+async fn ensure_environment(
+    client: &deadpool_postgres::Client,
+    project_id: Uuid,
+    environment_id: Uuid,
+) -> Result<(), AppError> {
+    client
+        .query_opt(
+            "SELECT id FROM environments WHERE id = $1 AND project_id = $2",
+            &[&environment_id, &project_id],
+        )
+        .await?
+        .ok_or(AppError::NotFound("Environment not found".to_string()))?;
+
+    Ok(())
+}
+
+/// List feature flags in a project
+#[utoipa::path(
+    get,
+    path = "/v1/projects/{project_id}/flags",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+        ListFlagsQuery,
+    ),
+    responses(
+        (status = 200, description = "Feature flags", body = DataResponse<Vec<FeatureFlags>>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "Forbidden", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Flags"
+)]
+async fn list_flags(
+    State(state): State<AppState>,
+    ProjectMember(_auth_user, _): ProjectMember<require::FlagRead>,
+    Path(project_id): Path<Uuid>,
+    Query(query): Query<ListFlagsQuery>,
+) -> Result<Json<DataResponse<Vec<FeatureFlags>>>, AppError> {
+    if query.is_enabled.is_some() && query.environment_id.is_none() {
+        return Err(AppError::BadRequest(
+            "Filtering by is_enabled requires an environment_id".to_string(),
+        ));
+    }
+
+    let client = state.db_pool.get().await?;
+
+    let rows = client
+        .query(
+            "SELECT f.* FROM feature_flags f
+             LEFT JOIN feature_flag_environments fe
+               ON fe.feature_flag_id = f.id AND fe.environment_id = $4
+             WHERE f.project_id = $1
+               AND ($2::text IS NULL OR starts_with(f.key, $2))
+               AND ($3::feature_flag_type IS NULL OR f.type = $3)
+               AND ($5::boolean IS NULL OR COALESCE(fe.is_enabled, false) = $5)
+             ORDER BY f.key",
+            &[
+                &project_id,
+                &query.key_prefix,
+                &query.r#type,
+                &query.environment_id,
+                &query.is_enabled,
+            ],
+        )
+        .await?;
+
+    let flags = rows.iter().map(parse_flag).collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Json(DataResponse::new().data(flags).build()))
+}
+
+/// Get a single feature flag
+#[utoipa::path(
+    get,
+    path = "/v1/projects/{project_id}/flags/{flag_id}",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+        ("flag_id" = Uuid, Path, description = "Feature flag ID"),
+    ),
+    responses(
+        (status = 200, description = "Feature flag", body = DataResponse<FeatureFlags>),
+        (status = 404, description = "Feature flag not found", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Flags"
+)]
+async fn get_flag(
+    State(state): State<AppState>,
+    ProjectMember(_auth_user, _): ProjectMember<require::FlagRead>,
+    Path((project_id, flag_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<DataResponse<FeatureFlags>>, AppError> {
+    let client = state.db_pool.get().await?;
+
+    let flag = ensure_flag(&client, project_id, flag_id).await?;
+
+    Ok(Json(DataResponse::new().data(flag).build()))
+}
+
+/// Create a feature flag in a project, with the same initial state in every environment
+#[utoipa::path(
+    post,
+    path = "/v1/projects/{project_id}/flags",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+    ),
+    request_body = CreateFlagRequest,
+    responses(
+        (status = 200, description = "Feature flag created", body = DataResponse<FeatureFlags>),
+        (status = 404, description = "Project not found", body = DataResponse<serde_json::Value>),
+        (status = 409, description = "Flag key already exists", body = DataResponse<serde_json::Value>),
+        (status = 422, description = "Invalid flag", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Flags"
+)]
+async fn create_flag(
+    State(state): State<AppState>,
+    ProjectMember(auth_user, _): ProjectMember<require::FlagWrite>,
+    Path(project_id): Path<Uuid>,
+    WithRejection(Json(payload), _): WithRejection<Json<CreateFlagRequest>, AppError>,
+) -> Result<Json<DataResponse<FeatureFlags>>, AppError> {
+    validate_key(&payload.key)?;
+    validate_json_schema(payload.r#type, payload.json_schema.as_ref())?;
+
+    let mut client = state.db_pool.get().await?;
+
+    let project = client
+        .query_opt("SELECT org_id FROM projects WHERE id = $1", &[&project_id])
+        .await?
+        .ok_or(AppError::NotFound("Project not found".to_string()))?;
+    let org_id: Uuid = project.get(0);
+
+    // Check if the key is already taken in this project
+    let existing = client
+        .query_opt(
+            "SELECT id FROM feature_flags WHERE key = $1 AND project_id = $2",
+            &[&payload.key, &project_id],
+        )
+        .await?;
+
+    if existing.is_some() {
+        return Err(AppError::Conflict("Flag key already exists".to_string()));
+    }
+
+    let transaction = client.transaction().await?;
+
+    let row = transaction
+        .query_one(
+            "INSERT INTO feature_flags (org_id, key, type, project_id, json_schema)
+             VALUES ($1, $2, $3, $4, $5)
+             RETURNING *",
+            &[
+                &org_id,
+                &payload.key,
+                &payload.r#type,
+                &project_id,
+                &payload.json_schema,
+            ],
+        )
+        .await?;
+    let flag = parse_flag(&row)?;
+
+    for variant in &payload.variants {
+        variants::insert_variant(&transaction, (&auth_user).into(), &flag, variant).await?;
+    }
+
+    if let Some(value) = &payload.value {
+        variants::value_validator(&transaction, &flag)
+            .await?
+            .validate("value", value)?;
+    }
+
+    transaction
+        .execute(
+            "INSERT INTO feature_flag_environments (feature_flag_id, environment_id, is_enabled, value)
+             SELECT $1, id, $2, $3 FROM environments WHERE project_id = $4",
+            &[&flag.id, &payload.is_enabled, &payload.value, &project_id],
+        )
+        .await?;
+
+    audit::record(
+        &transaction,
+        AuditEvent::new(&auth_user, "flag.created", "flag", flag.id)
+            .project(project_id)
+            .after(&flag),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    let environment_ids = sdk::project_environments(&client, project_id).await?;
+    sdk::publish_flag_change(
+        &state,
+        &client,
+        project_id,
+        flag.id,
+        &flag.key,
+        &environment_ids,
+        false,
+    )
+    .await;
+
+    Ok(Json(DataResponse::new().data(flag).build()))
+}
+
+/// Partially update a feature flag
+#[utoipa::path(
+    patch,
+    path = "/v1/projects/{project_id}/flags/{flag_id}",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+        ("flag_id" = Uuid, Path, description = "Feature flag ID"),
+    ),
+    request_body = UpdateFlagRequest,
+    responses(
+        (status = 200, description = "Feature flag updated", body = DataResponse<FeatureFlags>),
+        (status = 404, description = "Feature flag not found", body = DataResponse<serde_json::Value>),
+        (status = 409, description = "Flag key already exists", body = DataResponse<serde_json::Value>),
+        (status = 422, description = "Invalid flag", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Flags"
+)]
+async fn update_flag(
+    State(state): State<AppState>,
+    ProjectMember(auth_user, _): ProjectMember<require::FlagWrite>,
+    Path((project_id, flag_id)): Path<(Uuid, Uuid)>,
+    WithRejection(Json(payload), _): WithRejection<Json<UpdateFlagRequest>, AppError>,
+) -> Result<Json<DataResponse<FeatureFlags>>, AppError> {
+    let mut client = state.db_pool.get().await?;
+
+    let flag = ensure_flag(&client, project_id, flag_id).await?;
+    validate_json_schema(
+        payload.r#type.unwrap_or(flag.r#type),
+        payload.json_schema.as_ref(),
+    )?;
+
+    if let Some(key) = &payload.key {
+        validate_key(key)?;
+
+        let existing = client
+            .query_opt(
+                "SELECT id FROM feature_flags WHERE key = $1 AND project_id = $2 AND id <> $3",
+                &[key, &project_id, &flag_id],
+            )
+            .await?;
+
+        if existing.is_some() {
+            return Err(AppError::Conflict("Flag key already exists".to_string()));
+        }
+    }
+
+    let transaction = client.transaction().await?;
+
+    let before = transaction
+        .query_one(
+            "SELECT * FROM feature_flags WHERE id = $1 FOR UPDATE",
+            &[&flag_id],
+        )
+        .await?;
+    let before = parse_flag(&before)?;
+
+    let row = transaction
+        .query_one(
+            "UPDATE feature_flags SET
+                key = COALESCE($3, key),
+                type = COALESCE($4, type),
+                json_schema = CASE WHEN $6 OR COALESCE($4, type) <> 'json' THEN NULL
+                                   ELSE COALESCE($5, json_schema) END,
+                updated_at = current_timestamp
+             WHERE id = $1 AND project_id = $2
+             RETURNING *",
+            &[
+                &flag_id,
+                &project_id,
+                &payload.key,
+                &payload.r#type,
+                &payload.json_schema,
+                &payload.clear_json_schema,
+            ],
+        )
+        .await?;
+    let flag = parse_flag(&row)?;
+
+    // A new type or schema must still accept every value the flag already holds
+    if payload.r#type.is_some() || payload.json_schema.is_some() || payload.clear_json_schema {
+        transaction
+            .execute(
+                "UPDATE feature_flag_overrides SET type = $2 WHERE feature_flag_id = $1",
+                &[&flag_id, &flag.r#type],
+            )
+            .await?;
+        revalidate_stored_values(&transaction, &flag).await?;
+    }
+
+    audit::record(
+        &transaction,
+        AuditEvent::new(&auth_user, "flag.updated", "flag", flag.id)
+            .project(project_id)
+            .before(&before)
+            .after(&flag),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    let environment_ids = sdk::project_environments(&client, project_id).await?;
+    sdk::publish_flag_change(
+        &state,
+        &client,
+        project_id,
+        flag.id,
+        &flag.key,
+        &environment_ids,
+        false,
+    )
+    .await;
+
+    Ok(Json(DataResponse::new().data(flag).build()))
+}
+
+/// Delete a feature flag
+#[utoipa::path(
+    delete,
+    path = "/v1/projects/{project_id}/flags/{flag_id}",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+        ("flag_id" = Uuid, Path, description = "Feature flag ID"),
+    ),
+    responses(
+        (status = 200, description = "Feature flag deleted", body = DataResponse<FeatureFlags>),
+        (status = 404, description = "Feature flag not found", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Flags"
+)]
+async fn delete_flag(
+    State(state): State<AppState>,
+    ProjectMember(auth_user, _): ProjectMember<require::FlagWrite>,
+    Path((project_id, flag_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<DataResponse<FeatureFlags>>, AppError> {
+    let mut client = state.db_pool.get().await?;
+
+    let transaction = client.transaction().await?;
+
+    let row = transaction
+        .query_opt(
+            "DELETE FROM feature_flags WHERE id = $1 AND project_id = $2 RETURNING *",
+            &[&flag_id, &project_id],
+        )
+        .await?
+        .ok_or(AppError::NotFound("Feature flag not found".to_string()))?;
+
+    let flag = parse_flag(&row)?;
+
+    audit::record(
+        &transaction,
+        AuditEvent::new(&auth_user, "flag.deleted", "flag", flag.id)
+            .project(project_id)
+            .before(&flag),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    let environment_ids = sdk::project_environments(&client, project_id).await?;
+    sdk::publish_flag_change(
+        &state,
+        &client,
+        project_id,
+        flag.id,
+        &flag.key,
+        &environment_ids,
+        true,
+    )
+    .await;
+
+    Ok(Json(DataResponse::new().data(flag).build()))
+}
+
+/// List the state of a feature flag in every environment
+#[utoipa::path(
+    get,
+    path = "/v1/projects/{project_id}/flags/{flag_id}/environments",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+        ("flag_id" = Uuid, Path, description = "Feature flag ID"),
+    ),
+    responses(
+        (status = 200, description = "Flag state per environment", body = DataResponse<Vec<FeatureFlagEnvironments>>),
+        (status = 404, description = "Feature flag not found", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Flags"
+)]
+async fn list_flag_states(
+    State(state): State<AppState>,
+    ProjectMember(_auth_user, _): ProjectMember<require::FlagRead>,
+    Path((project_id, flag_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<DataResponse<Vec<FeatureFlagEnvironments>>>, AppError> {
+    let client = state.db_pool.get().await?;
+
+    ensure_flag(&client, project_id, flag_id).await?;
+
+    let rows = client
+        .query(
+            "SELECT fe.* FROM feature_flag_environments fe
+             JOIN environments e ON e.id = fe.environment_id
+             WHERE fe.feature_flag_id = $1
+             ORDER BY e.created_at, e.id",
+            &[&flag_id],
+        )
+        .await?;
+
+    let states = rows
+        .iter()
+        .map(parse_flag_state)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Json(DataResponse::new().data(states).build()))
+}
+
+/// Update the state of a feature flag in one environment
+#[utoipa::path(
+    patch,
+    path = "/v1/projects/{project_id}/flags/{flag_id}/environments/{environment_id}",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+        ("flag_id" = Uuid, Path, description = "Feature flag ID"),
+        ("environment_id" = Uuid, Path, description = "Environment ID"),
+    ),
+    request_body = UpdateFlagStateRequest,
+    responses(
+        (status = 200, description = "Flag state updated", body = DataResponse<FeatureFlagEnvironments>),
+        (status = 404, description = "Feature flag or environment not found", body = DataResponse<serde_json::Value>),
+        (status = 422, description = "Invalid value or rollout", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Flags"
+)]
+async fn update_flag_state(
+    State(state): State<AppState>,
+    ProjectMember(auth_user, _): ProjectMember<require::FlagWrite>,
+    Path((project_id, flag_id, environment_id)): Path<(Uuid, Uuid, Uuid)>,
+    WithRejection(Json(payload), _): WithRejection<Json<UpdateFlagStateRequest>, AppError>,
+) -> Result<Json<DataResponse<FeatureFlagEnvironments>>, AppError> {
+    let mut client = state.db_pool.get().await?;
+
+    let flag = ensure_flag(&client, project_id, flag_id).await?;
+    ensure_environment(&client, project_id, environment_id).await?;
+    validate_values(
+        &client,
+        &flag,
+        payload.value.as_ref(),
+        payload.rollout.as_ref(),
+    )
+    .await?;
+    let rollout = rollout_column(&flag, payload.rollout.as_ref())?;
+
+    let transaction = client.transaction().await?;
+
+    let before = transaction
+        .query_opt(
+            "SELECT * FROM feature_flag_environments
+             WHERE feature_flag_id = $1 AND environment_id = $2
+             FOR UPDATE",
+            &[&flag_id, &environment_id],
+        )
+        .await?
+        .as_ref()
+        .map(parse_flag_state)
+        .transpose()?;
+
+    let row = transaction
+        .query_one(
+            "INSERT INTO feature_flag_environments (feature_flag_id, environment_id, is_enabled, value, rollout)
+             VALUES ($1, $2, COALESCE($3, false), $4, $5)
+             ON CONFLICT (feature_flag_id, environment_id) DO UPDATE SET
+                is_enabled = COALESCE($3, feature_flag_environments.is_enabled),
+                value = COALESCE($4, feature_flag_environments.value),
+                rollout = CASE WHEN $6 THEN NULL
+                               ELSE COALESCE($5, feature_flag_environments.rollout) END,
+                updated_at = current_timestamp
+             RETURNING *",
+            &[
+                &flag_id,
+                &environment_id,
+                &payload.is_enabled,
+                &payload.value,
+                &rollout,
+                &payload.clear_rollout,
+            ],
+        )
+        .await?;
+
+    let flag_state = parse_flag_state(&row)?;
+
+    // Recorded under the flag, so a flag's history covers its state in every environment
+    let mut event = AuditEvent::new(&auth_user, "flag_state.updated", "flag_state", flag.id)
+        .project(project_id)
+        .environment(environment_id)
+        .after(&flag_state);
+    if let Some(before) = &before {
+        event = event.before(before);
+    }
+    audit::record(&transaction, event).await?;
+
+    transaction.commit().await?;
+
+    sdk::publish_flag_change(
+        &state,
+        &client,
+        project_id,
+        flag.id,
+        &flag.key,
+        &[environment_id],
+        false,
+    )
+    .await;
+
+    Ok(Json(DataResponse::new().data(flag_state).build()))
+}
+
+/// List the audience overrides of a feature flag in one environment, in the order they apply
+#[utoipa::path(
+    get,
+    path = "/v1/projects/{project_id}/flags/{flag_id}/environments/{environment_id}/overrides",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+        ("flag_id" = Uuid, Path, description = "Feature flag ID"),
+        ("environment_id" = Uuid, Path, description = "Environment ID"),
+    ),
+    responses(
+        (status = 200, description = "Overrides", body = DataResponse<Vec<FeatureFlagOverrides>>),
+        (status = 404, description = "Feature flag or environment not found", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Flags"
+)]
+async fn list_overrides(
+    State(state): State<AppState>,
+    ProjectMember(_auth_user, _): ProjectMember<require::FlagRead>,
+    Path((project_id, flag_id, environment_id)): Path<(Uuid, Uuid, Uuid)>,
+) -> Result<Json<DataResponse<Vec<FeatureFlagOverrides>>>, AppError> {
+    let client = state.db_pool.get().await?;
+
+    ensure_flag(&client, project_id, flag_id).await?;
+    ensure_environment(&client, project_id, environment_id).await?;
+
+    let rows = client
+        .query(
+            "SELECT * FROM feature_flag_overrides
+             WHERE feature_flag_id = $1 AND environment_id = $2
+             ORDER BY created_at, id",
+            &[&flag_id, &environment_id],
+        )
+        .await?;
+
+    let overrides = rows
+        .iter()
+        .map(parse_override)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Json(DataResponse::new().data(overrides).build()))
+}
+
+/// Override a feature flag for an audience in one environment
+#[utoipa::path(
+    post,
+    path = "/v1/projects/{project_id}/flags/{flag_id}/environments/{environment_id}/overrides",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+        ("flag_id" = Uuid, Path, description = "Feature flag ID"),
+        ("environment_id" = Uuid, Path, description = "Environment ID"),
+    ),
+    request_body = CreateOverrideRequest,
+    responses(
+        (status = 200, description = "Override created", body = DataResponse<FeatureFlagOverrides>),
+        (status = 404, description = "Feature flag, environment or audience not found", body = DataResponse<serde_json::Value>),
+        (status = 409, description = "Audience already has an override", body = DataResponse<serde_json::Value>),
+        (status = 422, description = "Invalid value or rollout", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Flags"
+)]
+async fn create_override(
+    State(state): State<AppState>,
+    ProjectMember(auth_user, _): ProjectMember<require::FlagWrite>,
+    Path((project_id, flag_id, environment_id)): Path<(Uuid, Uuid, Uuid)>,
+    WithRejection(Json(payload), _): WithRejection<Json<CreateOverrideRequest>, AppError>,
+) -> Result<Json<DataResponse<FeatureFlagOverrides>>, AppError> {
+    let mut client = state.db_pool.get().await?;
+
+    let flag = ensure_flag(&client, project_id, flag_id).await?;
+    ensure_environment(&client, project_id, environment_id).await?;
+    validate_values(
+        &client,
+        &flag,
+        payload.value.as_ref(),
+        payload.rollout.as_ref(),
+    )
+    .await?;
+    let rollout = rollout_column(&flag, payload.rollout.as_ref())?;
+
+    client
+        .query_opt(
+            "SELECT id FROM audiences WHERE id = $1 AND project_id = $2",
+            &[&payload.audience_id, &project_id],
+        )
+        .await?
+        .ok_or(AppError::NotFound("Audience not found".to_string()))?;
+
+    let existing = client
+        .query_opt(
+            "SELECT id FROM feature_flag_overrides
+             WHERE feature_flag_id = $1 AND environment_id = $2 AND audience_id = $3",
+            &[&flag_id, &environment_id, &payload.audience_id],
+        )
+        .await?;
+
+    if existing.is_some() {
+        return Err(AppError::Conflict(
+            "Audience already has an override".to_string(),
+        ));
+    }
+
+    let transaction = client.transaction().await?;
+
+    let row = transaction
+        .query_one(
+            "INSERT INTO feature_flag_overrides
+                (feature_flag_id, environment_id, audience_id, is_enabled, type, value, rollout)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)
+             RETURNING *",
+            &[
+                &flag_id,
+                &environment_id,
+                &payload.audience_id,
+                &payload.is_enabled,
+                &flag.r#type,
+                &payload.value,
+                &rollout,
+            ],
+        )
+        .await?;
+
+    let flag_override = parse_override(&row)?;
+
+    audit::record(
+        &transaction,
+        AuditEvent::new(&auth_user, "override.created", "override", flag_override.id)
+            .project(project_id)
+            .environment(environment_id)
+            .after(&flag_override),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    sdk::publish_flag_change(
+        &state,
+        &client,
+        project_id,
+        flag.id,
+        &flag.key,
+        &[environment_id],
+        false,
+    )
+    .await;
+
+    Ok(Json(DataResponse::new().data(flag_override).build()))
+}
+
+/// Partially update an audience override
+#[utoipa::path(
+    patch,
+    path = "/v1/projects/{project_id}/flags/{flag_id}/environments/{environment_id}/overrides/{override_id}",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+        ("flag_id" = Uuid, Path, description = "Feature flag ID"),
+        ("environment_id" = Uuid, Path, description = "Environment ID"),
+        ("override_id" = Uuid, Path, description = "Override ID"),
+    ),
+    request_body = UpdateOverrideRequest,
+    responses(
+        (status = 200, description = "Override updated", body = DataResponse<FeatureFlagOverrides>),
+        (status = 404, description = "Override not found", body = DataResponse<serde_json::Value>),
+        (status = 422, description = "Invalid value or rollout", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Flags"
+)]
+async fn update_override(
+    State(state): State<AppState>,
+    ProjectMember(auth_user, _): ProjectMember<require::FlagWrite>,
+    Path((project_id, flag_id, environment_id, override_id)): Path<(Uuid, Uuid, Uuid, Uuid)>,
+    WithRejection(Json(payload), _): WithRejection<Json<UpdateOverrideRequest>, AppError>,
+) -> Result<Json<DataResponse<FeatureFlagOverrides>>, AppError> {
+    let mut client = state.db_pool.get().await?;
+
+    let flag = ensure_flag(&client, project_id, flag_id).await?;
+    validate_values(
+        &client,
+        &flag,
+        payload.value.as_ref(),
+        payload.rollout.as_ref(),
+    )
+    .await?;
+    let rollout = rollout_column(&flag, payload.rollout.as_ref())?;
+
+    let transaction = client.transaction().await?;
+
+    let before = transaction
+        .query_opt(
+            "SELECT * FROM feature_flag_overrides
+             WHERE id = $1 AND feature_flag_id = $2 AND environment_id = $3
+             FOR UPDATE",
+            &[&override_id, &flag_id, &environment_id],
+        )
+        .await?
+        .ok_or(AppError::NotFound("Override not found".to_string()))?;
+    let before = parse_override(&before)?;
+
+    let row = transaction
+        .query_one(
+            "UPDATE feature_flag_overrides SET
+                is_enabled = COALESCE($4, is_enabled),
+                value = COALESCE($5, value),
+                rollout = CASE WHEN $7 THEN NULL ELSE COALESCE($6, rollout) END,
+                updated_at = current_timestamp
+             WHERE id = $1 AND feature_flag_id = $2 AND environment_id = $3
+             RETURNING *",
+            &[
+                &override_id,
+                &flag_id,
+                &environment_id,
+                &payload.is_enabled,
+                &payload.value,
+                &rollout,
+                &payload.clear_rollout,
+            ],
+        )
+        .await?;
+    let flag_override = parse_override(&row)?;
+
+    audit::record(
+        &transaction,
+        AuditEvent::new(&auth_user, "override.updated", "override", flag_override.id)
+            .project(project_id)
+            .environment(environment_id)
+            .before(&before)
+            .after(&flag_override),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    sdk::publish_flag_change(
+        &state,
+        &client,
+        project_id,
+        flag.id,
+        &flag.key,
+        &[environment_id],
+        false,
+    )
+    .await;
+
+    Ok(Json(DataResponse::new().data(flag_override).build()))
+}
+
+/// Delete an audience override
+#[utoipa::path(
+    delete,
+    path = "/v1/projects/{project_id}/flags/{flag_id}/environments/{environment_id}/overrides/{override_id}",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+        ("flag_id" = Uuid, Path, description = "Feature flag ID"),
+        ("environment_id" = Uuid, Path, description = "Environment ID"),
+        ("override_id" = Uuid, Path, description = "Override ID"),
+    ),
+    responses(
+        (status = 200, description = "Override deleted", body = DataResponse<FeatureFlagOverrides>),
+        (status = 404, description = "Override not found", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Flags"
+)]
+async fn delete_override(
+    State(state): State<AppState>,
+    ProjectMember(auth_user, _): ProjectMember<require::FlagWrite>,
+    Path((project_id, flag_id, environment_id, override_id)): Path<(Uuid, Uuid, Uuid, Uuid)>,
+) -> Result<Json<DataResponse<FeatureFlagOverrides>>, AppError> {
+    let mut client = state.db_pool.get().await?;
+
+    let flag = ensure_flag(&client, project_id, flag_id).await?;
+
+    let transaction = client.transaction().await?;
+
+    let row = transaction
+        .query_opt(
+            "DELETE FROM feature_flag_overrides
+             WHERE id = $1 AND feature_flag_id = $2 AND environment_id = $3
+             RETURNING *",
+            &[&override_id, &flag_id, &environment_id],
+        )
+        .await?
+        .ok_or(AppError::NotFound("Override not found".to_string()))?;
+
+    let flag_override = parse_override(&row)?;
+
+    audit::record(
+        &transaction,
+        AuditEvent::new(&auth_user, "override.deleted", "override", flag_override.id)
+            .project(project_id)
+            .environment(environment_id)
+            .before(&flag_override),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    sdk::publish_flag_change(
+        &state,
+        &client,
+        project_id,
+        flag.id,
+        &flag.key,
+        &[environment_id],
+        false,
+    )
+    .await;
+
+    Ok(Json(DataResponse::new().data(flag_override).build()))
+}
+
+/// Evaluate a feature flag against a context, to preview what a user would get
+#[utoipa::path(
+    post,
+    path = "/v1/projects/{project_id}/flags/{flag_id}/evaluate",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+        ("flag_id" = Uuid, Path, description = "Feature flag ID"),
+    ),
+    request_body = EvaluateFlagRequest,
+    responses(
+        (status = 200, description = "Evaluation result", body = DataResponse<Evaluation>),
+        (status = 404, description = "Feature flag or environment not found", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Flags"
+)]
+async fn evaluate_flag(
+    State(state): State<AppState>,
+    ProjectMember(_auth_user, _): ProjectMember<require::FlagRead>,
+    Path((project_id, flag_id)): Path<(Uuid, Uuid)>,
+    WithRejection(Json(payload), _): WithRejection<Json<EvaluateFlagRequest>, AppError>,
+) -> Result<Json<DataResponse<Evaluation>>, AppError> {
+    let client = state.db_pool.get().await?;
+
+    let flag = ensure_flag(&client, project_id, flag_id).await?;
+    ensure_environment(&client, project_id, payload.environment_id).await?;
+
+    let ruleset = sdk::environment_ruleset(&state, project_id, payload.environment_id).await?;
+    let result = ruleset
+        .evaluate_one(flag.id, &payload.context)
+        .ok_or(AppError::NotFound("Feature flag not found".to_string()))?;
+
+    Ok(Json(DataResponse::new().data(result).build()))
+}
+
+pub fn router() -> Router<AppState> {
+    let flag_routes = Router::new()
+        .route("/", axum::routing::get(list_flags).post(create_flag))
+        .route(
+            "/{flag_id}",
+            axum::routing::get(get_flag)
+                .patch(update_flag)
+                .delete(delete_flag),
+        )
+        .route(
+            "/{flag_id}/environments",
+            axum::routing::get(list_flag_states),
+        )
+        .route(
+            "/{flag_id}/environments/{environment_id}",
+            axum::routing::patch(update_flag_state),
+        )
+        .route(
+            "/{flag_id}/environments/{environment_id}/overrides",
+            axum::routing::get(list_overrides).post(create_override),
+        )
+        .route(
+            "/{flag_id}/environments/{environment_id}/overrides/{override_id}",
+            axum::routing::patch(update_override).delete(delete_override),
+        )
+        .route("/{flag_id}/evaluate", axum::routing::post(evaluate_flag));
+
+    Router::new().nest("/v1/projects/{project_id}/flags", flag_routes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_key() {
+        assert!(validate_key("new-checkout.v2_beta").is_ok());
+        assert!(validate_key("").is_err());
+        assert!(validate_key("has space").is_err());
+        assert!(validate_key(&"a".repeat(MAX_KEY_LENGTH + 1)).is_err());
+    }
+}
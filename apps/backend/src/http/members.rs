@@ -0,0 +1,445 @@
+use crate::models::enums::ProjectRole;
+use crate::pkg::audit::{self, AuditEvent};
+use crate::pkg::auth::ProjectMember;
+use crate::pkg::error::AppError;
+use crate::pkg::permissions::require;
+use crate::pkg::response::DataResponse;
+use crate::pkg::state::AppState;
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+};
+use axum_extra::extract::WithRejection;
+use chrono::{DateTime, Utc};
+use pgmap::FromRow;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(utoipa::OpenApi)]
+#[openapi(
+    paths(
+        list_members,
+        add_member,
+        update_member,
+        remove_member,
+        transfer_ownership,
+    ),
+    components(
+        schemas(
+            Member,
+            AddMemberRequest,
+            UpdateMemberRequest,
+            TransferOwnershipRequest,
+        ),
+    ),
+    tags(
+        (name = "Members", description = "Project membership and per-project roles"),
+    ),
+)]
+#[allow(dead_code)]
+pub struct MembersApi;
+
+/// A user's membership of a project
+#[derive(FromRow, Serialize, Deserialize, ToSchema)]
+pub struct Member {
+    pub user_id: Uuid,
+    pub email: String,
+    pub role: ProjectRole,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct AddMemberRequest {
+    /// Email of an existing user
+    pub email: String,
+    /// `Editor` or `Viewer`, ownership is handed over with a transfer
+    pub role: ProjectRole,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct UpdateMemberRequest {
+    /// `Editor` or `Viewer`, ownership is handed over with a transfer
+    pub role: ProjectRole,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct TransferOwnershipRequest {
+    /// Member becoming the owner, the current owner stays on as an editor
+    pub user_id: Uuid,
+}
+
+const MEMBER_SELECT: &str = "SELECT po.user_id, u.email, po.role, po.created_at
+     FROM project_owners po
+     JOIN users u ON u.id = po.user_id";
+
+fn parse_member(row: &tokio_postgres::Row) -> Result<Member, AppError> {
+    Member::from_row(row)
+        .map_err(|_| AppError::InternalError("Failed to parse member data".to_string()))
+}
+
+fn ensure_not_owner_role(role: ProjectRole) -> Result<(), AppError> {
+    match role {
+        ProjectRole::Owner => Err(AppError::UnprocessableEntity(
+            "role: transfer ownership to make a member the owner".to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+async fn ensure_project(
+    client: &deadpool_postgres::Client,
+    project_id: Uuid,
+) -> Result<(), AppError> {
+    client
+        .query_opt("SELECT id FROM projects WHERE id = $1", &[&project_id])
+        .await?
+        .ok_or(AppError::NotFound("Project not found".to_string()))?;
+
+    Ok(())
+}
+
+async fn find_member(
+    client: &impl deadpool_postgres::GenericClient,
+    project_id: Uuid,
+    user_id: Uuid,
+) -> Result<Member, AppError> {
+    let row = client
+        .query_opt(
+            &format!(
+                "{} WHERE po.project_id = $1 AND po.user_id = $2",
+                MEMBER_SELECT
+            ),
+            &[&project_id, &user_id],
+        )
+        .await?
+        .ok_or(AppError::NotFound("Member not found".to_string()))?;
+
+    parse_member(&row)
+}
+
+/// List the members of a project
+#[utoipa::path(
+    get,
+    path = "/v1/projects/{project_id}/members",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+    ),
+    responses(
+        (status = 200, description = "Members", body = DataResponse<Vec<Member>>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "Project not found", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Members"
+)]
+async fn list_members(
+    State(state): State<AppState>,
+    ProjectMember(_auth_user, _): ProjectMember<require::FlagRead>,
+    Path(project_id): Path<Uuid>,
+) -> Result<Json<DataResponse<Vec<Member>>>, AppError> {
+    let client = state.db_pool.get().await?;
+
+    ensure_project(&client, project_id).await?;
+
+    let rows = client
+        .query(
+            &format!(
+                "{} WHERE po.project_id = $1 ORDER BY po.created_at, u.email",
+                MEMBER_SELECT
+            ),
+            &[&project_id],
+        )
+        .await?;
+
+    let members = rows
+        .iter()
+        .map(parse_member)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Json(DataResponse::new().data(members).build()))
+}
+
+/// Add an existing user to a project
+#[utoipa::path(
+    post,
+    path = "/v1/projects/{project_id}/members",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+    ),
+    request_body = AddMemberRequest,
+    responses(
+        (status = 200, description = "Member added", body = DataResponse<Member>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "Forbidden", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "Project or user not found", body = DataResponse<serde_json::Value>),
+        (status = 409, description = "User is already a member", body = DataResponse<serde_json::Value>),
+        (status = 422, description = "Invalid role", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Members"
+)]
+async fn add_member(
+    State(state): State<AppState>,
+    ProjectMember(auth_user, _): ProjectMember<require::ProjectManage>,
+    Path(project_id): Path<Uuid>,
+    WithRejection(Json(payload), _): WithRejection<Json<AddMemberRequest>, AppError>,
+) -> Result<Json<DataResponse<Member>>, AppError> {
+    ensure_not_owner_role(payload.role)?;
+
+    let mut client = state.db_pool.get().await?;
+
+    ensure_project(&client, project_id).await?;
+
+    // Only users of the project's org can join it
+    let user_id: Uuid = client
+        .query_opt(
+            "SELECT id FROM users WHERE email = $1 AND org_id = $2",
+            &[&payload.email, &auth_user.org_id],
+        )
+        .await?
+        .ok_or(AppError::NotFound("User not found".to_string()))?
+        .get(0);
+
+    let transaction = client.transaction().await?;
+
+    let inserted = transaction
+        .execute(
+            "INSERT INTO project_owners (project_id, user_id, role) VALUES ($1, $2, $3)
+             ON CONFLICT (project_id, user_id) DO NOTHING",
+            &[&project_id, &user_id, &payload.role],
+        )
+        .await?;
+
+    if inserted == 0 {
+        return Err(AppError::Conflict(
+            "User is already a member of this project".to_string(),
+        ));
+    }
+
+    let member = find_member(&transaction, project_id, user_id).await?;
+
+    audit::record(
+        &transaction,
+        AuditEvent::new(&auth_user, "member.added", "member", user_id)
+            .project(project_id)
+            .after(&member),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(DataResponse::new().data(member).build()))
+}
+
+/// Change the role of a member
+#[utoipa::path(
+    patch,
+    path = "/v1/projects/{project_id}/members/{user_id}",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+        ("user_id" = Uuid, Path, description = "User ID"),
+    ),
+    request_body = UpdateMemberRequest,
+    responses(
+        (status = 200, description = "Member updated", body = DataResponse<Member>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "Forbidden", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "Member not found", body = DataResponse<serde_json::Value>),
+        (status = 409, description = "Member is the owner", body = DataResponse<serde_json::Value>),
+        (status = 422, description = "Invalid role", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Members"
+)]
+async fn update_member(
+    State(state): State<AppState>,
+    ProjectMember(auth_user, _): ProjectMember<require::ProjectManage>,
+    Path((project_id, user_id)): Path<(Uuid, Uuid)>,
+    WithRejection(Json(payload), _): WithRejection<Json<UpdateMemberRequest>, AppError>,
+) -> Result<Json<DataResponse<Member>>, AppError> {
+    ensure_not_owner_role(payload.role)?;
+
+    let mut client = state.db_pool.get().await?;
+    let transaction = client.transaction().await?;
+
+    let before = find_member(&transaction, project_id, user_id).await?;
+    if matches!(before.role, ProjectRole::Owner) {
+        return Err(AppError::Conflict(
+            "Transfer ownership before changing the owner's role".to_string(),
+        ));
+    }
+
+    transaction
+        .execute(
+            "UPDATE project_owners SET role = $3 WHERE project_id = $1 AND user_id = $2",
+            &[&project_id, &user_id, &payload.role],
+        )
+        .await?;
+
+    let member = find_member(&transaction, project_id, user_id).await?;
+
+    audit::record(
+        &transaction,
+        AuditEvent::new(&auth_user, "member.updated", "member", user_id)
+            .project(project_id)
+            .before(&before)
+            .after(&member),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(DataResponse::new().data(member).build()))
+}
+
+/// Remove a member from a project
+#[utoipa::path(
+    delete,
+    path = "/v1/projects/{project_id}/members/{user_id}",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+        ("user_id" = Uuid, Path, description = "User ID"),
+    ),
+    responses(
+        (status = 200, description = "Member removed", body = DataResponse<Member>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "Forbidden", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "Member not found", body = DataResponse<serde_json::Value>),
+        (status = 409, description = "Member is the owner", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Members"
+)]
+async fn remove_member(
+    State(state): State<AppState>,
+    ProjectMember(auth_user, _): ProjectMember<require::ProjectManage>,
+    Path((project_id, user_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<DataResponse<Member>>, AppError> {
+    let mut client = state.db_pool.get().await?;
+    let transaction = client.transaction().await?;
+
+    let member = find_member(&transaction, project_id, user_id).await?;
+    if matches!(member.role, ProjectRole::Owner) {
+        return Err(AppError::Conflict(
+            "Transfer ownership before removing the owner".to_string(),
+        ));
+    }
+
+    transaction
+        .execute(
+            "DELETE FROM project_owners WHERE project_id = $1 AND user_id = $2",
+            &[&project_id, &user_id],
+        )
+        .await?;
+
+    audit::record(
+        &transaction,
+        AuditEvent::new(&auth_user, "member.removed", "member", user_id)
+            .project(project_id)
+            .before(&member),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(DataResponse::new().data(member).build()))
+}
+
+/// Make another member the owner of a project, the current owner becomes an editor
+#[utoipa::path(
+    post,
+    path = "/v1/projects/{project_id}/members/transfer",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+    ),
+    request_body = TransferOwnershipRequest,
+    responses(
+        (status = 200, description = "Ownership transferred", body = DataResponse<Vec<Member>>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "Forbidden", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "Member not found", body = DataResponse<serde_json::Value>),
+        (status = 409, description = "Member is already the owner", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Members"
+)]
+async fn transfer_ownership(
+    State(state): State<AppState>,
+    ProjectMember(auth_user, _): ProjectMember<require::ProjectManage>,
+    Path(project_id): Path<Uuid>,
+    WithRejection(Json(payload), _): WithRejection<Json<TransferOwnershipRequest>, AppError>,
+) -> Result<Json<DataResponse<Vec<Member>>>, AppError> {
+    let mut client = state.db_pool.get().await?;
+    let transaction = client.transaction().await?;
+
+    let new_owner = find_member(&transaction, project_id, payload.user_id).await?;
+    if matches!(new_owner.role, ProjectRole::Owner) {
+        return Err(AppError::Conflict(
+            "Member is already the owner".to_string(),
+        ));
+    }
+
+    // Demote first, only one owner is allowed at a time
+    let previous_owner = transaction
+        .query_opt(
+            "UPDATE project_owners SET role = 'editor'
+             WHERE project_id = $1 AND role = 'owner'
+             RETURNING user_id",
+            &[&project_id],
+        )
+        .await?
+        .map(|row| row.get::<_, Uuid>(0));
+
+    transaction
+        .execute(
+            "UPDATE project_owners SET role = 'owner' WHERE project_id = $1 AND user_id = $2",
+            &[&project_id, &payload.user_id],
+        )
+        .await?;
+
+    let mut members = vec![find_member(&transaction, project_id, payload.user_id).await?];
+    if let Some(previous_owner) = previous_owner {
+        members.push(find_member(&transaction, project_id, previous_owner).await?);
+    }
+
+    audit::record(
+        &transaction,
+        AuditEvent::new(
+            &auth_user,
+            "member.ownership_transferred",
+            "member",
+            payload.user_id,
+        )
+        .project(project_id)
+        .before(&serde_json::json!({ "owner": previous_owner }))
+        .after(&serde_json::json!({ "owner": payload.user_id })),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(DataResponse::new().data(members).build()))
+}
+
+pub fn router() -> Router<AppState> {
+    let member_routes = Router::new()
+        .route("/", axum::routing::get(list_members).post(add_member))
+        .route("/transfer", axum::routing::post(transfer_ownership))
+        .route(
+            "/{user_id}",
+            axum::routing::patch(update_member).delete(remove_member),
+        );
+
+    Router::new().nest("/v1/projects/{project_id}/members", member_routes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ensure_not_owner_role() {
+        assert!(ensure_not_owner_role(ProjectRole::Editor).is_ok());
+        assert!(ensure_not_owner_role(ProjectRole::Viewer).is_ok());
+        assert!(matches!(
+            ensure_not_owner_role(ProjectRole::Owner),
+            Err(AppError::UnprocessableEntity(_))
+        ));
+    }
+}
@@ -0,0 +1,476 @@
+use crate::http::auth::{SessionResponse, session_response, user_agent};
+use crate::http::users::parse_user;
+use crate::pkg::audit::{self, Actor, AuditEvent};
+use crate::pkg::auth::AuthUser;
+use crate::pkg::error::AppError;
+use crate::pkg::jwt::{API_AUDIENCE, TokenType};
+use crate::pkg::lockout;
+use crate::pkg::mfa::{self, SecondFactor};
+use crate::pkg::rate_limit;
+use crate::pkg::response::DataResponse;
+use crate::pkg::sessions;
+use crate::pkg::state::AppState;
+use axum::{Json, Router, extract::State, http::HeaderMap};
+use axum_extra::extract::WithRejection;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(utoipa::OpenApi)]
+#[openapi(
+    paths(
+        get_status,
+        enroll_totp,
+        confirm_totp,
+        disable_totp,
+        regenerate_recovery_codes,
+        verify_challenge,
+    ),
+    components(
+        schemas(
+            MfaStatus,
+            TotpEnrollment,
+            MfaCodeRequest,
+            RecoveryCodes,
+            MfaVerifyRequest,
+        ),
+    ),
+    tags(
+        (name = "MFA", description = "Multi-factor authentication endpoints"),
+    ),
+)]
+#[allow(dead_code)]
+pub struct MfaApi;
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct MfaStatus {
+    pub enabled: bool,
+    pub recovery_codes_remaining: i64,
+    /// Whether the user's org requires every member to use MFA
+    pub required_by_org: bool,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct TotpEnrollment {
+    /// Base32 secret, for authenticator apps that cannot scan the QR code
+    pub secret: String,
+    /// `otpauth://` URI to show as a QR code
+    pub provisioning_uri: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct MfaCodeRequest {
+    /// A code from the authenticator app, or a recovery code where accepted
+    pub code: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct RecoveryCodes {
+    /// One-time codes to sign in with when the authenticator is lost, only returned once
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct MfaVerifyRequest {
+    /// Token returned by a login that needs a second factor
+    pub challenge_token: String,
+    /// A code from the authenticator app or a recovery code
+    pub code: String,
+}
+
+/// Check a code of an enrolled user, failing with `error` when it is wrong
+async fn require_code(
+    client: &impl deadpool_postgres::GenericClient,
+    user_id: Uuid,
+    code: &str,
+    error: AppError,
+) -> Result<SecondFactor, AppError> {
+    mfa::verify_code(client, user_id, code).await?.ok_or(error)
+}
+
+fn invalid_code() -> AppError {
+    AppError::BadRequest("Invalid code".to_string())
+}
+
+/// Get the current user's MFA status
+#[utoipa::path(
+    get,
+    path = "/v1/auth/mfa",
+    responses(
+        (status = 200, description = "MFA status", body = DataResponse<MfaStatus>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "MFA"
+)]
+async fn get_status(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<DataResponse<MfaStatus>>, AppError> {
+    let client = state.db_pool.get().await?;
+
+    let required_by_org: bool = client
+        .query_one(
+            "SELECT require_mfa FROM orgs WHERE id = $1",
+            &[&auth_user.org_id],
+        )
+        .await?
+        .get(0);
+
+    Ok(Json(
+        DataResponse::new()
+            .data(MfaStatus {
+                enabled: mfa::is_enabled(&client, auth_user.id).await?,
+                recovery_codes_remaining: mfa::remaining_recovery_codes(&client, auth_user.id)
+                    .await?,
+                required_by_org,
+            })
+            .build(),
+    ))
+}
+
+/// Start enrolling an authenticator app. MFA is only enabled once a code is confirmed,
+/// enrolling again before that replaces the secret.
+#[utoipa::path(
+    post,
+    path = "/v1/auth/mfa/totp/enroll",
+    responses(
+        (status = 200, description = "Secret to add to an authenticator app", body = DataResponse<TotpEnrollment>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 409, description = "MFA is already enabled", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "MFA"
+)]
+async fn enroll_totp(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<DataResponse<TotpEnrollment>>, AppError> {
+    let client = state.db_pool.get().await?;
+
+    if mfa::is_enabled(&client, auth_user.id).await? {
+        return Err(AppError::Conflict("MFA is already enabled".to_string()));
+    }
+
+    let email: String = client
+        .query_one("SELECT email FROM users WHERE id = $1", &[&auth_user.id])
+        .await?
+        .get(0);
+
+    let secret = mfa::generate_secret()?;
+    let provisioning_uri = mfa::provisioning_uri(&secret, &email)?;
+
+    client
+        .execute(
+            "INSERT INTO user_mfa (user_id, totp_secret) VALUES ($1, $2)
+             ON CONFLICT (user_id) DO UPDATE
+             SET totp_secret = EXCLUDED.totp_secret, last_used_step = NULL,
+                 created_at = current_timestamp",
+            &[&auth_user.id, &secret],
+        )
+        .await?;
+
+    Ok(Json(
+        DataResponse::new()
+            .data(TotpEnrollment {
+                secret,
+                provisioning_uri,
+            })
+            .build(),
+    ))
+}
+
+/// Confirm the enrollment with a code from the authenticator app, enabling MFA.
+/// Returns the recovery codes, they are not shown again.
+#[utoipa::path(
+    post,
+    path = "/v1/auth/mfa/totp/confirm",
+    request_body = MfaCodeRequest,
+    responses(
+        (status = 200, description = "MFA enabled", body = DataResponse<RecoveryCodes>),
+        (status = 400, description = "Invalid code or no pending enrollment", body = DataResponse<serde_json::Value>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 409, description = "MFA is already enabled", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "MFA"
+)]
+async fn confirm_totp(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    WithRejection(Json(payload), _): WithRejection<Json<MfaCodeRequest>, AppError>,
+) -> Result<Json<DataResponse<RecoveryCodes>>, AppError> {
+    let mut client = state.db_pool.get().await?;
+    let transaction = client.transaction().await?;
+
+    let enabled = transaction
+        .query_opt(
+            "SELECT enabled_at IS NOT NULL FROM user_mfa WHERE user_id = $1 FOR UPDATE",
+            &[&auth_user.id],
+        )
+        .await?
+        .ok_or(AppError::BadRequest("Enroll first".to_string()))?
+        .get::<_, bool>(0);
+
+    if enabled {
+        return Err(AppError::Conflict("MFA is already enabled".to_string()));
+    }
+
+    require_code(&transaction, auth_user.id, &payload.code, invalid_code()).await?;
+
+    transaction
+        .execute(
+            "UPDATE user_mfa SET enabled_at = current_timestamp WHERE user_id = $1",
+            &[&auth_user.id],
+        )
+        .await?;
+    let recovery_codes = mfa::replace_recovery_codes(&transaction, auth_user.id).await?;
+
+    audit::record(
+        &transaction,
+        AuditEvent::new(&auth_user, "user.mfa_enabled", "user", auth_user.id)
+            .after(&serde_json::json!({ "method": "totp" })),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(
+        DataResponse::new()
+            .data(RecoveryCodes { recovery_codes })
+            .build(),
+    ))
+}
+
+/// Turn MFA off with a current code. Not allowed when the user's org requires MFA.
+#[utoipa::path(
+    post,
+    path = "/v1/auth/mfa/totp/disable",
+    request_body = MfaCodeRequest,
+    responses(
+        (status = 200, description = "MFA disabled", body = DataResponse<MfaStatus>),
+        (status = 400, description = "Invalid code or MFA not enabled", body = DataResponse<serde_json::Value>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "The organization requires MFA", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "MFA"
+)]
+async fn disable_totp(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    WithRejection(Json(payload), _): WithRejection<Json<MfaCodeRequest>, AppError>,
+) -> Result<Json<DataResponse<MfaStatus>>, AppError> {
+    let mut client = state.db_pool.get().await?;
+
+    let required_by_org: bool = client
+        .query_one(
+            "SELECT require_mfa FROM orgs WHERE id = $1",
+            &[&auth_user.org_id],
+        )
+        .await?
+        .get(0);
+
+    if required_by_org {
+        return Err(AppError::Forbidden(
+            "Your organization requires multi-factor authentication".to_string(),
+        ));
+    }
+
+    if !mfa::is_enabled(&client, auth_user.id).await? {
+        return Err(AppError::BadRequest("MFA is not enabled".to_string()));
+    }
+
+    let transaction = client.transaction().await?;
+
+    require_code(&transaction, auth_user.id, &payload.code, invalid_code()).await?;
+
+    transaction
+        .execute("DELETE FROM user_mfa WHERE user_id = $1", &[&auth_user.id])
+        .await?;
+    transaction
+        .execute(
+            "DELETE FROM mfa_recovery_codes WHERE user_id = $1",
+            &[&auth_user.id],
+        )
+        .await?;
+
+    audit::record(
+        &transaction,
+        AuditEvent::new(&auth_user, "user.mfa_disabled", "user", auth_user.id)
+            .before(&serde_json::json!({ "method": "totp" })),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(
+        DataResponse::new()
+            .data(MfaStatus {
+                enabled: false,
+                recovery_codes_remaining: 0,
+                required_by_org,
+            })
+            .build(),
+    ))
+}
+
+/// Replace the recovery codes with new ones, invalidating the old ones
+#[utoipa::path(
+    post,
+    path = "/v1/auth/mfa/recovery-codes",
+    request_body = MfaCodeRequest,
+    responses(
+        (status = 200, description = "New recovery codes", body = DataResponse<RecoveryCodes>),
+        (status = 400, description = "Invalid code or MFA not enabled", body = DataResponse<serde_json::Value>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "MFA"
+)]
+async fn regenerate_recovery_codes(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    WithRejection(Json(payload), _): WithRejection<Json<MfaCodeRequest>, AppError>,
+) -> Result<Json<DataResponse<RecoveryCodes>>, AppError> {
+    let mut client = state.db_pool.get().await?;
+
+    if !mfa::is_enabled(&client, auth_user.id).await? {
+        return Err(AppError::BadRequest("MFA is not enabled".to_string()));
+    }
+
+    let transaction = client.transaction().await?;
+
+    require_code(&transaction, auth_user.id, &payload.code, invalid_code()).await?;
+    let recovery_codes = mfa::replace_recovery_codes(&transaction, auth_user.id).await?;
+
+    audit::record(
+        &transaction,
+        AuditEvent::new(
+            &auth_user,
+            "user.mfa_recovery_codes_regenerated",
+            "user",
+            auth_user.id,
+        ),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(
+        DataResponse::new()
+            .data(RecoveryCodes { recovery_codes })
+            .build(),
+    ))
+}
+
+/// Exchange the challenge token of a login and a second factor for a session
+#[utoipa::path(
+    post,
+    path = "/v1/auth/mfa/verify",
+    request_body = MfaVerifyRequest,
+    responses(
+        (status = 200, description = "Second factor verified", body = DataResponse<crate::http::auth::AuthResponse>),
+        (status = 401, description = "Invalid or expired challenge, invalid code, or too many invalid codes for the challenge", body = DataResponse<serde_json::Value>),
+        (status = 429, description = "Too many attempts or the account is temporarily locked", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "MFA"
+)]
+async fn verify_challenge(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    WithRejection(Json(payload), _): WithRejection<Json<MfaVerifyRequest>, AppError>,
+) -> Result<SessionResponse, AppError> {
+    let claims =
+        state
+            .jwt
+            .validate_token(&payload.challenge_token, TokenType::Mfa, API_AUDIENCE)?;
+    let user_id = Uuid::parse_str(&claims.sub)
+        .map_err(|_| AppError::Unauthorized("Invalid challenge token".to_string()))?;
+
+    // Without Redis wrong codes are still counted against the account below
+    match mfa::challenge_failures(&state.redis_pool, &claims.jti).await {
+        Ok(failures) if failures >= mfa::MAX_CHALLENGE_FAILURES => {
+            return Err(AppError::Unauthorized(
+                "Too many invalid codes, sign in again".to_string(),
+            ));
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("Failed to read MFA challenge failures: {}", e),
+    }
+
+    let mut client = state.db_pool.get().await?;
+
+    let row = client
+        .query_opt("SELECT * FROM users WHERE id = $1", &[&user_id])
+        .await?
+        .ok_or(AppError::Unauthorized(
+            "Invalid challenge token".to_string(),
+        ))?;
+    let user = parse_user(&row)?;
+
+    lockout::check(&user)?;
+
+    let transaction = client.transaction().await?;
+
+    let factor = match mfa::verify_code(&transaction, user_id, &payload.code).await? {
+        Some(factor) => factor,
+        None => {
+            // Wrong codes lock the account the same way wrong passwords do
+            drop(transaction);
+            lockout::record_failure(&client, &state.config, user_id).await?;
+            if let Err(e) = mfa::record_challenge_failure(&state.redis_pool, &claims.jti).await {
+                eprintln!("Failed to count MFA challenge failure: {}", e);
+            }
+            return Err(AppError::Unauthorized("Invalid code".to_string()));
+        }
+    };
+
+    lockout::reset(&transaction, &user).await?;
+
+    // Recorded so lost authenticators show up in the audit log
+    if factor == SecondFactor::RecoveryCode {
+        let remaining = mfa::remaining_recovery_codes(&transaction, user_id).await?;
+        audit::record(
+            &transaction,
+            AuditEvent::new(
+                Actor::User(user_id),
+                "user.mfa_recovery_code_used",
+                "user",
+                user_id,
+            )
+            .after(&serde_json::json!({ "recovery_codes_remaining": remaining })),
+        )
+        .await?;
+    }
+
+    let tokens = sessions::create(
+        &transaction,
+        &state.jwt,
+        user_id,
+        claims.refresh_typ.unwrap_or(TokenType::Refresh),
+        user_agent(&headers),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(session_response(tokens))
+}
+
+pub fn router(state: &AppState) -> Router<AppState> {
+    let verify_routes = Router::new()
+        .route("/verify", axum::routing::post(verify_challenge))
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            rate_limit::limit,
+        ));
+
+    let mfa_routes = Router::new()
+        .merge(verify_routes)
+        .route("/", axum::routing::get(get_status))
+        .route("/totp/enroll", axum::routing::post(enroll_totp))
+        .route("/totp/confirm", axum::routing::post(confirm_totp))
+        .route("/totp/disable", axum::routing::post(disable_totp))
+        .route(
+            "/recovery-codes",
+            axum::routing::post(regenerate_recovery_codes),
+        );
+
+    Router::new().nest("/v1/auth/mfa", mfa_routes)
+}
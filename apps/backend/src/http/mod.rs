@@ -1,5 +1,19 @@
+mod api_keys;
+mod audit;
 mod auth;
+mod environments;
+mod flags;
 mod health;
+mod members;
+mod mfa;
+mod oidc;
+mod orgs;
+mod projects;
+mod sdk;
+#[cfg(test)]
+mod tests;
+mod users;
+mod variants;
 
 use crate::pkg::state::AppState;
 use axum::Router;
@@ -15,8 +29,21 @@ use utoipa_swagger_ui::SwaggerUi;
 ))]
 pub struct ApiDoc;
 
-fn api_router() -> Router<AppState> {
-    Router::new().merge(auth::router())
+fn api_router(state: &AppState) -> Router<AppState> {
+    Router::new()
+        .merge(auth::router(state))
+        .merge(mfa::router(state))
+        .merge(oidc::router())
+        .merge(flags::router())
+        .merge(sdk::router())
+        .merge(api_keys::router())
+        .merge(variants::router())
+        .merge(audit::router())
+        .merge(members::router())
+        .merge(orgs::router())
+        .merge(projects::router())
+        .merge(environments::router())
+        .merge(users::router())
 }
 
 pub fn router(state: AppState) -> Router {
@@ -25,12 +52,25 @@ pub fn router(state: AppState) -> Router {
 
     let router = Router::new()
         .merge(health::router())
-        .nest("/api", api_router())
+        .merge(auth::well_known_router())
+        .nest("/api", api_router(&state))
         .layer(cors)
         .with_state(state);
 
     let mut openapi = ApiDoc::openapi();
     openapi.merge(auth::AuthApi::openapi());
+    openapi.merge(mfa::MfaApi::openapi());
+    openapi.merge(oidc::OidcApi::openapi());
+    openapi.merge(flags::FlagsApi::openapi());
+    openapi.merge(sdk::SdkApi::openapi());
+    openapi.merge(api_keys::ApiKeysApi::openapi());
+    openapi.merge(variants::VariantsApi::openapi());
+    openapi.merge(audit::AuditApi::openapi());
+    openapi.merge(members::MembersApi::openapi());
+    openapi.merge(orgs::OrgsApi::openapi());
+    openapi.merge(projects::ProjectsApi::openapi());
+    openapi.merge(environments::EnvironmentsApi::openapi());
+    openapi.merge(users::UsersApi::openapi());
     openapi.merge(health::HealthApi::openapi());
 
     router.merge(SwaggerUi::new("/swagger-ui").url("/api-docs/openapi.json", openapi))
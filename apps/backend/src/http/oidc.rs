@@ -0,0 +1,315 @@
+use crate::http::auth::{hash_password, sign_in};
+use crate::http::orgs;
+use crate::models::db::UserIdentities;
+use crate::models::enums::UserRole;
+use crate::pkg::audit::{self, Actor, AuditEvent};
+use crate::pkg::auth::{generate_token, hash_token};
+use crate::pkg::error::AppError;
+use crate::pkg::jwt::TokenType;
+use crate::pkg::oidc::{IdTokenClaims, OidcProvider};
+use crate::pkg::registration;
+use crate::pkg::response::DataResponse;
+use crate::pkg::state::AppState;
+use axum::{Json, Router, extract::State, http::HeaderMap, response::Response};
+use axum_extra::extract::WithRejection;
+use pgmap::FromRow;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(utoipa::OpenApi)]
+#[openapi(
+    paths(
+        authorize,
+        callback,
+    ),
+    components(
+        schemas(
+            OidcAuthorization,
+            OidcCallbackRequest,
+        ),
+    ),
+    tags(
+        (name = "Single Sign-On", description = "Sign in through the OIDC identity provider"),
+    ),
+)]
+#[allow(dead_code)]
+pub struct OidcApi;
+
+/// How long a user has to complete signing in at the identity provider
+const LOGIN_TTL_MINUTES: i64 = 10;
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct OidcAuthorization {
+    /// Send the user here, the provider redirects back to the configured redirect URL
+    pub authorization_url: String,
+}
+
+/// Query parameters the identity provider redirected back with
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct OidcCallbackRequest {
+    pub code: String,
+    pub state: String,
+}
+
+fn provider(state: &AppState) -> Result<&Arc<OidcProvider>, AppError> {
+    state.oidc.as_ref().ok_or(AppError::NotFound(
+        "Single sign-on is not configured".to_string(),
+    ))
+}
+
+/// Start signing in at the identity provider
+#[utoipa::path(
+    get,
+    path = "/v1/auth/oidc/authorize",
+    responses(
+        (status = 200, description = "Authorization URL to send the user to", body = DataResponse<OidcAuthorization>),
+        (status = 404, description = "Single sign-on is not configured", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Single Sign-On"
+)]
+async fn authorize(
+    State(state): State<AppState>,
+) -> Result<Json<DataResponse<OidcAuthorization>>, AppError> {
+    let provider = provider(&state)?;
+
+    let login_state = generate_token()?;
+    let nonce = generate_token()?;
+    let code_verifier = generate_token()?;
+    let authorization_url = provider
+        .authorization_url(&login_state, &nonce, &code_verifier)
+        .await?;
+
+    let client = state.db_pool.get().await?;
+    client
+        .execute(
+            "DELETE FROM oidc_logins WHERE expires_at < current_timestamp",
+            &[],
+        )
+        .await?;
+    client
+        .execute(
+            "INSERT INTO oidc_logins (state_hash, nonce, code_verifier, expires_at)
+             VALUES ($1, $2, $3, $4)",
+            &[
+                &hash_token(&login_state),
+                &nonce,
+                &code_verifier,
+                &(chrono::Utc::now() + chrono::Duration::minutes(LOGIN_TTL_MINUTES)),
+            ],
+        )
+        .await?;
+
+    Ok(Json(
+        DataResponse::new()
+            .data(OidcAuthorization { authorization_url })
+            .build(),
+    ))
+}
+
+fn parse_identity(row: &tokio_postgres::Row) -> Result<UserIdentities, AppError> {
+    UserIdentities::from_row(row)
+        .map_err(|_| AppError::InternalError("Failed to parse identity data".to_string()))
+}
+
+/// The user an identity belongs to, linking it to the account with its verified email
+/// or creating an account for it
+async fn resolve_user(
+    client: &impl deadpool_postgres::GenericClient,
+    state: &AppState,
+    issuer: &str,
+    claims: &IdTokenClaims,
+) -> Result<Uuid, AppError> {
+    let linked = client
+        .query_opt(
+            "UPDATE user_identities SET last_login_at = current_timestamp, email = $3
+             WHERE issuer = $1 AND subject = $2
+             RETURNING *",
+            &[&issuer, &claims.sub, &claims.email],
+        )
+        .await?;
+    if let Some(row) = linked {
+        return Ok(parse_identity(&row)?.user_id);
+    }
+
+    let email = claims.email.as_deref().ok_or(AppError::Unauthorized(
+        "The identity provider did not share an email address".to_string(),
+    ))?;
+    let existing = client
+        .query_opt("SELECT id FROM users WHERE email = $1", &[&email])
+        .await?;
+
+    let user_id = match existing {
+        // Only the provider vouching for the address proves it is the same person
+        Some(_) if !claims.email_verified => {
+            return Err(AppError::Conflict(
+                "An account with this email already exists and the identity provider has not verified the email".to_string(),
+            ));
+        }
+        Some(row) => {
+            let user_id: Uuid = row.get(0);
+            registration::mark_verified(client, user_id).await?;
+            audit::record(
+                client,
+                AuditEvent::new(
+                    Actor::User(user_id),
+                    "user.identity_linked",
+                    "user",
+                    user_id,
+                )
+                .after(&serde_json::json!({ "issuer": issuer, "subject": claims.sub })),
+            )
+            .await?;
+            user_id
+        }
+        None => create_user(client, state, issuer, claims, email).await?,
+    };
+
+    let row = client
+        .query_one(
+            "INSERT INTO user_identities (user_id, issuer, subject, email, last_login_at)
+             VALUES ($1, $2, $3, $4, current_timestamp)
+             RETURNING *",
+            &[&user_id, &issuer, &claims.sub, &claims.email],
+        )
+        .await?;
+
+    Ok(parse_identity(&row)?.user_id)
+}
+
+/// Provision a user signing in for the first time, in the configured org or an org of their own
+async fn create_user(
+    client: &impl deadpool_postgres::GenericClient,
+    state: &AppState,
+    issuer: &str,
+    claims: &IdTokenClaims,
+    email: &str,
+) -> Result<Uuid, AppError> {
+    let user_id = Uuid::new_v4();
+
+    let (org_id, role, org) = match &state.config.oidc_org {
+        Some(slug) => {
+            let org_id: Uuid = client
+                .query_opt("SELECT id FROM orgs WHERE slug = $1", &[slug])
+                .await?
+                .ok_or(AppError::InternalError(format!(
+                    "OIDC_ORG {} does not exist",
+                    slug
+                )))?
+                .get(0);
+            (org_id, UserRole::Viewer, None)
+        }
+        None => {
+            if !registration::may_create_org(client, &state.config).await? {
+                return Err(AppError::Forbidden(
+                    "Sign ups need an invitation or an organization to join".to_string(),
+                ));
+            }
+            let org = orgs::create_org(client, email).await?;
+            (org.id, UserRole::Admin, Some(org))
+        }
+    };
+
+    // Nobody knows the password, the user signs in through the provider or resets it
+    let password_hash = hash_password(state, &generate_token()?)?;
+
+    client
+        .execute(
+            "INSERT INTO users (id, email, password_hash, role, org_id, email_verified_at)
+             VALUES ($1, $2, $3, $4, $5, CASE WHEN $6 THEN current_timestamp END)",
+            &[
+                &user_id,
+                &email,
+                &password_hash,
+                &role,
+                &org_id,
+                &claims.email_verified,
+            ],
+        )
+        .await?;
+
+    if let Some(org) = &org {
+        audit::record(
+            client,
+            AuditEvent::new(Actor::User(user_id), "org.created", "org", org.id).after(org),
+        )
+        .await?;
+    }
+
+    audit::record(
+        client,
+        AuditEvent::new(Actor::User(user_id), "user.registered", "user", user_id).after(
+            &serde_json::json!({
+                "id": user_id,
+                "email": email,
+                "role": role,
+                "org_id": org_id,
+                "issuer": issuer,
+                "subject": claims.sub,
+            }),
+        ),
+    )
+    .await?;
+
+    Ok(user_id)
+}
+
+/// Finish signing in with the code and state the identity provider redirected back with.
+/// Users are matched by their identity, linked by a verified email or created on first sign in.
+#[utoipa::path(
+    post,
+    path = "/v1/auth/oidc/callback",
+    request_body = OidcCallbackRequest,
+    responses(
+        (status = 200, description = "Signed in", body = DataResponse<crate::http::auth::AuthResponse>),
+        (status = 202, description = "Signed in at the provider, a second factor is required", body = DataResponse<crate::http::auth::MfaChallengeResponse>),
+        (status = 400, description = "Invalid or expired state", body = DataResponse<serde_json::Value>),
+        (status = 401, description = "The provider rejected the code or returned an invalid ID token", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "New accounts may not start an organization, or a password reset is required", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "Single sign-on is not configured", body = DataResponse<serde_json::Value>),
+        (status = 409, description = "The email belongs to another account and is not verified", body = DataResponse<serde_json::Value>),
+        (status = 429, description = "The account is temporarily locked", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Single Sign-On"
+)]
+async fn callback(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    WithRejection(Json(payload), _): WithRejection<Json<OidcCallbackRequest>, AppError>,
+) -> Result<Response, AppError> {
+    let provider = provider(&state)?;
+    let mut client = state.db_pool.get().await?;
+
+    // Each login can be completed once
+    let login = client
+        .query_opt(
+            "DELETE FROM oidc_logins
+             WHERE state_hash = $1 AND expires_at > current_timestamp
+             RETURNING nonce, code_verifier",
+            &[&hash_token(&payload.state)],
+        )
+        .await?
+        .ok_or(AppError::BadRequest(
+            "Invalid or expired sign in state".to_string(),
+        ))?;
+    let (nonce, code_verifier): (String, String) = (login.get(0), login.get(1));
+
+    let claims = provider
+        .authenticate(&payload.code, &code_verifier, &nonce)
+        .await?;
+
+    let transaction = client.transaction().await?;
+    let user_id = resolve_user(&transaction, &state, provider.issuer(), &claims).await?;
+    transaction.commit().await?;
+
+    sign_in(&client, &state, user_id, TokenType::Refresh, &headers).await
+}
+
+pub fn router() -> Router<AppState> {
+    let oidc_routes = Router::new()
+        .route("/authorize", axum::routing::get(authorize))
+        .route("/callback", axum::routing::post(callback));
+
+    Router::new().nest("/v1/auth/oidc", oidc_routes)
+}
@@ -0,0 +1,644 @@
+use crate::models::db::{OrgInvitations, Orgs};
+use crate::models::enums::{RegistrationMode, UserRole};
+use crate::pkg::audit::{self, AuditEvent};
+use crate::pkg::auth::{self, OrgMember};
+use crate::pkg::emails::Template;
+use crate::pkg::error::AppError;
+use crate::pkg::mfa;
+use crate::pkg::permissions::require;
+use crate::pkg::registration;
+use crate::pkg::response::DataResponse;
+use crate::pkg::state::AppState;
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+};
+use axum_extra::extract::WithRejection;
+use chrono::{DateTime, Utc};
+use pgmap::FromRow;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(utoipa::OpenApi)]
+#[openapi(
+    paths(
+        get_org,
+        update_org,
+        delete_org,
+        list_invitations,
+        create_invitation,
+        revoke_invitation,
+    ),
+    components(
+        schemas(
+            Orgs,
+            UpdateOrgRequest,
+            CreateInvitationRequest,
+            InvitationSummary,
+            CreatedInvitation,
+        ),
+    ),
+    tags(
+        (name = "Organizations", description = "Organization settings and invitations"),
+    ),
+)]
+#[allow(dead_code)]
+pub struct OrgsApi;
+
+/// Region new orgs are placed in
+const DEFAULT_REGION: &str = "us-east-1";
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct UpdateOrgRequest {
+    pub name: Option<String>,
+    /// Lowercase letters, digits and dashes
+    pub slug: Option<String>,
+    pub region: Option<String>,
+    /// Require every member to sign in with a second factor, members who have not
+    /// enrolled can only enroll until they do
+    pub require_mfa: Option<bool>,
+    /// Who may join by signing up without an invitation
+    pub registration_mode: Option<RegistrationMode>,
+    /// Email domains that may join when the registration mode is `AllowedDomains`
+    pub allowed_email_domains: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CreateInvitationRequest {
+    pub email: String,
+    /// Role the user gets in the org once they accept
+    #[serde(default = "default_invitation_role")]
+    pub role: UserRole,
+}
+
+fn default_invitation_role() -> UserRole {
+    UserRole::Viewer
+}
+
+/// An invitation as shown in listings, never includes the token or its hash
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct InvitationSummary {
+    pub id: Uuid,
+    pub org_id: Uuid,
+    pub email: String,
+    pub role: UserRole,
+    pub invited_by: Option<Uuid>,
+    pub expires_at: DateTime<Utc>,
+    pub accepted_at: Option<DateTime<Utc>>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl From<OrgInvitations> for InvitationSummary {
+    fn from(invitation: OrgInvitations) -> Self {
+        InvitationSummary {
+            id: invitation.id,
+            org_id: invitation.org_id,
+            email: invitation.email,
+            role: invitation.role,
+            invited_by: invitation.invited_by,
+            expires_at: invitation.expires_at,
+            accepted_at: invitation.accepted_at,
+            created_at: invitation.created_at,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CreatedInvitation {
+    /// Token to register with, only returned once at creation time
+    pub token: String,
+    pub invitation: InvitationSummary,
+}
+
+fn parse_org(row: &tokio_postgres::Row) -> Result<Orgs, AppError> {
+    Orgs::from_row(row)
+        .map_err(|_| AppError::InternalError("Failed to parse organization data".to_string()))
+}
+
+fn parse_invitation(row: &tokio_postgres::Row) -> Result<OrgInvitations, AppError> {
+    OrgInvitations::from_row(row)
+        .map_err(|_| AppError::InternalError("Failed to parse invitation data".to_string()))
+}
+
+/// Turn a name into a slug, e.g. `Acme Corp.` into `acme-corp`
+pub(crate) fn slugify(name: &str) -> String {
+    let mut slug = String::new();
+    for c in name.chars().flat_map(char::to_lowercase) {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    slug.trim_end_matches('-').to_string()
+}
+
+fn validate_slug(slug: &str) -> Result<(), AppError> {
+    if slug.is_empty() || slug.len() > 100 {
+        return Err(AppError::UnprocessableEntity(
+            "slug: must be between 1 and 100 characters".to_string(),
+        ));
+    }
+
+    if slugify(slug) != slug {
+        return Err(AppError::UnprocessableEntity(
+            "slug: only lowercase letters, digits and single dashes are allowed".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+fn validate_name(name: &str) -> Result<(), AppError> {
+    if name.trim().is_empty() || name.len() > 255 {
+        return Err(AppError::UnprocessableEntity(
+            "name: must be between 1 and 255 characters".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Create an org for a user signing up without an invitation.
+/// The slug gets a random suffix so similar names never collide.
+pub(crate) async fn create_org(
+    client: &impl deadpool_postgres::GenericClient,
+    name: &str,
+) -> Result<Orgs, AppError> {
+    validate_name(name)?;
+
+    let existing = client
+        .query_opt("SELECT id FROM orgs WHERE name = $1", &[&name])
+        .await?;
+    if existing.is_some() {
+        return Err(AppError::Conflict(
+            "Organization name already exists".to_string(),
+        ));
+    }
+
+    let suffix = &Uuid::new_v4().simple().to_string()[..6];
+    let base = slugify(name);
+    let base = if base.is_empty() {
+        "org"
+    } else {
+        &base[..base.len().min(90)]
+    };
+    let slug = format!("{}-{}", base.trim_end_matches('-'), suffix);
+
+    let row = client
+        .query_one(
+            "INSERT INTO orgs (name, slug, region) VALUES ($1, $2, $3) RETURNING *",
+            &[&name, &slug, &DEFAULT_REGION],
+        )
+        .await?;
+
+    parse_org(&row)
+}
+
+/// Redeem an invitation for `email`, returning it so the new user joins its org with its role
+pub(crate) async fn accept_invitation(
+    client: &impl deadpool_postgres::GenericClient,
+    token: &str,
+    email: &str,
+) -> Result<InvitationSummary, AppError> {
+    let row = client
+        .query_opt(
+            "UPDATE org_invitations SET accepted_at = current_timestamp
+             WHERE token_hash = $1
+               AND lower(email) = lower($2)
+               AND accepted_at IS NULL
+               AND expires_at > current_timestamp
+             RETURNING *",
+            &[&auth::hash_token(token), &email],
+        )
+        .await?
+        .ok_or(AppError::BadRequest(
+            "Invalid or expired invitation".to_string(),
+        ))?;
+
+    Ok(InvitationSummary::from(parse_invitation(&row)?))
+}
+
+/// Get an organization
+#[utoipa::path(
+    get,
+    path = "/v1/orgs/{org_id}",
+    params(
+        ("org_id" = Uuid, Path, description = "Organization ID"),
+    ),
+    responses(
+        (status = 200, description = "Organization", body = DataResponse<Orgs>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "Organization not found", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Organizations"
+)]
+async fn get_org(
+    State(state): State<AppState>,
+    OrgMember(_auth_user, _): OrgMember<require::OrgRead>,
+    Path(org_id): Path<Uuid>,
+) -> Result<Json<DataResponse<Orgs>>, AppError> {
+    let client = state.db_pool.get().await?;
+
+    let row = client
+        .query_opt("SELECT * FROM orgs WHERE id = $1", &[&org_id])
+        .await?
+        .ok_or(AppError::NotFound("Organization not found".to_string()))?;
+
+    Ok(Json(DataResponse::new().data(parse_org(&row)?).build()))
+}
+
+/// Rename an organization or move it to another region
+#[utoipa::path(
+    patch,
+    path = "/v1/orgs/{org_id}",
+    params(
+        ("org_id" = Uuid, Path, description = "Organization ID"),
+    ),
+    request_body = UpdateOrgRequest,
+    responses(
+        (status = 200, description = "Organization updated", body = DataResponse<Orgs>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "Forbidden", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "Organization not found", body = DataResponse<serde_json::Value>),
+        (status = 409, description = "Name or slug already exists", body = DataResponse<serde_json::Value>),
+        (status = 422, description = "Invalid organization", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Organizations"
+)]
+async fn update_org(
+    State(state): State<AppState>,
+    OrgMember(auth_user, _): OrgMember<require::OrgManage>,
+    Path(org_id): Path<Uuid>,
+    WithRejection(Json(payload), _): WithRejection<Json<UpdateOrgRequest>, AppError>,
+) -> Result<Json<DataResponse<Orgs>>, AppError> {
+    if let Some(name) = &payload.name {
+        validate_name(name)?;
+    }
+    if let Some(slug) = &payload.slug {
+        validate_slug(slug)?;
+    }
+    let allowed_email_domains = payload
+        .allowed_email_domains
+        .as_deref()
+        .map(registration::normalize_domains)
+        .transpose()?;
+
+    let mut client = state.db_pool.get().await?;
+
+    // Admins cannot lock themselves out
+    if payload.require_mfa == Some(true) && !mfa::is_enabled(&client, auth_user.id).await? {
+        return Err(AppError::UnprocessableEntity(
+            "Enroll in multi-factor authentication before requiring it".to_string(),
+        ));
+    }
+
+    let existing = client
+        .query_opt(
+            "SELECT id FROM orgs WHERE (name = $2 OR slug = $3) AND id <> $1",
+            &[&org_id, &payload.name, &payload.slug],
+        )
+        .await?;
+
+    if existing.is_some() {
+        return Err(AppError::Conflict(
+            "Organization name or slug already exists".to_string(),
+        ));
+    }
+
+    let transaction = client.transaction().await?;
+
+    let before = transaction
+        .query_opt("SELECT * FROM orgs WHERE id = $1 FOR UPDATE", &[&org_id])
+        .await?
+        .ok_or(AppError::NotFound("Organization not found".to_string()))?;
+    let before = parse_org(&before)?;
+
+    let row = transaction
+        .query_one(
+            "UPDATE orgs SET
+                name = COALESCE($2, name),
+                slug = COALESCE($3, slug),
+                region = COALESCE($4, region),
+                require_mfa = COALESCE($5, require_mfa),
+                registration_mode = COALESCE($6, registration_mode),
+                allowed_email_domains = COALESCE($7, allowed_email_domains),
+                updated_at = current_timestamp
+             WHERE id = $1
+             RETURNING *",
+            &[
+                &org_id,
+                &payload.name,
+                &payload.slug,
+                &payload.region,
+                &payload.require_mfa,
+                &payload.registration_mode,
+                &allowed_email_domains,
+            ],
+        )
+        .await?;
+    let org = parse_org(&row)?;
+
+    // Without domains the org would quietly be invite only, returning rolls the update back
+    if matches!(org.registration_mode, RegistrationMode::AllowedDomains)
+        && org
+            .allowed_email_domains
+            .as_array()
+            .is_none_or(|domains| domains.is_empty())
+    {
+        return Err(AppError::UnprocessableEntity(
+            "Allow at least one email domain to accept sign ups by domain".to_string(),
+        ));
+    }
+
+    audit::record(
+        &transaction,
+        AuditEvent::new(&auth_user, "org.updated", "org", org_id)
+            .before(&before)
+            .after(&org),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(DataResponse::new().data(org).build()))
+}
+
+/// Delete an organization with all of its users, projects, flags and keys
+#[utoipa::path(
+    delete,
+    path = "/v1/orgs/{org_id}",
+    params(
+        ("org_id" = Uuid, Path, description = "Organization ID"),
+    ),
+    responses(
+        (status = 200, description = "Organization deleted", body = DataResponse<Orgs>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "Forbidden", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "Organization not found", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Organizations"
+)]
+async fn delete_org(
+    State(state): State<AppState>,
+    OrgMember(auth_user, _): OrgMember<require::OrgManage>,
+    Path(org_id): Path<Uuid>,
+) -> Result<Json<DataResponse<Orgs>>, AppError> {
+    let mut client = state.db_pool.get().await?;
+    let transaction = client.transaction().await?;
+
+    let row = transaction
+        .query_opt("SELECT * FROM orgs WHERE id = $1 FOR UPDATE", &[&org_id])
+        .await?
+        .ok_or(AppError::NotFound("Organization not found".to_string()))?;
+    let org = parse_org(&row)?;
+
+    // Recorded first, the event is attributed through the acting user who is deleted with the org
+    audit::record(
+        &transaction,
+        AuditEvent::new(&auth_user, "org.deleted", "org", org_id).before(&org),
+    )
+    .await?;
+
+    transaction
+        .execute("DELETE FROM orgs WHERE id = $1", &[&org_id])
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(DataResponse::new().data(org).build()))
+}
+
+/// List the invitations of an organization, newest first
+#[utoipa::path(
+    get,
+    path = "/v1/orgs/{org_id}/invitations",
+    params(
+        ("org_id" = Uuid, Path, description = "Organization ID"),
+    ),
+    responses(
+        (status = 200, description = "Invitations", body = DataResponse<Vec<InvitationSummary>>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "Forbidden", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "Organization not found", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Organizations"
+)]
+async fn list_invitations(
+    State(state): State<AppState>,
+    OrgMember(_auth_user, _): OrgMember<require::UserManage>,
+    Path(org_id): Path<Uuid>,
+) -> Result<Json<DataResponse<Vec<InvitationSummary>>>, AppError> {
+    let client = state.db_pool.get().await?;
+
+    let rows = client
+        .query(
+            "SELECT * FROM org_invitations WHERE org_id = $1 ORDER BY created_at DESC",
+            &[&org_id],
+        )
+        .await?;
+
+    let invitations = rows
+        .iter()
+        .map(|row| parse_invitation(row).map(InvitationSummary::from))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Json(DataResponse::new().data(invitations).build()))
+}
+
+/// Invite someone into an organization, emailing them a link to sign up.
+/// The returned token is the only copy, it is stored hashed and cannot be shown again.
+#[utoipa::path(
+    post,
+    path = "/v1/orgs/{org_id}/invitations",
+    params(
+        ("org_id" = Uuid, Path, description = "Organization ID"),
+    ),
+    request_body = CreateInvitationRequest,
+    responses(
+        (status = 200, description = "Invitation created", body = DataResponse<CreatedInvitation>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "Forbidden", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "Organization not found", body = DataResponse<serde_json::Value>),
+        (status = 409, description = "User already exists", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Organizations"
+)]
+async fn create_invitation(
+    State(state): State<AppState>,
+    OrgMember(auth_user, _): OrgMember<require::UserManage>,
+    Path(org_id): Path<Uuid>,
+    WithRejection(Json(payload), _): WithRejection<Json<CreateInvitationRequest>, AppError>,
+) -> Result<Json<DataResponse<CreatedInvitation>>, AppError> {
+    let mut client = state.db_pool.get().await?;
+
+    // Users belong to a single org, so only new users can be invited
+    let existing = client
+        .query_opt(
+            "SELECT id FROM users WHERE lower(email) = lower($1)",
+            &[&payload.email],
+        )
+        .await?;
+
+    if existing.is_some() {
+        return Err(AppError::Conflict("User already exists".to_string()));
+    }
+
+    let org_name: String = client
+        .query_opt("SELECT name FROM orgs WHERE id = $1", &[&org_id])
+        .await?
+        .ok_or(AppError::NotFound("Organization not found".to_string()))?
+        .get(0);
+
+    let token = auth::generate_token()?;
+
+    let transaction = client.transaction().await?;
+
+    // A new invitation replaces any pending one for the same email
+    transaction
+        .execute(
+            "DELETE FROM org_invitations
+             WHERE org_id = $1 AND lower(email) = lower($2) AND accepted_at IS NULL",
+            &[&org_id, &payload.email],
+        )
+        .await?;
+
+    let row = transaction
+        .query_one(
+            "INSERT INTO org_invitations (org_id, email, role, token_hash, invited_by)
+             VALUES ($1, $2, $3, $4, $5)
+             RETURNING *",
+            &[
+                &org_id,
+                &payload.email,
+                &payload.role,
+                &auth::hash_token(&token),
+                &auth_user.id,
+            ],
+        )
+        .await?;
+    let invitation = InvitationSummary::from(parse_invitation(&row)?);
+
+    audit::record(
+        &transaction,
+        AuditEvent::new(
+            &auth_user,
+            "invitation.created",
+            "invitation",
+            invitation.id,
+        )
+        .after(&invitation),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    state.mailer.send(
+        &invitation.email,
+        Template::Invitation {
+            org_name: &org_name,
+            role: &invitation.role,
+            token: &token,
+        },
+    );
+
+    Ok(Json(
+        DataResponse::new()
+            .data(CreatedInvitation { token, invitation })
+            .build(),
+    ))
+}
+
+/// Revoke a pending invitation
+#[utoipa::path(
+    delete,
+    path = "/v1/orgs/{org_id}/invitations/{invitation_id}",
+    params(
+        ("org_id" = Uuid, Path, description = "Organization ID"),
+        ("invitation_id" = Uuid, Path, description = "Invitation ID"),
+    ),
+    responses(
+        (status = 200, description = "Invitation revoked", body = DataResponse<InvitationSummary>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "Forbidden", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "Invitation not found", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Organizations"
+)]
+async fn revoke_invitation(
+    State(state): State<AppState>,
+    OrgMember(auth_user, _): OrgMember<require::UserManage>,
+    Path((org_id, invitation_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<DataResponse<InvitationSummary>>, AppError> {
+    let mut client = state.db_pool.get().await?;
+    let transaction = client.transaction().await?;
+
+    let row = transaction
+        .query_opt(
+            "DELETE FROM org_invitations
+             WHERE id = $1 AND org_id = $2 AND accepted_at IS NULL
+             RETURNING *",
+            &[&invitation_id, &org_id],
+        )
+        .await?
+        .ok_or(AppError::NotFound("Invitation not found".to_string()))?;
+    let invitation = InvitationSummary::from(parse_invitation(&row)?);
+
+    audit::record(
+        &transaction,
+        AuditEvent::new(
+            &auth_user,
+            "invitation.revoked",
+            "invitation",
+            invitation.id,
+        )
+        .before(&invitation),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(DataResponse::new().data(invitation).build()))
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/v1/orgs/{org_id}",
+            axum::routing::get(get_org)
+                .patch(update_org)
+                .delete(delete_org),
+        )
+        .route(
+            "/v1/orgs/{org_id}/invitations",
+            axum::routing::get(list_invitations).post(create_invitation),
+        )
+        .route(
+            "/v1/orgs/{org_id}/invitations/{invitation_id}",
+            axum::routing::delete(revoke_invitation),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("Acme Corp."), "acme-corp");
+        assert_eq!(slugify("  Hello -- World  "), "hello-world");
+        assert_eq!(slugify("jane@example.com"), "jane-example-com");
+        assert_eq!(slugify("!!!"), "");
+    }
+
+    #[test]
+    fn test_validate_slug() {
+        assert!(validate_slug("acme-corp").is_ok());
+        assert!(validate_slug("").is_err());
+        assert!(validate_slug("Acme").is_err());
+        assert!(validate_slug("acme--corp").is_err());
+        assert!(validate_slug("-acme").is_err());
+    }
+}
@@ -0,0 +1,380 @@
+use crate::http::environments::{self, CreatedEnvironment};
+use crate::models::db::Projects;
+use crate::models::enums::UserRole;
+use crate::pkg::audit::{self, AuditEvent};
+use crate::pkg::auth::{Authorized, ProjectMember};
+use crate::pkg::error::AppError;
+use crate::pkg::permissions::require;
+use crate::pkg::response::DataResponse;
+use crate::pkg::state::AppState;
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+};
+use axum_extra::extract::WithRejection;
+use pgmap::FromRow;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Environments every new project starts with
+const DEFAULT_ENVIRONMENTS: [&str; 3] = ["development", "staging", "production"];
+
+#[derive(utoipa::OpenApi)]
+#[openapi(
+    paths(
+        list_projects,
+        get_project,
+        create_project,
+        update_project,
+        delete_project,
+    ),
+    components(
+        schemas(
+            Projects,
+            CreateProjectRequest,
+            UpdateProjectRequest,
+            CreatedProject,
+        ),
+    ),
+    tags(
+        (name = "Projects", description = "Projects of the organization"),
+    ),
+)]
+#[allow(dead_code)]
+pub struct ProjectsApi;
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CreateProjectRequest {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct UpdateProjectRequest {
+    pub name: Option<String>,
+    pub description: Option<String>,
+}
+
+/// A new project with its default environments and their keys, the keys are only shown once
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CreatedProject {
+    pub project: Projects,
+    pub environments: Vec<CreatedEnvironment>,
+}
+
+fn parse_project(row: &tokio_postgres::Row) -> Result<Projects, AppError> {
+    Projects::from_row(row)
+        .map_err(|_| AppError::InternalError("Failed to parse project data".to_string()))
+}
+
+fn validate_name(name: &str) -> Result<(), AppError> {
+    if name.trim().is_empty() || name.len() > 100 {
+        return Err(AppError::UnprocessableEntity(
+            "name: must be between 1 and 100 characters".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+async fn ensure_name_available(
+    client: &impl deadpool_postgres::GenericClient,
+    org_id: Uuid,
+    name: &str,
+    except: Option<Uuid>,
+) -> Result<(), AppError> {
+    let existing = client
+        .query_opt(
+            "SELECT id FROM projects
+             WHERE org_id = $1 AND name = $2 AND ($3::uuid IS NULL OR id <> $3)",
+            &[&org_id, &name, &except],
+        )
+        .await?;
+
+    if existing.is_some() {
+        return Err(AppError::Conflict(
+            "Project name already exists".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+async fn find_project(
+    client: &impl deadpool_postgres::GenericClient,
+    project_id: Uuid,
+) -> Result<Projects, AppError> {
+    let row = client
+        .query_opt("SELECT * FROM projects WHERE id = $1", &[&project_id])
+        .await?
+        .ok_or(AppError::NotFound("Project not found".to_string()))?;
+
+    parse_project(&row)
+}
+
+/// List the projects of the org. Admins see every project, other users the ones they are members of.
+#[utoipa::path(
+    get,
+    path = "/v1/projects",
+    responses(
+        (status = 200, description = "Projects", body = DataResponse<Vec<Projects>>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "Forbidden", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Projects"
+)]
+async fn list_projects(
+    State(state): State<AppState>,
+    Authorized(auth_user, _): Authorized<require::FlagRead>,
+) -> Result<Json<DataResponse<Vec<Projects>>>, AppError> {
+    let client = state.db_pool.get().await?;
+
+    let is_admin = matches!(auth_user.role, UserRole::Admin);
+    let rows = client
+        .query(
+            "SELECT p.* FROM projects p
+             WHERE p.org_id = $1
+               AND ($3 OR EXISTS (
+                   SELECT 1 FROM project_owners po
+                   WHERE po.project_id = p.id AND po.user_id = $2
+               ))
+             ORDER BY p.name",
+            &[&auth_user.org_id, &auth_user.id, &is_admin],
+        )
+        .await?;
+
+    let projects = rows
+        .iter()
+        .map(parse_project)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Json(DataResponse::new().data(projects).build()))
+}
+
+/// Get a project
+#[utoipa::path(
+    get,
+    path = "/v1/projects/{project_id}",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+    ),
+    responses(
+        (status = 200, description = "Project", body = DataResponse<Projects>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "Project not found", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Projects"
+)]
+async fn get_project(
+    State(state): State<AppState>,
+    ProjectMember(_auth_user, _): ProjectMember<require::FlagRead>,
+    Path(project_id): Path<Uuid>,
+) -> Result<Json<DataResponse<Projects>>, AppError> {
+    let client = state.db_pool.get().await?;
+
+    let project = find_project(&client, project_id).await?;
+
+    Ok(Json(DataResponse::new().data(project).build()))
+}
+
+/// Create a project owned by the caller, with development, staging and production environments.
+/// The keys issued for the environments are only shown once.
+#[utoipa::path(
+    post,
+    path = "/v1/projects",
+    request_body = CreateProjectRequest,
+    responses(
+        (status = 200, description = "Project created", body = DataResponse<CreatedProject>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "Forbidden", body = DataResponse<serde_json::Value>),
+        (status = 409, description = "Project name already exists", body = DataResponse<serde_json::Value>),
+        (status = 422, description = "Invalid project", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Projects"
+)]
+async fn create_project(
+    State(state): State<AppState>,
+    Authorized(auth_user, _): Authorized<require::ProjectManage>,
+    WithRejection(Json(payload), _): WithRejection<Json<CreateProjectRequest>, AppError>,
+) -> Result<Json<DataResponse<CreatedProject>>, AppError> {
+    validate_name(&payload.name)?;
+
+    let mut client = state.db_pool.get().await?;
+    ensure_name_available(&client, auth_user.org_id, &payload.name, None).await?;
+
+    let transaction = client.transaction().await?;
+
+    let row = transaction
+        .query_one(
+            "INSERT INTO projects (org_id, name, description) VALUES ($1, $2, $3) RETURNING *",
+            &[&auth_user.org_id, &payload.name, &payload.description],
+        )
+        .await?;
+    let project = parse_project(&row)?;
+
+    transaction
+        .execute(
+            "INSERT INTO project_owners (project_id, user_id, role) VALUES ($1, $2, 'owner')",
+            &[&project.id, &auth_user.id],
+        )
+        .await?;
+
+    audit::record(
+        &transaction,
+        AuditEvent::new(&auth_user, "project.created", "project", project.id)
+            .project(project.id)
+            .after(&project),
+    )
+    .await?;
+
+    let mut created_environments = Vec::new();
+    for name in DEFAULT_ENVIRONMENTS {
+        created_environments.push(
+            environments::insert_environment(&transaction, &auth_user, project.id, name, None)
+                .await?,
+        );
+    }
+
+    transaction.commit().await?;
+
+    Ok(Json(
+        DataResponse::new()
+            .data(CreatedProject {
+                project,
+                environments: created_environments,
+            })
+            .build(),
+    ))
+}
+
+/// Update a project's name or description
+#[utoipa::path(
+    patch,
+    path = "/v1/projects/{project_id}",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+    ),
+    request_body = UpdateProjectRequest,
+    responses(
+        (status = 200, description = "Project updated", body = DataResponse<Projects>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "Forbidden", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "Project not found", body = DataResponse<serde_json::Value>),
+        (status = 409, description = "Project name already exists", body = DataResponse<serde_json::Value>),
+        (status = 422, description = "Invalid project", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Projects"
+)]
+async fn update_project(
+    State(state): State<AppState>,
+    ProjectMember(auth_user, _): ProjectMember<require::ProjectManage>,
+    Path(project_id): Path<Uuid>,
+    WithRejection(Json(payload), _): WithRejection<Json<UpdateProjectRequest>, AppError>,
+) -> Result<Json<DataResponse<Projects>>, AppError> {
+    let mut client = state.db_pool.get().await?;
+
+    if let Some(name) = &payload.name {
+        validate_name(name)?;
+        ensure_name_available(&client, auth_user.org_id, name, Some(project_id)).await?;
+    }
+
+    let transaction = client.transaction().await?;
+
+    let before = find_project(&transaction, project_id).await?;
+
+    let row = transaction
+        .query_one(
+            "UPDATE projects SET
+                name = COALESCE($2, name),
+                description = COALESCE($3, description),
+                updated_at = current_timestamp
+             WHERE id = $1
+             RETURNING *",
+            &[&project_id, &payload.name, &payload.description],
+        )
+        .await?;
+    let project = parse_project(&row)?;
+
+    audit::record(
+        &transaction,
+        AuditEvent::new(&auth_user, "project.updated", "project", project_id)
+            .project(project_id)
+            .before(&before)
+            .after(&project),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(DataResponse::new().data(project).build()))
+}
+
+/// Delete a project with its environments, flags, API keys and memberships
+#[utoipa::path(
+    delete,
+    path = "/v1/projects/{project_id}",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+    ),
+    responses(
+        (status = 200, description = "Project deleted", body = DataResponse<Projects>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "Forbidden", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "Project not found", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Projects"
+)]
+async fn delete_project(
+    State(state): State<AppState>,
+    ProjectMember(auth_user, _): ProjectMember<require::ProjectManage>,
+    Path(project_id): Path<Uuid>,
+) -> Result<Json<DataResponse<Projects>>, AppError> {
+    let mut client = state.db_pool.get().await?;
+    let transaction = client.transaction().await?;
+
+    let project = find_project(&transaction, project_id).await?;
+
+    // Recorded first, so the event still resolves the project's org
+    audit::record(
+        &transaction,
+        AuditEvent::new(&auth_user, "project.deleted", "project", project_id)
+            .project(project_id)
+            .before(&project),
+    )
+    .await?;
+
+    transaction
+        .execute("DELETE FROM projects WHERE id = $1", &[&project_id])
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(DataResponse::new().data(project).build()))
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/v1/projects",
+            axum::routing::get(list_projects).post(create_project),
+        )
+        .route(
+            "/v1/projects/{project_id}",
+            axum::routing::get(get_project)
+                .patch(update_project)
+                .delete(delete_project),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_name() {
+        assert!(validate_name("Checkout").is_ok());
+        assert!(validate_name("").is_err());
+        assert!(validate_name(&"x".repeat(101)).is_err());
+    }
+}
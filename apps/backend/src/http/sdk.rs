@@ -0,0 +1,329 @@
+use crate::models::db::{Audiences, FeatureFlagEnvironments, FeatureFlagOverrides, FeatureFlags};
+use crate::pkg::auth::ApiKeyAuth;
+use crate::pkg::cache;
+use crate::pkg::error::AppError;
+use crate::pkg::evaluation::{Evaluation, EvaluationContext, EvaluationReason, Ruleset};
+use crate::pkg::events::{self, FlagChange, FlagEvent};
+use crate::pkg::response::DataResponse;
+use crate::pkg::state::AppState;
+use axum::{
+    Json, Router,
+    extract::State,
+    http::HeaderMap,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use axum_extra::extract::WithRejection;
+use deadpool_redis::redis;
+use futures_util::{Stream, StreamExt, future, stream};
+use pgmap::FromRow;
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::time::Duration;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(utoipa::OpenApi)]
+#[openapi(
+    paths(
+        evaluate,
+        stream_flags,
+    ),
+    components(
+        schemas(
+            SdkEvaluateRequest,
+            Evaluation,
+            EvaluationReason,
+            Ruleset,
+            FlagChange,
+        ),
+    ),
+    tags(
+        (name = "SDK", description = "Flag evaluation endpoints for SDKs, authenticated by API key"),
+    ),
+)]
+#[allow(dead_code)]
+pub struct SdkApi;
+
+/// How often an idle stream sends a comment so proxies keep the connection open
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+const SNAPSHOT_EVENT: &str = "snapshot";
+const FLAG_CHANGE_EVENT: &str = "flag_change";
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct SdkEvaluateRequest {
+    /// Attributes of the subject to evaluate flags for
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    pub context: EvaluationContext,
+}
+
+/// Load the flags of a project together with their state, overrides and audiences
+/// in one environment, either every flag or only `flag_id`
+async fn load_ruleset(
+    client: &deadpool_postgres::Client,
+    project_id: Uuid,
+    environment_id: Uuid,
+    flag_id: Option<Uuid>,
+) -> Result<Ruleset, AppError> {
+    let flag_rows = client
+        .query(
+            "SELECT * FROM feature_flags
+             WHERE project_id = $1 AND ($2::uuid IS NULL OR id = $2)
+             ORDER BY key",
+            &[&project_id, &flag_id],
+        )
+        .await?;
+
+    let state_rows = client
+        .query(
+            "SELECT * FROM feature_flag_environments
+             WHERE environment_id = $1 AND ($2::uuid IS NULL OR feature_flag_id = $2)",
+            &[&environment_id, &flag_id],
+        )
+        .await?;
+
+    let override_rows = client
+        .query(
+            "SELECT * FROM feature_flag_overrides
+             WHERE environment_id = $1 AND ($2::uuid IS NULL OR feature_flag_id = $2)
+             ORDER BY created_at, id",
+            &[&environment_id, &flag_id],
+        )
+        .await?;
+
+    let audience_rows = client
+        .query(
+            "SELECT DISTINCT a.* FROM audiences a
+             JOIN feature_flag_overrides o ON o.audience_id = a.id
+             WHERE o.environment_id = $1 AND ($2::uuid IS NULL OR o.feature_flag_id = $2)",
+            &[&environment_id, &flag_id],
+        )
+        .await?;
+
+    Ok(Ruleset {
+        flags: FeatureFlags::from_rows(&flag_rows)
+            .map_err(|_| AppError::InternalError("Failed to parse flag data".to_string()))?,
+        states: FeatureFlagEnvironments::from_rows(&state_rows)
+            .map_err(|_| AppError::InternalError("Failed to parse flag state data".to_string()))?,
+        overrides: FeatureFlagOverrides::from_rows(&override_rows)
+            .map_err(|_| AppError::InternalError("Failed to parse override data".to_string()))?,
+        audiences: Audiences::from_rows(&audience_rows)
+            .map_err(|_| AppError::InternalError("Failed to parse audience data".to_string()))?,
+    })
+}
+
+/// Ruleset of an environment, served from the Redis cache when it holds the current version.
+/// Misses and Redis outages fall back to Postgres, refilling the cache when possible.
+pub(crate) async fn environment_ruleset(
+    state: &AppState,
+    project_id: Uuid,
+    environment_id: Uuid,
+) -> Result<Ruleset, AppError> {
+    let version = match cache::get(&state.redis_pool, environment_id).await {
+        Ok((_, Some(ruleset))) => return Ok(ruleset),
+        Ok((version, None)) => Some(version),
+        Err(e) => {
+            eprintln!("Failed to read cached ruleset: {}", e);
+            None
+        }
+    };
+
+    let client = state.db_pool.get().await?;
+    let ruleset = load_ruleset(&client, project_id, environment_id, None).await?;
+
+    if let Some(version) = version
+        && let Err(e) = cache::store(&state.redis_pool, environment_id, version, &ruleset).await
+    {
+        eprintln!("Failed to cache ruleset: {}", e);
+    }
+
+    Ok(ruleset)
+}
+
+/// Evaluate every flag of the API key's environment for a context
+#[utoipa::path(
+    post,
+    path = "/v1/sdk/evaluate",
+    request_body = SdkEvaluateRequest,
+    params(
+        ("x-api-key" = String, Header, description = "Environment API key"),
+    ),
+    responses(
+        (status = 200, description = "Resolved flags", body = DataResponse<Vec<Evaluation>>),
+        (status = 401, description = "Missing or invalid API key", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "SDK"
+)]
+async fn evaluate(
+    State(state): State<AppState>,
+    api_key: ApiKeyAuth,
+    WithRejection(Json(payload), _): WithRejection<Json<SdkEvaluateRequest>, AppError>,
+) -> Result<Json<DataResponse<Vec<Evaluation>>>, AppError> {
+    let ruleset = environment_ruleset(&state, api_key.project_id, api_key.environment_id).await?;
+
+    Ok(Json(
+        DataResponse::new()
+            .data(ruleset.evaluate_all(&payload.context))
+            .build(),
+    ))
+}
+
+/// Every environment of a project, for changes that affect a flag everywhere
+pub(crate) async fn project_environments(
+    client: &deadpool_postgres::Client,
+    project_id: Uuid,
+) -> Result<Vec<Uuid>, AppError> {
+    let rows = client
+        .query(
+            "SELECT id FROM environments WHERE project_id = $1",
+            &[&project_id],
+        )
+        .await?;
+
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+/// Invalidate the cached rulesets of the given environments and send the current
+/// rules of a flag to SDKs streaming them. The change itself is already stored,
+/// so failures are only logged: cached rulesets expire on their own and
+/// streaming SDKs catch up on their next snapshot.
+pub(crate) async fn publish_flag_change(
+    state: &AppState,
+    client: &deadpool_postgres::Client,
+    project_id: Uuid,
+    flag_id: Uuid,
+    key: &str,
+    environment_ids: &[Uuid],
+    deleted: bool,
+) {
+    for environment_id in environment_ids {
+        if let Err(e) = cache::invalidate(&state.redis_pool, *environment_id).await {
+            eprintln!("Failed to invalidate cached ruleset: {}", e);
+        }
+
+        let ruleset = if deleted {
+            None
+        } else {
+            match load_ruleset(client, project_id, *environment_id, Some(flag_id)).await {
+                Ok(ruleset) => Some(ruleset),
+                Err(e) => {
+                    eprintln!("Failed to load flag {} for publishing: {}", key, e);
+                    continue;
+                }
+            }
+        };
+
+        let change = FlagChange {
+            flag_id,
+            key: key.to_string(),
+            ruleset,
+        };
+        if let Err(e) = events::publish(&state.redis_pool, *environment_id, change).await {
+            eprintln!("Failed to publish change of flag {}: {}", key, e);
+        }
+    }
+}
+
+fn flag_change_event(event: &FlagEvent) -> Result<Event, AppError> {
+    Event::default()
+        .event(FLAG_CHANGE_EVENT)
+        .id(event.id.to_string())
+        .json_data(&event.change)
+        .map_err(|_| AppError::InternalError("Failed to encode event".to_string()))
+}
+
+/// Stream the flag rules of the API key's environment.
+/// Starts with a `snapshot` event holding the whole ruleset, then sends a `flag_change`
+/// event with the new rules of a flag whenever it changes. Reconnecting with
+/// `Last-Event-ID` replays missed changes instead, or sends a fresh snapshot when
+/// they are no longer retained.
+#[utoipa::path(
+    get,
+    path = "/v1/sdk/stream",
+    params(
+        ("x-api-key" = String, Header, description = "Environment server API key"),
+        ("Last-Event-ID" = Option<String>, Header, description = "Id of the last event received, to resume a stream"),
+    ),
+    responses(
+        (status = 200, description = "Server-Sent Events stream of `snapshot` (Ruleset) and `flag_change` (FlagChange) events", content_type = "text/event-stream", body = String),
+        (status = 401, description = "Missing or invalid API key", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "Client API keys cannot stream rulesets", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "SDK"
+)]
+async fn stream_flags(
+    State(state): State<AppState>,
+    api_key: ApiKeyAuth,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
+    // Rulesets reveal every audience rule, so they are only streamed to server SDKs
+    if !api_key.is_server_key {
+        return Err(AppError::Forbidden(
+            "Streaming requires a server API key".to_string(),
+        ));
+    }
+
+    let environment_id = api_key.environment_id;
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+
+    // Subscribe before reading the current state so no change falls in between
+    let redis_client = redis::Client::open(state.config.redis_addr())?;
+    let mut pubsub = redis_client.get_async_pubsub().await?;
+    pubsub.subscribe(events::channel(environment_id)).await?;
+
+    let missed = match last_event_id {
+        Some(last_id) => events::events_since(&state.redis_pool, environment_id, last_id).await?,
+        None => None,
+    };
+
+    let (initial, mut last_id) = match (missed, last_event_id) {
+        (Some(missed), Some(last_id)) => {
+            let last_id = missed.last().map(|e| e.id).unwrap_or(last_id);
+            let events = missed
+                .iter()
+                .map(flag_change_event)
+                .collect::<Result<Vec<_>, _>>()?;
+            (events, last_id)
+        }
+        _ => {
+            let snapshot_id = events::current_id(&state.redis_pool, environment_id).await?;
+            let ruleset = environment_ruleset(&state, api_key.project_id, environment_id).await?;
+            let snapshot = Event::default()
+                .event(SNAPSHOT_EVENT)
+                .id(snapshot_id.to_string())
+                .json_data(&ruleset)
+                .map_err(|_| AppError::InternalError("Failed to encode snapshot".to_string()))?;
+            (vec![snapshot], snapshot_id)
+        }
+    };
+
+    let changes = pubsub.into_on_message().filter_map(move |message| {
+        let event = message
+            .get_payload::<String>()
+            .ok()
+            .and_then(|payload| serde_json::from_str::<FlagEvent>(&payload).ok())
+            // Skip changes already covered by the snapshot or replayed history
+            .filter(|event| event.id > last_id)
+            .and_then(|event| {
+                last_id = event.id;
+                flag_change_event(&event).ok()
+            });
+        future::ready(event.map(Ok))
+    });
+
+    Ok(
+        Sse::new(stream::iter(initial.into_iter().map(Ok)).chain(changes))
+            .keep_alive(KeepAlive::new().interval(HEARTBEAT_INTERVAL)),
+    )
+}
+
+pub fn router() -> Router<AppState> {
+    let sdk_routes = Router::new()
+        .route("/evaluate", axum::routing::post(evaluate))
+        .route("/stream", axum::routing::get(stream_flags));
+
+    Router::new().nest("/v1/sdk", sdk_routes)
+}
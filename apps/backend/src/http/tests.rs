@@ -0,0 +1,2199 @@
+//! Tests against a real database, through the full router, mostly about tenant isolation.
+//! They are ignored by a plain `cargo test`, run them against a migrated database with
+//! `DATABASE_URL=postgres://... cargo test -- --ignored` (or `make test.db`).
+
+use crate::models::enums::UserRole;
+use crate::pkg::config::Config;
+use crate::pkg::emails::Email;
+use crate::pkg::jwt::tests::test_key;
+use crate::pkg::jwt::{API_AUDIENCE, JwtService, TokenType};
+use crate::pkg::mailer::Mailer;
+use crate::pkg::mfa;
+use crate::pkg::oidc::OidcProvider;
+use crate::pkg::sessions;
+use crate::pkg::state::{AppState, BaseState};
+use axum::body::Body;
+use axum::http::{Method, Request, StatusCode};
+use clap::Parser;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+/// Ignored tests are run on purpose, so a missing database fails them instead of passing silently
+async fn test_state() -> AppState {
+    let url = std::env::var("DATABASE_URL")
+        .expect("DATABASE_URL must point at a migrated database to run the database tests");
+
+    // Emails land in an outbox of the test's own, founders sign up to orgs of their own
+    let mut config = Config::parse_from(["backend"]);
+    config.allow_org_creation = true;
+    config.mail_outbox_dir =
+        Some(std::env::temp_dir().join(format!("vexillum-outbox-{}", Uuid::new_v4())));
+
+    let db_pool = deadpool_postgres::Config {
+        url: Some(url),
+        ..Default::default()
+    }
+    .create_pool(
+        Some(deadpool_postgres::Runtime::Tokio1),
+        tokio_postgres::NoTls,
+    )
+    .expect("Failed to create database pool");
+
+    // Connections are only opened on use, the isolation checks never reach Redis
+    let redis_pool = deadpool_redis::Config::from_url(config.redis_addr())
+        .create_pool(Some(deadpool_redis::Runtime::Tokio1))
+        .expect("Failed to create Redis pool");
+
+    let jwt = JwtService::new(&test_key(), 3600, 3600).expect("Failed to create JWT service");
+    let mailer = Mailer::from_config(&config).expect("Failed to create mailer");
+
+    Arc::new(BaseState {
+        db_pool,
+        redis_pool,
+        config: Arc::new(config),
+        argon2: argon2::Argon2::default(),
+        jwt: Arc::new(jwt),
+        mailer,
+        oidc: None,
+    })
+}
+
+/// An org with an admin, a project, an environment, a flag and an API key
+struct Tenant {
+    org_id: Uuid,
+    token: String,
+    project_id: Uuid,
+    environment_id: Uuid,
+    flag_id: Uuid,
+    api_key_id: Uuid,
+}
+
+async fn seed_tenant(state: &AppState) -> Tenant {
+    let client = state.db_pool.get().await.unwrap();
+    let name = format!("isolation-{}", Uuid::new_v4());
+
+    let org_id: Uuid = client
+        .query_one(
+            "INSERT INTO orgs (name, slug, region) VALUES ($1, $1, 'us-east-1') RETURNING id",
+            &[&name],
+        )
+        .await
+        .unwrap()
+        .get(0);
+
+    let user_id: Uuid = client
+        .query_one(
+            "INSERT INTO users (org_id, email, password_hash, role, email_verified_at)
+             VALUES ($1, $2, 'unused', $3, current_timestamp) RETURNING id",
+            &[&org_id, &format!("{}@example.com", name), &UserRole::Admin],
+        )
+        .await
+        .unwrap()
+        .get(0);
+
+    let project_id: Uuid = client
+        .query_one(
+            "INSERT INTO projects (org_id, name) VALUES ($1, 'Isolation') RETURNING id",
+            &[&org_id],
+        )
+        .await
+        .unwrap()
+        .get(0);
+
+    let environment_id: Uuid = client
+        .query_one(
+            "INSERT INTO environments (project_id, name) VALUES ($1, 'production') RETURNING id",
+            &[&project_id],
+        )
+        .await
+        .unwrap()
+        .get(0);
+
+    let flag_id: Uuid = client
+        .query_one(
+            "INSERT INTO feature_flags (org_id, project_id, key) VALUES ($1, $2, 'checkout')
+             RETURNING id",
+            &[&org_id, &project_id],
+        )
+        .await
+        .unwrap()
+        .get(0);
+
+    client
+        .execute(
+            "INSERT INTO feature_flag_environments (feature_flag_id, environment_id)
+             VALUES ($1, $2)",
+            &[&flag_id, &environment_id],
+        )
+        .await
+        .unwrap();
+
+    let api_key_id: Uuid = client
+        .query_one(
+            "INSERT INTO api_keys (user_id, environment_id, key_hash, key_prefix)
+             VALUES ($1, $2, $3, 'vx_srv_test')
+             RETURNING id",
+            &[&user_id, &environment_id, &Uuid::new_v4().to_string()],
+        )
+        .await
+        .unwrap()
+        .get(0);
+
+    let token = state
+        .jwt
+        .generate_token(user_id, TokenType::Access, API_AUDIENCE)
+        .unwrap();
+
+    Tenant {
+        org_id,
+        token,
+        project_id,
+        environment_id,
+        flag_id,
+        api_key_id,
+    }
+}
+
+async fn delete_org(state: &AppState, org_id: Uuid) {
+    let client = state.db_pool.get().await.unwrap();
+    client
+        .execute("DELETE FROM audit_events WHERE org_id = $1", &[&org_id])
+        .await
+        .unwrap();
+    client
+        .execute("DELETE FROM orgs WHERE id = $1", &[&org_id])
+        .await
+        .unwrap();
+}
+
+async fn cleanup(state: &AppState, tenants: &[&Tenant]) {
+    for tenant in tenants {
+        delete_org(state, tenant.org_id).await;
+    }
+
+    if let Some(dir) = &state.config.mail_outbox_dir {
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
+
+/// Wait for the background task sending an email to `to`, and return it
+async fn sent_email(state: &AppState, to: &str) -> Email {
+    let dir = state.config.mail_outbox_dir.as_ref().unwrap();
+
+    for _ in 0..50 {
+        let emails = std::fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| std::fs::read(entry.path()).ok())
+            .filter_map(|content| serde_json::from_slice::<Email>(&content).ok());
+        if let Some(email) = emails.into_iter().find(|email| email.to == to) {
+            return email;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+
+    panic!("No email was sent to {}", to);
+}
+
+/// The token of the link in an email
+fn link_token(email: &Email) -> String {
+    email
+        .text
+        .split_whitespace()
+        .find_map(|word| word.split_once("token=").or(word.split_once("invitation=")))
+        .map(|(_, token)| token.to_string())
+        .expect("Email has no link")
+}
+
+/// Follow the verification link emailed to `to`, returning the session it signs in
+async fn verify_email(state: &AppState, to: &str) -> Value {
+    let token = link_token(&sent_email(state, to).await);
+    let (status, verified) = send(
+        state,
+        "",
+        Method::POST,
+        "/api/v1/auth/email/verify".to_string(),
+        Some(json!({ "token": token })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", verified);
+    verified
+}
+
+async fn send(
+    state: &AppState,
+    token: &str,
+    method: Method,
+    uri: String,
+    body: Option<Value>,
+) -> (StatusCode, Value) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("authorization", format!("Bearer {}", token))
+        .header("content-type", "application/json")
+        .body(match body {
+            Some(body) => Body::from(body.to_string()),
+            None => Body::empty(),
+        })
+        .unwrap();
+
+    let response = super::router(state.clone()).oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+
+    (
+        status,
+        serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+    )
+}
+
+/// POST to an auth endpoint that reads the refresh token cookie
+async fn send_refresh_token(
+    state: &AppState,
+    uri: &str,
+    refresh_token: &str,
+) -> (StatusCode, Value) {
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(uri)
+        .header("cookie", format!("refresh_token={}", refresh_token))
+        .body(Body::empty())
+        .unwrap();
+
+    let response = super::router(state.clone()).oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+
+    (
+        status,
+        serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+    )
+}
+
+/// Every request another org could make against `victim`'s resources
+fn cross_tenant_requests(
+    victim: &Tenant,
+    attacker: &Tenant,
+) -> Vec<(Method, String, Option<Value>)> {
+    let project = format!("/api/v1/projects/{}", victim.project_id);
+    let flag = format!("{}/flags/{}", project, victim.flag_id);
+    let environment = format!("{}/environments/{}", project, victim.environment_id);
+    let keys = format!("{}/api-keys", environment);
+    let org = format!("/api/v1/orgs/{}", victim.org_id);
+
+    vec![
+        (Method::GET, format!("{}/flags", project), None),
+        (
+            Method::POST,
+            format!("{}/flags", project),
+            Some(json!({ "key": "stolen", "type": "Boolean" })),
+        ),
+        (Method::GET, flag.clone(), None),
+        (
+            Method::PATCH,
+            flag.clone(),
+            Some(json!({ "key": "renamed" })),
+        ),
+        (Method::DELETE, flag.clone(), None),
+        (Method::GET, format!("{}/environments", flag), None),
+        (
+            Method::PATCH,
+            format!("{}/environments/{}", flag, victim.environment_id),
+            Some(json!({ "is_enabled": true })),
+        ),
+        (Method::GET, format!("{}/variants", flag), None),
+        (Method::GET, keys.clone(), None),
+        (Method::POST, keys.clone(), Some(json!({}))),
+        (
+            Method::DELETE,
+            format!("{}/{}", keys, victim.api_key_id),
+            None,
+        ),
+        (Method::GET, format!("{}/members", project), None),
+        (
+            Method::POST,
+            format!("{}/members", project),
+            Some(json!({ "email": "intruder@example.com", "role": "Editor" })),
+        ),
+        (Method::GET, project.clone(), None),
+        (
+            Method::PATCH,
+            project.clone(),
+            Some(json!({ "name": "Taken" })),
+        ),
+        (Method::DELETE, project.clone(), None),
+        (Method::GET, format!("{}/environments", project), None),
+        (
+            Method::POST,
+            format!("{}/environments", project),
+            Some(json!({ "name": "intruder" })),
+        ),
+        (Method::GET, environment.clone(), None),
+        (
+            Method::PATCH,
+            environment.clone(),
+            Some(json!({ "name": "renamed" })),
+        ),
+        (Method::DELETE, environment.clone(), None),
+        (
+            Method::POST,
+            format!("{}/clone", environment),
+            Some(json!({ "name": "copy" })),
+        ),
+        // The attacker's own project, pointed at the victim's flag and environment
+        (
+            Method::GET,
+            format!(
+                "/api/v1/projects/{}/flags/{}",
+                attacker.project_id, victim.flag_id
+            ),
+            None,
+        ),
+        (
+            Method::GET,
+            format!(
+                "/api/v1/projects/{}/environments/{}/api-keys",
+                attacker.project_id, victim.environment_id
+            ),
+            None,
+        ),
+        (
+            Method::POST,
+            format!(
+                "/api/v1/projects/{}/environments/{}/clone",
+                attacker.project_id, victim.environment_id
+            ),
+            Some(json!({ "name": "copy" })),
+        ),
+        (Method::GET, org.clone(), None),
+        (Method::PATCH, org.clone(), Some(json!({ "name": "Taken" }))),
+        (Method::DELETE, org.clone(), None),
+        (Method::GET, format!("{}/invitations", org), None),
+        (
+            Method::POST,
+            format!("{}/invitations", org),
+            Some(json!({ "email": "intruder@example.com" })),
+        ),
+    ]
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL, run with `cargo test -- --ignored`"]
+async fn test_orgs_cannot_reach_each_other() {
+    let state = test_state().await;
+
+    let alpha = seed_tenant(&state).await;
+    let beta = seed_tenant(&state).await;
+
+    // Each org reads its own data on the same routes, so the rejections below are not a setup problem
+    for tenant in [&alpha, &beta] {
+        for (method, uri, _) in cross_tenant_requests(tenant, tenant) {
+            if method != Method::GET {
+                continue;
+            }
+            let (status, body) = send(&state, &tenant.token, method, uri.clone(), None).await;
+            assert_eq!(status, StatusCode::OK, "GET {}: {}", uri, body);
+        }
+    }
+
+    for (victim, attacker) in [(&alpha, &beta), (&beta, &alpha)] {
+        for (method, uri, body) in cross_tenant_requests(victim, attacker) {
+            let (status, _) =
+                send(&state, &attacker.token, method.clone(), uri.clone(), body).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{} {}", method, uri);
+        }
+    }
+
+    // Nothing was changed behind the rejections
+    let client = state.db_pool.get().await.unwrap();
+    for tenant in [&alpha, &beta] {
+        let flag = client
+            .query_one(
+                "SELECT key FROM feature_flags WHERE id = $1",
+                &[&tenant.flag_id],
+            )
+            .await
+            .unwrap();
+        assert_eq!(flag.get::<_, String>(0), "checkout");
+
+        let key = client
+            .query_one(
+                "SELECT revoked_at IS NULL FROM api_keys WHERE id = $1",
+                &[&tenant.api_key_id],
+            )
+            .await
+            .unwrap();
+        assert!(key.get::<_, bool>(0));
+
+        let flags: i64 = client
+            .query_one(
+                "SELECT count(*) FROM feature_flags WHERE project_id = $1",
+                &[&tenant.project_id],
+            )
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(flags, 1);
+
+        let environments = client
+            .query(
+                "SELECT e.name, p.name FROM environments e
+                 JOIN projects p ON p.id = e.project_id
+                 WHERE e.project_id = $1",
+                &[&tenant.project_id],
+            )
+            .await
+            .unwrap();
+        assert_eq!(environments.len(), 1);
+        assert_eq!(environments[0].get::<_, String>(0), "production");
+        assert_eq!(environments[0].get::<_, String>(1), "Isolation");
+    }
+
+    cleanup(&state, &[&alpha, &beta]).await;
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL, run with `cargo test -- --ignored`"]
+async fn test_audit_log_is_confined_to_the_org() {
+    let state = test_state().await;
+
+    let alpha = seed_tenant(&state).await;
+    let beta = seed_tenant(&state).await;
+
+    let (status, _) = send(
+        &state,
+        &alpha.token,
+        Method::PATCH,
+        format!(
+            "/api/v1/projects/{}/flags/{}",
+            alpha.project_id, alpha.flag_id
+        ),
+        Some(json!({ "key": "checkout-v2" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, own) = send(
+        &state,
+        &alpha.token,
+        Method::GET,
+        format!("/api/v1/audit?project_id={}", alpha.project_id),
+        None,
+    )
+    .await;
+    assert_eq!(own["data"]["total"], 1);
+
+    let (status, other) = send(
+        &state,
+        &beta.token,
+        Method::GET,
+        format!("/api/v1/audit?project_id={}", alpha.project_id),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(other["data"]["total"], 0);
+
+    cleanup(&state, &[&alpha, &beta]).await;
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL, run with `cargo test -- --ignored`"]
+async fn test_flag_key_prefix_is_literal() {
+    let state = test_state().await;
+
+    let tenant = seed_tenant(&state).await;
+    let client = state.db_pool.get().await.unwrap();
+    for key in ["new_checkout", "newXcheckout", "new%banner"] {
+        client
+            .execute(
+                "INSERT INTO feature_flags (org_id, project_id, key) VALUES ($1, $2, $3)",
+                &[&tenant.org_id, &tenant.project_id, &key],
+            )
+            .await
+            .unwrap();
+    }
+
+    // `_` and `%` are plain characters of a prefix, not wildcards
+    for (prefix, expected) in [
+        ("new_", json!(["new_checkout"])),
+        ("new%25", json!(["new%banner"])),
+    ] {
+        let (status, flags) = send(
+            &state,
+            &tenant.token,
+            Method::GET,
+            format!(
+                "/api/v1/projects/{}/flags?key_prefix={}",
+                tenant.project_id, prefix
+            ),
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let keys: Vec<Value> = flags["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|flag| flag["key"].clone())
+            .collect();
+        assert_eq!(json!(keys), expected);
+    }
+
+    cleanup(&state, &[&tenant]).await;
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL, run with `cargo test -- --ignored`"]
+async fn test_flag_type_change_revalidates_values() {
+    let state = test_state().await;
+
+    let tenant = seed_tenant(&state).await;
+    let client = state.db_pool.get().await.unwrap();
+    client
+        .execute(
+            "UPDATE feature_flag_environments SET value = 'true' WHERE feature_flag_id = $1",
+            &[&tenant.flag_id],
+        )
+        .await
+        .unwrap();
+    let uri = format!(
+        "/api/v1/projects/{}/flags/{}",
+        tenant.project_id, tenant.flag_id
+    );
+
+    // A multivariate flag without variants cannot serve the stored `true`
+    let (status, body) = send(
+        &state,
+        &tenant.token,
+        Method::PATCH,
+        uri.clone(),
+        Some(json!({ "type": "Multivariate" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+
+    // Nor can a JSON flag whose schema expects an object
+    let (status, _) = send(
+        &state,
+        &tenant.token,
+        Method::PATCH,
+        uri.clone(),
+        Some(json!({ "type": "Json", "json_schema": { "type": "object" } })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let (_, flag) = send(&state, &tenant.token, Method::GET, uri.clone(), None).await;
+    assert_eq!(flag["data"]["type"], "Boolean");
+
+    let (status, flag) = send(
+        &state,
+        &tenant.token,
+        Method::PATCH,
+        uri,
+        Some(json!({ "type": "Json" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(flag["data"]["type"], "Json");
+
+    cleanup(&state, &[&tenant]).await;
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL, run with `cargo test -- --ignored`"]
+async fn test_invitations_join_the_inviting_org() {
+    let state = test_state().await;
+
+    let alpha = seed_tenant(&state).await;
+    let email = format!("invited-{}@example.com", Uuid::new_v4());
+
+    let (status, created) = send(
+        &state,
+        &alpha.token,
+        Method::POST,
+        format!("/api/v1/orgs/{}/invitations", alpha.org_id),
+        Some(json!({ "email": email, "role": "User" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let invitation_token = created["data"]["token"].as_str().unwrap().to_string();
+
+    // The invitee is emailed a link carrying the same token
+    let invitation_email = sent_email(&state, &email).await;
+    assert!(invitation_email.subject.contains("isolation-"));
+    assert_eq!(link_token(&invitation_email), invitation_token);
+
+    // Invitations only work for the invited email
+    let (status, _) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/register".to_string(),
+        Some(json!({
+            "email": format!("other-{}", email),
+            "password": "correct horse battery staple",
+            "invitation_token": invitation_token,
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, registered) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/register".to_string(),
+        Some(json!({
+            "email": email,
+            "password": "correct horse battery staple",
+            "invitation_token": invitation_token,
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, me) = send(
+        &state,
+        registered["data"]["access_token"].as_str().unwrap(),
+        Method::GET,
+        "/api/v1/auth/me".to_string(),
+        None,
+    )
+    .await;
+    assert_eq!(me["data"]["org_id"], json!(alpha.org_id));
+    assert_eq!(me["data"]["role"], "User");
+
+    // Without an invitation, registering starts a separate org once the email is verified
+    let founder_email = format!("founder-{}", email);
+    let (status, _) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/register".to_string(),
+        Some(json!({
+            "email": founder_email,
+            "password": "correct horse battery staple",
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let registered = verify_email(&state, &founder_email).await;
+
+    let (_, founder) = send(
+        &state,
+        registered["data"]["access_token"].as_str().unwrap(),
+        Method::GET,
+        "/api/v1/auth/me".to_string(),
+        None,
+    )
+    .await;
+    assert_eq!(founder["data"]["role"], "Admin");
+    assert_ne!(founder["data"]["org_id"], json!(alpha.org_id));
+
+    let founder_org: Uuid = serde_json::from_value(founder["data"]["org_id"].clone()).unwrap();
+    delete_org(&state, founder_org).await;
+
+    cleanup(&state, &[&alpha]).await;
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL, run with `cargo test -- --ignored`"]
+async fn test_registration_controls() {
+    let state = test_state().await;
+
+    let alpha = seed_tenant(&state).await;
+    let slug: String = state
+        .db_pool
+        .get()
+        .await
+        .unwrap()
+        .query_one("SELECT slug FROM orgs WHERE id = $1", &[&alpha.org_id])
+        .await
+        .unwrap()
+        .get(0);
+    let email = format!("joiner-{}@example.com", Uuid::new_v4());
+    let register = |email: &str, password: &str| {
+        Some(json!({ "email": email, "password": password, "org": slug }))
+    };
+
+    for (email, password) in [
+        ("not an email", "correct horse battery staple"),
+        (email.as_str(), "short"),
+        (email.as_str(), "onlylowercaseletters"),
+    ] {
+        let (status, _) = send(
+            &state,
+            "",
+            Method::POST,
+            "/api/v1/auth/register".to_string(),
+            register(email, password),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", password);
+    }
+
+    // With org creation off, signing up must join an existing org
+    let mut config = (*state.config).clone();
+    config.allow_org_creation = false;
+    let mut base = (*state).clone();
+    base.config = Arc::new(config);
+    let closed: AppState = Arc::new(base);
+    let (status, _) = send(
+        &closed,
+        "",
+        Method::POST,
+        "/api/v1/auth/register".to_string(),
+        Some(json!({ "email": email, "password": "correct horse battery staple" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // Orgs only take invited members until they say otherwise
+    let (status, _) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/register".to_string(),
+        register(&email, "correct horse battery staple"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let org_uri = format!("/api/v1/orgs/{}", alpha.org_id);
+    let (status, _) = send(
+        &state,
+        &alpha.token,
+        Method::PATCH,
+        org_uri.clone(),
+        Some(json!({ "registration_mode": "AllowedDomains" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let (status, updated) = send(
+        &state,
+        &alpha.token,
+        Method::PATCH,
+        org_uri,
+        Some(json!({
+            "registration_mode": "AllowedDomains",
+            "allowed_email_domains": ["@Example.com"],
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", updated);
+    assert_eq!(
+        updated["data"]["allowed_email_domains"],
+        json!(["example.com"])
+    );
+
+    let (status, _) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/register".to_string(),
+        register(
+            &email.replace("example.com", "example.org"),
+            "correct horse battery staple",
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, pending) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/register".to_string(),
+        register(&email, "correct horse battery staple"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(pending["data"]["email"], email);
+    assert!(pending["data"].get("access_token").is_none());
+
+    // The password alone does not sign in before the email is verified
+    let login = json!({ "email": email, "password": "correct horse battery staple" });
+    let (status, _) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/login".to_string(),
+        Some(login.clone()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let token = link_token(&sent_email(&state, &email).await);
+    let verified = verify_email(&state, &email).await;
+    let (_, me) = send(
+        &state,
+        verified["data"]["access_token"].as_str().unwrap(),
+        Method::GET,
+        "/api/v1/auth/me".to_string(),
+        None,
+    )
+    .await;
+    assert_eq!(me["data"]["org_id"], json!(alpha.org_id));
+    assert_eq!(me["data"]["role"], "Viewer");
+
+    // Verification links work once
+    let (status, _) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/email/verify".to_string(),
+        Some(json!({ "token": token })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/login".to_string(),
+        Some(login),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    cleanup(&state, &[&alpha]).await;
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL, run with `cargo test -- --ignored`"]
+async fn test_magic_link_email_signs_in() {
+    let state = test_state().await;
+
+    let alpha = seed_tenant(&state).await;
+    let client = state.db_pool.get().await.unwrap();
+    let email: String = client
+        .query_one(
+            "SELECT email FROM users WHERE org_id = $1",
+            &[&alpha.org_id],
+        )
+        .await
+        .unwrap()
+        .get(0);
+
+    let (status, _) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/magic-link/request".to_string(),
+        Some(json!({ "email": email })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let magic_link = sent_email(&state, &email).await;
+    assert!(
+        magic_link
+            .text
+            .contains(&format!("{}/auth/magic?token=", state.config.frontend_url))
+    );
+
+    let (status, signed_in) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/magic-link/verify".to_string(),
+        Some(json!({ "token": link_token(&magic_link) })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, me) = send(
+        &state,
+        signed_in["data"]["access_token"].as_str().unwrap(),
+        Method::GET,
+        "/api/v1/auth/me".to_string(),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(me["data"]["email"], email);
+
+    // A link does not get around a forced password reset or a lockout
+    for (gate, expected) in [
+        (
+            "UPDATE users SET password_reset_required = true WHERE email = $1",
+            StatusCode::FORBIDDEN,
+        ),
+        (
+            "UPDATE users SET password_reset_required = false,
+                 locked_until = current_timestamp + interval '1 hour' WHERE email = $1",
+            StatusCode::TOO_MANY_REQUESTS,
+        ),
+    ] {
+        client.execute(gate, &[&email]).await.unwrap();
+        let token = Uuid::new_v4();
+        client
+            .execute(
+                "INSERT INTO magic_links (user_id, token, expires_at)
+                 SELECT id, $2, current_timestamp + interval '15 minutes' FROM users WHERE email = $1",
+                &[&email, &token],
+            )
+            .await
+            .unwrap();
+
+        let (status, _) = send(
+            &state,
+            "",
+            Method::POST,
+            "/api/v1/auth/magic-link/verify".to_string(),
+            Some(json!({ "token": token.to_string() })),
+        )
+        .await;
+        assert_eq!(status, expected, "{}", gate);
+    }
+
+    cleanup(&state, &[&alpha]).await;
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL, run with `cargo test -- --ignored`"]
+async fn test_password_reset() {
+    let state = test_state().await;
+
+    let alpha = seed_tenant(&state).await;
+    let client = state.db_pool.get().await.unwrap();
+    let user = client
+        .query_one(
+            "SELECT id, email FROM users WHERE org_id = $1",
+            &[&alpha.org_id],
+        )
+        .await
+        .unwrap();
+    let (user_id, email): (Uuid, String) = (user.get(0), user.get(1));
+    let session = sessions::create(&client, &state.jwt, user_id, TokenType::Refresh, None)
+        .await
+        .unwrap();
+
+    // Unknown emails get the same answer
+    let (status, unknown) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/password/forgot".to_string(),
+        Some(json!({ "email": format!("nobody-{}", email) })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, known) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/password/forgot".to_string(),
+        Some(json!({ "email": email })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(known, unknown);
+
+    let reset_token = link_token(&sent_email(&state, &email).await);
+    let stored: String = client
+        .query_one(
+            "SELECT token_hash FROM password_resets WHERE user_id = $1",
+            &[&user_id],
+        )
+        .await
+        .unwrap()
+        .get(0);
+    assert_ne!(stored, reset_token);
+
+    let reset = json!({ "token": reset_token, "password": "a brand new password" });
+    let (status, _) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/password/reset".to_string(),
+        Some(reset.clone()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Tokens are single use
+    let (status, _) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/password/reset".to_string(),
+        Some(reset),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // Sessions from before the reset are gone
+    let (status, _) =
+        send_refresh_token(&state, "/api/v1/auth/refresh", &session.refresh_token).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/login".to_string(),
+        Some(json!({ "email": email, "password": "a brand new password" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    cleanup(&state, &[&alpha]).await;
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL, run with `cargo test -- --ignored`"]
+async fn test_login_lockout() {
+    use argon2::password_hash::{PasswordHasher, SaltString};
+
+    let state = test_state().await;
+
+    let alpha = seed_tenant(&state).await;
+    let client = state.db_pool.get().await.unwrap();
+    let user = client
+        .query_one(
+            "SELECT id, email FROM users WHERE org_id = $1",
+            &[&alpha.org_id],
+        )
+        .await
+        .unwrap();
+    let (user_id, email): (Uuid, String) = (user.get(0), user.get(1));
+    let password_hash = state
+        .argon2
+        .hash_password(
+            b"correct horse battery staple",
+            &SaltString::encode_b64(b"lockout-test-salt").unwrap(),
+        )
+        .unwrap()
+        .to_string();
+    client
+        .execute(
+            "UPDATE users SET password_hash = $2 WHERE id = $1",
+            &[&user_id, &password_hash],
+        )
+        .await
+        .unwrap();
+    let login = |password: &str| Some(json!({ "email": email, "password": password }));
+
+    for _ in 0..state.config.lockout_threshold {
+        let (status, _) = send(
+            &state,
+            "",
+            Method::POST,
+            "/api/v1/auth/login".to_string(),
+            login("wrong password"),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    // Locked out, even with the right password
+    let (status, _) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/login".to_string(),
+        login("correct horse battery staple"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+    let lockouts: i64 = client
+        .query_one(
+            "SELECT count(*) FROM audit_events WHERE action = 'user.locked_out' AND entity_id = $1",
+            &[&user_id],
+        )
+        .await
+        .unwrap()
+        .get(0);
+    assert_eq!(lockouts, 1);
+
+    // Once the lockout passes the right password signs in and the count starts over
+    client
+        .execute(
+            "UPDATE users SET locked_until = current_timestamp - interval '1 second' WHERE id = $1",
+            &[&user_id],
+        )
+        .await
+        .unwrap();
+    let (status, _) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/login".to_string(),
+        login("correct horse battery staple"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let failures: i32 = client
+        .query_one(
+            "SELECT failed_login_attempts FROM users WHERE id = $1",
+            &[&user_id],
+        )
+        .await
+        .unwrap()
+        .get(0);
+    assert_eq!(failures, 0);
+
+    cleanup(&state, &[&alpha]).await;
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL, run with `cargo test -- --ignored`"]
+async fn test_user_management() {
+    use argon2::password_hash::{PasswordHasher, SaltString};
+
+    let state = test_state().await;
+
+    let alpha = seed_tenant(&state).await;
+    let beta = seed_tenant(&state).await;
+    let client = state.db_pool.get().await.unwrap();
+    let admin_id: Uuid = client
+        .query_one("SELECT id FROM users WHERE org_id = $1", &[&alpha.org_id])
+        .await
+        .unwrap()
+        .get(0);
+
+    let email = format!("member-{}@example.com", Uuid::new_v4());
+    let password_hash = state
+        .argon2
+        .hash_password(
+            b"correct horse battery staple",
+            &SaltString::encode_b64(b"users-test-salt").unwrap(),
+        )
+        .unwrap()
+        .to_string();
+    let member_id: Uuid = client
+        .query_one(
+            "INSERT INTO users (org_id, email, password_hash, role, email_verified_at)
+             VALUES ($1, $2, $3, $4, current_timestamp) RETURNING id",
+            &[&alpha.org_id, &email, &password_hash, &UserRole::Viewer],
+        )
+        .await
+        .unwrap()
+        .get(0);
+    let users_uri = format!("/api/v1/orgs/{}/users", alpha.org_id);
+    let member_uri = format!("{}/{}", users_uri, member_id);
+    let login = || Some(json!({ "email": email, "password": "correct horse battery staple" }));
+
+    let (status, listed) = send(
+        &state,
+        &alpha.token,
+        Method::GET,
+        format!("{}?search=MEMBER-&status=active", users_uri),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", listed);
+    assert_eq!(listed["data"]["total"], 1);
+    assert_eq!(listed["data"]["users"][0]["email"], email);
+    assert!(listed["data"]["users"][0].get("password_hash").is_none());
+
+    // Other orgs cannot see or touch the users
+    let (status, _) = send(&state, &beta.token, Method::GET, users_uri.clone(), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send(
+        &state,
+        &beta.token,
+        Method::DELETE,
+        format!("/api/v1/orgs/{}/users/{}", beta.org_id, member_id),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // Admins cannot lock themselves out
+    let (status, _) = send(
+        &state,
+        &alpha.token,
+        Method::PATCH,
+        format!("{}/{}", users_uri, admin_id),
+        Some(json!({ "role": "Viewer" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let (status, updated) = send(
+        &state,
+        &alpha.token,
+        Method::PATCH,
+        member_uri.clone(),
+        Some(json!({ "role": "User" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(updated["data"]["role"], "User");
+
+    let (status, signed_in) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/login".to_string(),
+        login(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let access_token = signed_in["data"]["access_token"].as_str().unwrap();
+    let refresh_token = signed_in["data"]["refresh_token"].as_str().unwrap();
+
+    // Only admins manage users, and nobody sees a password hash
+    let (status, _) = send(&state, access_token, Method::GET, users_uri.clone(), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, me) = send(
+        &state,
+        access_token,
+        Method::GET,
+        "/api/v1/auth/me".to_string(),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(me["data"]["role"], "User");
+    assert!(me["data"].get("password_hash").is_none());
+
+    // Deactivating signs the user out everywhere and keeps them out
+    let (status, _) = send(
+        &state,
+        &alpha.token,
+        Method::POST,
+        format!("{}/deactivate", member_uri),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(
+        &state,
+        access_token,
+        Method::GET,
+        "/api/v1/auth/me".to_string(),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = send_refresh_token(&state, "/api/v1/auth/refresh", refresh_token).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/login".to_string(),
+        login(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = send(
+        &state,
+        &alpha.token,
+        Method::POST,
+        format!("{}/reactivate", member_uri),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/login".to_string(),
+        login(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // A forced reset refuses the old password until the emailed link is used
+    let (status, forced) = send(
+        &state,
+        &alpha.token,
+        Method::POST,
+        format!("{}/password-reset", member_uri),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(forced["data"]["password_reset_required"], true);
+    let (status, _) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/login".to_string(),
+        login(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let reset_token = link_token(&sent_email(&state, &email).await);
+    let (status, _) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/password/reset".to_string(),
+        Some(json!({ "token": reset_token, "password": "a brand new password" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/login".to_string(),
+        Some(json!({ "email": email, "password": "a brand new password" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = send(
+        &state,
+        &alpha.token,
+        Method::DELETE,
+        member_uri.clone(),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&state, &alpha.token, Method::GET, member_uri, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let sessions: i64 = client
+        .query_one(
+            "SELECT count(*) FROM sessions WHERE user_id = $1",
+            &[&member_id],
+        )
+        .await
+        .unwrap()
+        .get(0);
+    assert_eq!(sessions, 0);
+
+    cleanup(&state, &[&alpha, &beta]).await;
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL, run with `cargo test -- --ignored`"]
+async fn test_mfa_login_challenge() {
+    use argon2::password_hash::{PasswordHasher, SaltString};
+
+    let state = test_state().await;
+
+    let alpha = seed_tenant(&state).await;
+    let client = state.db_pool.get().await.unwrap();
+    let user = client
+        .query_one(
+            "SELECT id, email FROM users WHERE org_id = $1",
+            &[&alpha.org_id],
+        )
+        .await
+        .unwrap();
+    let (user_id, email): (Uuid, String) = (user.get(0), user.get(1));
+    let password_hash = state
+        .argon2
+        .hash_password(
+            b"correct horse battery staple",
+            &SaltString::encode_b64(b"mfa-test-salt").unwrap(),
+        )
+        .unwrap()
+        .to_string();
+    client
+        .execute(
+            "UPDATE users SET password_hash = $2 WHERE id = $1",
+            &[&user_id, &password_hash],
+        )
+        .await
+        .unwrap();
+    let login = json!({ "email": email, "password": "correct horse battery staple" });
+    let org_uri = format!("/api/v1/orgs/{}", alpha.org_id);
+
+    // Admins cannot require what they don't use themselves
+    let (status, _) = send(
+        &state,
+        &alpha.token,
+        Method::PATCH,
+        org_uri.clone(),
+        Some(json!({ "require_mfa": true })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let (status, enrollment) = send(
+        &state,
+        &alpha.token,
+        Method::POST,
+        "/api/v1/auth/mfa/totp/enroll".to_string(),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", enrollment);
+    let secret = enrollment["data"]["secret"].as_str().unwrap().to_string();
+    assert!(
+        enrollment["data"]["provisioning_uri"]
+            .as_str()
+            .unwrap()
+            .starts_with("otpauth://totp/")
+    );
+
+    // Until confirmed, logins are not challenged
+    let (status, _) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/login".to_string(),
+        Some(login.clone()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = send(
+        &state,
+        &alpha.token,
+        Method::POST,
+        "/api/v1/auth/mfa/totp/confirm".to_string(),
+        Some(json!({ "code": "000000x" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let confirmation_code = mfa::current_code(&secret);
+    let (status, confirmed) = send(
+        &state,
+        &alpha.token,
+        Method::POST,
+        "/api/v1/auth/mfa/totp/confirm".to_string(),
+        Some(json!({ "code": confirmation_code })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", confirmed);
+    let recovery_codes = confirmed["data"]["recovery_codes"].as_array().unwrap();
+    assert_eq!(recovery_codes.len(), 10);
+    let recovery_code = recovery_codes[0].as_str().unwrap().to_string();
+
+    // The password alone only gets a challenge, which is no access token
+    let (status, challenge) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/login".to_string(),
+        Some(login.clone()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert!(challenge["data"].get("access_token").is_none());
+    let challenge_token = challenge["data"]["challenge_token"].as_str().unwrap();
+
+    let (status, _) = send(
+        &state,
+        challenge_token,
+        Method::GET,
+        "/api/v1/auth/me".to_string(),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // The code that confirmed the enrollment cannot be replayed
+    let verify = |code: String| json!({ "challenge_token": challenge_token, "code": code });
+    let (status, _) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/mfa/verify".to_string(),
+        Some(verify(confirmation_code)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // As if the confirmation happened a step earlier
+    client
+        .execute(
+            "UPDATE user_mfa SET last_used_step = last_used_step - 1 WHERE user_id = $1",
+            &[&user_id],
+        )
+        .await
+        .unwrap();
+    let (status, session) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/mfa/verify".to_string(),
+        Some(verify(mfa::current_code(&secret))),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", session);
+    let (status, _) = send(
+        &state,
+        session["data"]["access_token"].as_str().unwrap(),
+        Method::GET,
+        "/api/v1/auth/me".to_string(),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Recovery codes work once, however they are typed
+    let (status, _) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/mfa/verify".to_string(),
+        Some(verify(recovery_code.to_uppercase())),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/mfa/verify".to_string(),
+        Some(verify(recovery_code)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, status_body) = send(
+        &state,
+        &alpha.token,
+        Method::GET,
+        "/api/v1/auth/mfa".to_string(),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(status_body["data"]["enabled"], true);
+    assert_eq!(status_body["data"]["recovery_codes_remaining"], 9);
+
+    // Once the org requires MFA, members without it can only enroll
+    let (status, _) = send(
+        &state,
+        &alpha.token,
+        Method::PATCH,
+        org_uri,
+        Some(json!({ "require_mfa": true })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let member_id: Uuid = client
+        .query_one(
+            "INSERT INTO users (org_id, email, password_hash, role)
+             VALUES ($1, $2, 'unused', $3) RETURNING id",
+            &[
+                &alpha.org_id,
+                &format!("member-{}", email),
+                &UserRole::Admin,
+            ],
+        )
+        .await
+        .unwrap()
+        .get(0);
+    let member_token = state
+        .jwt
+        .generate_token(member_id, TokenType::Access, API_AUDIENCE)
+        .unwrap();
+
+    let (status, _) = send(
+        &state,
+        &member_token,
+        Method::GET,
+        "/api/v1/projects".to_string(),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, member_status) = send(
+        &state,
+        &member_token,
+        Method::GET,
+        "/api/v1/auth/mfa".to_string(),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(member_status["data"]["required_by_org"], true);
+    let (status, _) = send(
+        &state,
+        &member_token,
+        Method::POST,
+        "/api/v1/auth/mfa/totp/enroll".to_string(),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = send(
+        &state,
+        &alpha.token,
+        Method::POST,
+        "/api/v1/auth/mfa/totp/disable".to_string(),
+        Some(json!({ "code": recovery_codes[1] })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = send(
+        &state,
+        &alpha.token,
+        Method::GET,
+        "/api/v1/projects".to_string(),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Wrong codes count against the account, guessing ends in a lockout
+    let (_, challenge) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/login".to_string(),
+        Some(login),
+    )
+    .await;
+    let challenge_token = challenge["data"]["challenge_token"].as_str().unwrap();
+    let verify = |code: String| json!({ "challenge_token": challenge_token, "code": code });
+    let mut statuses = Vec::new();
+    for _ in 0..state.config.lockout_threshold + 1 {
+        let (status, _) = send(
+            &state,
+            "",
+            Method::POST,
+            "/api/v1/auth/mfa/verify".to_string(),
+            Some(verify("000000".to_string())),
+        )
+        .await;
+        statuses.push(status);
+    }
+    assert_eq!(statuses.last(), Some(&StatusCode::TOO_MANY_REQUESTS));
+
+    // Not even the right code gets through until the lockout ends
+    let (status, _) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/mfa/verify".to_string(),
+        Some(verify(mfa::current_code(&secret))),
+    )
+    .await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+    cleanup(&state, &[&alpha]).await;
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL, run with `cargo test -- --ignored`"]
+async fn test_project_lifecycle() {
+    let state = test_state().await;
+
+    let alpha = seed_tenant(&state).await;
+
+    let (status, created) = send(
+        &state,
+        &alpha.token,
+        Method::POST,
+        "/api/v1/projects".to_string(),
+        Some(json!({ "name": "Storefront", "description": "Web shop" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", created);
+
+    // Default environments, each with its own server and client key
+    let environments = created["data"]["environments"].as_array().unwrap();
+    let names: Vec<&str> = environments
+        .iter()
+        .map(|created| created["environment"]["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["development", "staging", "production"]);
+    for created in environments {
+        let keys = created["api_keys"].as_array().unwrap();
+        assert_eq!(keys.len(), 2);
+        assert!(keys[0]["key"].as_str().unwrap().starts_with("vx_srv_"));
+        assert!(keys[1]["key"].as_str().unwrap().starts_with("vx_cli_"));
+    }
+
+    let project_id = created["data"]["project"]["id"].as_str().unwrap();
+    let project = format!("/api/v1/projects/{}", project_id);
+
+    let (status, _) = send(
+        &state,
+        &alpha.token,
+        Method::POST,
+        "/api/v1/projects".to_string(),
+        Some(json!({ "name": "Storefront" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (_, members) = send(
+        &state,
+        &alpha.token,
+        Method::GET,
+        format!("{}/members", project),
+        None,
+    )
+    .await;
+    assert_eq!(members["data"][0]["role"], "Owner");
+
+    // Flags created later get a state in every environment, enable it in production only
+    let (status, flag) = send(
+        &state,
+        &alpha.token,
+        Method::POST,
+        format!("{}/flags", project),
+        Some(json!({ "key": "banner", "type": "Boolean" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", flag);
+    let flag_id = flag["data"]["id"].as_str().unwrap();
+    let production = environments[2]["environment"]["id"].as_str().unwrap();
+
+    let (status, _) = send(
+        &state,
+        &alpha.token,
+        Method::PATCH,
+        format!("{}/flags/{}/environments/{}", project, flag_id, production),
+        Some(json!({ "is_enabled": true })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, cloned) = send(
+        &state,
+        &alpha.token,
+        Method::POST,
+        format!("{}/environments/{}/clone", project, production),
+        Some(json!({ "name": "production-eu" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", cloned);
+    assert_eq!(cloned["data"]["api_keys"].as_array().unwrap().len(), 2);
+    let cloned_id = cloned["data"]["environment"]["id"].as_str().unwrap();
+
+    let (_, states) = send(
+        &state,
+        &alpha.token,
+        Method::GET,
+        format!("{}/flags/{}/environments", project, flag_id),
+        None,
+    )
+    .await;
+    let enabled: Vec<&str> = states["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|state| state["is_enabled"] == true)
+        .map(|state| state["environment_id"].as_str().unwrap())
+        .collect();
+    assert_eq!(enabled.len(), 2);
+    assert!(enabled.contains(&production) && enabled.contains(&cloned_id));
+
+    let (status, _) = send(
+        &state,
+        &alpha.token,
+        Method::DELETE,
+        format!("{}/environments/{}", project, cloned_id),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, listed) = send(
+        &state,
+        &alpha.token,
+        Method::GET,
+        format!("{}/environments", project),
+        None,
+    )
+    .await;
+    assert_eq!(listed["data"].as_array().unwrap().len(), 3);
+
+    let (status, renamed) = send(
+        &state,
+        &alpha.token,
+        Method::PATCH,
+        project.clone(),
+        Some(json!({ "name": "Storefront v2" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(renamed["data"]["description"], "Web shop");
+
+    let (status, _) = send(&state, &alpha.token, Method::DELETE, project.clone(), None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = send(&state, &alpha.token, Method::GET, project, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    cleanup(&state, &[&alpha]).await;
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL, run with `cargo test -- --ignored`"]
+async fn test_refresh_tokens_rotate_and_detect_reuse() {
+    let state = test_state().await;
+
+    let email = format!("sessions-{}@example.com", Uuid::new_v4());
+    let password = "correct horse battery staple";
+
+    let (status, _) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/register".to_string(),
+        Some(json!({ "email": email, "password": password })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+
+    let registered = verify_email(&state, &email).await;
+    let access_token = registered["data"]["access_token"].as_str().unwrap();
+    let first = registered["data"]["refresh_token"].as_str().unwrap();
+
+    let (_, me) = send(
+        &state,
+        access_token,
+        Method::GET,
+        "/api/v1/auth/me".to_string(),
+        None,
+    )
+    .await;
+    let org_id: Uuid = serde_json::from_value(me["data"]["org_id"].clone()).unwrap();
+
+    // Refresh tokens are not accepted as bearer tokens
+    let (status, _) = send(
+        &state,
+        first,
+        Method::GET,
+        "/api/v1/auth/me".to_string(),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, refreshed) = send_refresh_token(&state, "/api/v1/auth/refresh", first).await;
+    assert_eq!(status, StatusCode::OK);
+    let second = refreshed["data"]["refresh_token"].as_str().unwrap();
+    assert_ne!(first, second);
+
+    // A second device signs in and sees both sessions
+    let (status, logged_in) = send(
+        &state,
+        "",
+        Method::POST,
+        "/api/v1/auth/login".to_string(),
+        Some(json!({ "email": email, "password": password })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let other_access_token = logged_in["data"]["access_token"].as_str().unwrap();
+    let other_refresh_token = logged_in["data"]["refresh_token"].as_str().unwrap();
+
+    let (_, listed) = send(
+        &state,
+        other_access_token,
+        Method::GET,
+        "/api/v1/auth/sessions".to_string(),
+        None,
+    )
+    .await;
+    let listed = listed["data"].as_array().unwrap();
+    assert_eq!(listed.len(), 2);
+    assert_eq!(listed.iter().filter(|s| s["current"] == true).count(), 1);
+
+    // Replaying the rotated token revokes its session, including the newer tokens
+    let (status, _) = send_refresh_token(&state, "/api/v1/auth/refresh", first).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = send_refresh_token(&state, "/api/v1/auth/refresh", second).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = send(
+        &state,
+        access_token,
+        Method::GET,
+        "/api/v1/auth/me".to_string(),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // The other device is unaffected until it logs out
+    let (_, listed) = send(
+        &state,
+        other_access_token,
+        Method::GET,
+        "/api/v1/auth/sessions".to_string(),
+        None,
+    )
+    .await;
+    assert_eq!(listed["data"].as_array().unwrap().len(), 1);
+
+    let (status, _) = send_refresh_token(&state, "/api/v1/auth/logout", other_refresh_token).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send_refresh_token(&state, "/api/v1/auth/refresh", other_refresh_token).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    delete_org(&state, org_id).await;
+    cleanup(&state, &[]).await;
+}
+
+/// A local identity provider, signing in whoever the test asks it to
+struct MockIdp {
+    issuer: String,
+    /// Code challenge and ID token claims of every code handed out
+    grants: std::sync::Mutex<std::collections::HashMap<String, (String, Value)>>,
+}
+
+impl MockIdp {
+    async fn start() -> Arc<Self> {
+        use axum::extract::{Form, State};
+        use axum::routing::{get, post};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let idp = Arc::new(MockIdp {
+            issuer: format!("http://{}", listener.local_addr().unwrap()),
+            grants: Default::default(),
+        });
+
+        let router = axum::Router::new()
+            .route(
+                "/.well-known/openid-configuration",
+                get(|State(idp): State<Arc<MockIdp>>| async move {
+                    axum::Json(json!({
+                        "issuer": idp.issuer,
+                        "authorization_endpoint": format!("{}/authorize", idp.issuer),
+                        "token_endpoint": format!("{}/token", idp.issuer),
+                        "jwks_uri": format!("{}/jwks", idp.issuer),
+                    }))
+                }),
+            )
+            .route(
+                "/jwks",
+                get(|| async { axum::Json(json!({ "keys": [test_key().jwk()] })) }),
+            )
+            .route(
+                "/token",
+                post(
+                    |State(idp): State<Arc<MockIdp>>,
+                     Form(form): Form<std::collections::HashMap<String, String>>| async move {
+                        let grant = idp.grants.lock().unwrap().remove(&form["code"]);
+                        match grant {
+                            Some((challenge, claims))
+                                if crate::pkg::oidc::pkce_challenge(&form["code_verifier"])
+                                    == challenge =>
+                            {
+                                let key = test_key();
+                                let mut header =
+                                    jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256);
+                                header.kid = Some(key.kid.clone());
+                                let id_token = jsonwebtoken::encode(
+                                    &header,
+                                    &claims,
+                                    &key.encoding_key().unwrap(),
+                                )
+                                .unwrap();
+                                (
+                                    StatusCode::OK,
+                                    axum::Json(json!({
+                                        "access_token": "unused",
+                                        "token_type": "Bearer",
+                                        "id_token": id_token,
+                                    })),
+                                )
+                            }
+                            _ => (
+                                StatusCode::BAD_REQUEST,
+                                axum::Json(json!({ "error": "invalid_grant" })),
+                            ),
+                        }
+                    },
+                ),
+            )
+            .with_state(idp.clone());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        idp
+    }
+
+    /// Sign `subject` in for an authorization URL, returning the code and state to call back with
+    fn sign_in(
+        &self,
+        authorization_url: &str,
+        subject: &str,
+        email: &str,
+        verified: bool,
+    ) -> Value {
+        let url = reqwest::Url::parse(authorization_url).unwrap();
+        let param = |name: &str| {
+            url.query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.to_string())
+                .unwrap()
+        };
+        let now = chrono::Utc::now().timestamp();
+        let code = Uuid::new_v4().to_string();
+
+        self.grants.lock().unwrap().insert(
+            code.clone(),
+            (
+                param("code_challenge"),
+                json!({
+                    "iss": self.issuer,
+                    "aud": param("client_id"),
+                    "sub": subject,
+                    "iat": now,
+                    "exp": now + 300,
+                    "nonce": param("nonce"),
+                    "email": email,
+                    "email_verified": verified,
+                }),
+            ),
+        );
+
+        json!({ "code": code, "state": param("state") })
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL, run with `cargo test -- --ignored`"]
+async fn test_oidc_sign_in() {
+    let state = test_state().await;
+
+    let (status, _) = send(
+        &state,
+        "",
+        Method::GET,
+        "/api/v1/auth/oidc/authorize".to_string(),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let alpha = seed_tenant(&state).await;
+    let client = state.db_pool.get().await.unwrap();
+    let admin = client
+        .query_one(
+            "SELECT u.id, u.email, o.slug FROM users u JOIN orgs o ON o.id = u.org_id
+             WHERE u.org_id = $1",
+            &[&alpha.org_id],
+        )
+        .await
+        .unwrap();
+    let (admin_id, admin_email, slug): (Uuid, String, String) =
+        (admin.get(0), admin.get(1), admin.get(2));
+
+    // New users join the tenant's org
+    let idp = MockIdp::start().await;
+    let mut config = (*state.config).clone();
+    config.oidc_issuer = Some(idp.issuer.clone());
+    config.oidc_client_id = Some("vexillum-test".to_string());
+    config.oidc_client_secret = Some("secret".to_string());
+    config.oidc_org = Some(slug);
+    let mut base = (*state).clone();
+    base.oidc = OidcProvider::from_config(&config).unwrap().map(Arc::new);
+    base.config = Arc::new(config);
+    let state: AppState = Arc::new(base);
+
+    let authorize = || async {
+        let (status, body) = send(
+            &state,
+            "",
+            Method::GET,
+            "/api/v1/auth/oidc/authorize".to_string(),
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        body["data"]["authorization_url"]
+            .as_str()
+            .unwrap()
+            .to_string()
+    };
+    let callback = |body: Value| async {
+        send(
+            &state,
+            "",
+            Method::POST,
+            "/api/v1/auth/oidc/callback".to_string(),
+            Some(body),
+        )
+        .await
+    };
+    let me = |token: String| {
+        let state = &state;
+        async move {
+            let (status, body) = send(
+                state,
+                &token,
+                Method::GET,
+                "/api/v1/auth/me".to_string(),
+                None,
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+            body["data"].clone()
+        }
+    };
+
+    let authorization_url = authorize().await;
+    assert!(authorization_url.starts_with(&format!("{}/authorize?", idp.issuer)));
+    assert!(authorization_url.contains("code_challenge_method=S256"));
+
+    let email = format!("sso-{}", admin_email);
+    let redirect = idp.sign_in(&authorization_url, "subject-1", &email, true);
+    let (status, session) = callback(redirect.clone()).await;
+    assert_eq!(status, StatusCode::OK, "{}", session);
+    let user = me(session["data"]["access_token"]
+        .as_str()
+        .unwrap()
+        .to_string())
+    .await;
+    assert_eq!(user["email"], email.as_str());
+    assert_eq!(user["role"], "Viewer");
+    assert_eq!(user["org_id"], alpha.org_id.to_string());
+
+    // Logins complete once
+    let (status, _) = callback(redirect).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // The identity keeps signing in to the same user
+    let redirect = idp.sign_in(&authorize().await, "subject-1", &email, true);
+    let (status, session) = callback(redirect).await;
+    assert_eq!(status, StatusCode::OK);
+    let again = me(session["data"]["access_token"]
+        .as_str()
+        .unwrap()
+        .to_string())
+    .await;
+    assert_eq!(again["id"], user["id"]);
+
+    // A code is useless without the verifier of the login it was issued for
+    let stolen = idp.sign_in(&authorize().await, "subject-1", &email, true);
+    let other = idp.sign_in(&authorize().await, "subject-1", &email, true);
+    let (status, _) = callback(json!({ "code": stolen["code"], "state": other["state"] })).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // Existing accounts are only linked through a verified email
+    let redirect = idp.sign_in(&authorize().await, "subject-2", &admin_email, false);
+    let (status, _) = callback(redirect).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let redirect = idp.sign_in(&authorize().await, "subject-2", &admin_email, true);
+    let (status, session) = callback(redirect).await;
+    assert_eq!(status, StatusCode::OK);
+    let linked = me(session["data"]["access_token"]
+        .as_str()
+        .unwrap()
+        .to_string())
+    .await;
+    assert_eq!(linked["id"], admin_id.to_string());
+
+    cleanup(&state, &[&alpha]).await;
+}
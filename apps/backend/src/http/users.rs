@@ -0,0 +1,590 @@
+use crate::http::auth::create_password_reset;
+use crate::models::db::Users;
+use crate::models::enums::UserRole;
+use crate::pkg::audit::{self, AuditEvent};
+use crate::pkg::auth::{AuthUser, OrgMember};
+use crate::pkg::emails::Template;
+use crate::pkg::error::AppError;
+use crate::pkg::permissions::require;
+use crate::pkg::response::DataResponse;
+use crate::pkg::sessions;
+use crate::pkg::state::AppState;
+use axum::{
+    Json, Router,
+    extract::{Path, Query, State},
+};
+use axum_extra::extract::WithRejection;
+use chrono::{DateTime, Utc};
+use pgmap::FromRow;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+#[derive(utoipa::OpenApi)]
+#[openapi(
+    paths(
+        list_users,
+        get_user,
+        update_user,
+        deactivate_user,
+        reactivate_user,
+        force_password_reset,
+        delete_user,
+    ),
+    components(
+        schemas(
+            UserSummary,
+            UserPage,
+            UserStatus,
+            UpdateUserRequest,
+        ),
+    ),
+    tags(
+        (name = "Users", description = "Managing the users of an organization"),
+    ),
+)]
+#[allow(dead_code)]
+pub struct UsersApi;
+
+const DEFAULT_PER_PAGE: i64 = 50;
+const MAX_PER_PAGE: i64 = 200;
+
+/// Filters shared by the count and page queries, in the order of `UserQuery`, then the org
+const USER_FILTER: &str = "WHERE ($1::text IS NULL OR email ILIKE '%' || $1 || '%')
+   AND ($2::user_role IS NULL OR role = $2)
+   AND ($3::text IS NULL
+        OR ($3 = 'active' AND deactivated_at IS NULL)
+        OR ($3 = 'deactivated' AND deactivated_at IS NOT NULL))
+   AND org_id = $4";
+
+/// A user as shown to them and to the admins of their org, without credentials
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct UserSummary {
+    pub id: Uuid,
+    pub org_id: Uuid,
+    pub email: String,
+    pub role: UserRole,
+    pub email_verified_at: Option<DateTime<Utc>>,
+    /// Deactivated users cannot sign in or refresh their sessions
+    pub deactivated_at: Option<DateTime<Utc>>,
+    /// The user has to reset their password before signing in with one
+    pub password_reset_required: bool,
+    /// Locked out after too many failed sign in attempts until then
+    pub locked_until: Option<DateTime<Utc>>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl From<Users> for UserSummary {
+    fn from(user: Users) -> Self {
+        UserSummary {
+            id: user.id,
+            org_id: user.org_id,
+            email: user.email,
+            role: user.role,
+            email_verified_at: user.email_verified_at,
+            deactivated_at: user.deactivated_at,
+            password_reset_required: user.password_reset_required,
+            locked_until: user.locked_until,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum UserStatus {
+    Active,
+    Deactivated,
+}
+
+impl UserStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            UserStatus::Active => "active",
+            UserStatus::Deactivated => "deactivated",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UserQuery {
+    /// Part of the email address
+    pub search: Option<String>,
+    pub role: Option<UserRole>,
+    pub status: Option<UserStatus>,
+    /// Page number, starting at 1
+    pub page: Option<i64>,
+    /// Users per page, at most 200
+    pub per_page: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct UserPage {
+    /// Matching users, by email
+    pub users: Vec<UserSummary>,
+    pub page: i64,
+    pub per_page: i64,
+    /// Number of matching users across all pages
+    pub total: i64,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct UpdateUserRequest {
+    pub role: UserRole,
+}
+
+pub(crate) fn parse_user(row: &tokio_postgres::Row) -> Result<Users, AppError> {
+    Users::from_row(row)
+        .map_err(|_| AppError::InternalError("Failed to parse user data".to_string()))
+}
+
+/// A user of the org, locked for the rest of the transaction
+async fn find_user(
+    client: &impl deadpool_postgres::GenericClient,
+    org_id: Uuid,
+    user_id: Uuid,
+) -> Result<Users, AppError> {
+    let row = client
+        .query_opt(
+            "SELECT * FROM users WHERE id = $1 AND org_id = $2 FOR UPDATE",
+            &[&user_id, &org_id],
+        )
+        .await?
+        .ok_or(AppError::NotFound("User not found".to_string()))?;
+
+    parse_user(&row)
+}
+
+/// Admins cannot lock themselves out, which also keeps an active admin in every org
+fn ensure_not_self(auth_user: &AuthUser, user_id: Uuid, action: &str) -> Result<(), AppError> {
+    if auth_user.id == user_id {
+        return Err(AppError::UnprocessableEntity(format!(
+            "You cannot {} yourself",
+            action
+        )));
+    }
+
+    Ok(())
+}
+
+/// List and search the users of an organization
+#[utoipa::path(
+    get,
+    path = "/v1/orgs/{org_id}/users",
+    params(
+        ("org_id" = Uuid, Path, description = "Organization ID"),
+        UserQuery,
+    ),
+    responses(
+        (status = 200, description = "Users", body = DataResponse<UserPage>),
+        (status = 400, description = "Invalid pagination", body = DataResponse<serde_json::Value>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "Forbidden", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "Organization not found", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Users"
+)]
+async fn list_users(
+    State(state): State<AppState>,
+    OrgMember(_auth_user, _): OrgMember<require::UserManage>,
+    Path(org_id): Path<Uuid>,
+    Query(query): Query<UserQuery>,
+) -> Result<Json<DataResponse<UserPage>>, AppError> {
+    let page = query.page.unwrap_or(1);
+    let per_page = query.per_page.unwrap_or(DEFAULT_PER_PAGE);
+    if page < 1 || !(1..=MAX_PER_PAGE).contains(&per_page) {
+        return Err(AppError::BadRequest(format!(
+            "page must be at least 1 and per_page between 1 and {}",
+            MAX_PER_PAGE
+        )));
+    }
+
+    let client = state.db_pool.get().await?;
+
+    // Wildcards in the search match literally
+    let search = query.search.as_deref().map(|search| {
+        search
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+    });
+    let status = query.status.as_ref().map(UserStatus::as_str);
+    let filters: [&(dyn tokio_postgres::types::ToSql + Sync); 4] =
+        [&search, &query.role, &status, &org_id];
+
+    let total: i64 = client
+        .query_one(
+            &format!("SELECT count(*) FROM users {}", USER_FILTER),
+            &filters,
+        )
+        .await?
+        .get(0);
+
+    let offset = (page - 1) * per_page;
+    let mut params = filters.to_vec();
+    params.push(&per_page);
+    params.push(&offset);
+
+    let rows = client
+        .query(
+            &format!(
+                "SELECT * FROM users {} ORDER BY email, id LIMIT $5 OFFSET $6",
+                USER_FILTER
+            ),
+            &params,
+        )
+        .await?;
+
+    let users = rows
+        .iter()
+        .map(|row| parse_user(row).map(UserSummary::from))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Json(
+        DataResponse::new()
+            .data(UserPage {
+                users,
+                page,
+                per_page,
+                total,
+            })
+            .build(),
+    ))
+}
+
+/// Get a user of an organization
+#[utoipa::path(
+    get,
+    path = "/v1/orgs/{org_id}/users/{user_id}",
+    params(
+        ("org_id" = Uuid, Path, description = "Organization ID"),
+        ("user_id" = Uuid, Path, description = "User ID"),
+    ),
+    responses(
+        (status = 200, description = "User", body = DataResponse<UserSummary>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "Forbidden", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "User not found", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Users"
+)]
+async fn get_user(
+    State(state): State<AppState>,
+    OrgMember(_auth_user, _): OrgMember<require::UserManage>,
+    Path((org_id, user_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<DataResponse<UserSummary>>, AppError> {
+    let client = state.db_pool.get().await?;
+
+    let row = client
+        .query_opt(
+            "SELECT * FROM users WHERE id = $1 AND org_id = $2",
+            &[&user_id, &org_id],
+        )
+        .await?
+        .ok_or(AppError::NotFound("User not found".to_string()))?;
+
+    Ok(Json(
+        DataResponse::new()
+            .data(UserSummary::from(parse_user(&row)?))
+            .build(),
+    ))
+}
+
+/// Change the role of a user
+#[utoipa::path(
+    patch,
+    path = "/v1/orgs/{org_id}/users/{user_id}",
+    params(
+        ("org_id" = Uuid, Path, description = "Organization ID"),
+        ("user_id" = Uuid, Path, description = "User ID"),
+    ),
+    request_body = UpdateUserRequest,
+    responses(
+        (status = 200, description = "User updated", body = DataResponse<UserSummary>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "Forbidden", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "User not found", body = DataResponse<serde_json::Value>),
+        (status = 422, description = "Admins cannot change their own role", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Users"
+)]
+async fn update_user(
+    State(state): State<AppState>,
+    OrgMember(auth_user, _): OrgMember<require::UserManage>,
+    Path((org_id, user_id)): Path<(Uuid, Uuid)>,
+    WithRejection(Json(payload), _): WithRejection<Json<UpdateUserRequest>, AppError>,
+) -> Result<Json<DataResponse<UserSummary>>, AppError> {
+    ensure_not_self(&auth_user, user_id, "change the role of")?;
+
+    let mut client = state.db_pool.get().await?;
+    let transaction = client.transaction().await?;
+
+    let before = UserSummary::from(find_user(&transaction, org_id, user_id).await?);
+
+    let row = transaction
+        .query_one(
+            "UPDATE users SET role = $2, updated_at = current_timestamp WHERE id = $1 RETURNING *",
+            &[&user_id, &payload.role],
+        )
+        .await?;
+    let user = UserSummary::from(parse_user(&row)?);
+
+    audit::record(
+        &transaction,
+        AuditEvent::new(&auth_user, "user.role_changed", "user", user_id)
+            .before(&before)
+            .after(&user),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(DataResponse::new().data(user).build()))
+}
+
+/// Deactivate a user, signing them out everywhere. They cannot sign in until reactivated.
+#[utoipa::path(
+    post,
+    path = "/v1/orgs/{org_id}/users/{user_id}/deactivate",
+    params(
+        ("org_id" = Uuid, Path, description = "Organization ID"),
+        ("user_id" = Uuid, Path, description = "User ID"),
+    ),
+    responses(
+        (status = 200, description = "User deactivated", body = DataResponse<UserSummary>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "Forbidden", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "User not found", body = DataResponse<serde_json::Value>),
+        (status = 422, description = "Admins cannot deactivate themselves", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Users"
+)]
+async fn deactivate_user(
+    State(state): State<AppState>,
+    OrgMember(auth_user, _): OrgMember<require::UserManage>,
+    Path((org_id, user_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<DataResponse<UserSummary>>, AppError> {
+    ensure_not_self(&auth_user, user_id, "deactivate")?;
+
+    let mut client = state.db_pool.get().await?;
+    let transaction = client.transaction().await?;
+
+    let before = UserSummary::from(find_user(&transaction, org_id, user_id).await?);
+
+    let row = transaction
+        .query_one(
+            "UPDATE users SET deactivated_at = COALESCE(deactivated_at, current_timestamp),
+                updated_at = current_timestamp
+             WHERE id = $1
+             RETURNING *",
+            &[&user_id],
+        )
+        .await?;
+    let user = UserSummary::from(parse_user(&row)?);
+    let revoked = sessions::revoke_all(&transaction, user_id, sessions::REVOKED_BY_ADMIN).await?;
+
+    audit::record(
+        &transaction,
+        AuditEvent::new(&auth_user, "user.deactivated", "user", user_id)
+            .before(&before)
+            .after(&serde_json::json!({ "user": user, "revoked_sessions": revoked })),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(DataResponse::new().data(user).build()))
+}
+
+/// Reactivate a deactivated user, letting them sign in again
+#[utoipa::path(
+    post,
+    path = "/v1/orgs/{org_id}/users/{user_id}/reactivate",
+    params(
+        ("org_id" = Uuid, Path, description = "Organization ID"),
+        ("user_id" = Uuid, Path, description = "User ID"),
+    ),
+    responses(
+        (status = 200, description = "User reactivated", body = DataResponse<UserSummary>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "Forbidden", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "User not found", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Users"
+)]
+async fn reactivate_user(
+    State(state): State<AppState>,
+    OrgMember(auth_user, _): OrgMember<require::UserManage>,
+    Path((org_id, user_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<DataResponse<UserSummary>>, AppError> {
+    let mut client = state.db_pool.get().await?;
+    let transaction = client.transaction().await?;
+
+    let before = UserSummary::from(find_user(&transaction, org_id, user_id).await?);
+
+    let row = transaction
+        .query_one(
+            "UPDATE users SET deactivated_at = NULL, updated_at = current_timestamp
+             WHERE id = $1
+             RETURNING *",
+            &[&user_id],
+        )
+        .await?;
+    let user = UserSummary::from(parse_user(&row)?);
+
+    audit::record(
+        &transaction,
+        AuditEvent::new(&auth_user, "user.reactivated", "user", user_id)
+            .before(&before)
+            .after(&user),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(DataResponse::new().data(user).build()))
+}
+
+/// Require a user to choose a new password. They are signed out everywhere and emailed a reset link,
+/// signing in with the old password is refused until they use it.
+#[utoipa::path(
+    post,
+    path = "/v1/orgs/{org_id}/users/{user_id}/password-reset",
+    params(
+        ("org_id" = Uuid, Path, description = "Organization ID"),
+        ("user_id" = Uuid, Path, description = "User ID"),
+    ),
+    responses(
+        (status = 200, description = "Password reset required and link sent", body = DataResponse<UserSummary>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "Forbidden", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "User not found", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Users"
+)]
+async fn force_password_reset(
+    State(state): State<AppState>,
+    OrgMember(auth_user, _): OrgMember<require::UserManage>,
+    Path((org_id, user_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<DataResponse<UserSummary>>, AppError> {
+    let mut client = state.db_pool.get().await?;
+    let transaction = client.transaction().await?;
+
+    let before = UserSummary::from(find_user(&transaction, org_id, user_id).await?);
+
+    let row = transaction
+        .query_one(
+            "UPDATE users SET password_reset_required = true, updated_at = current_timestamp
+             WHERE id = $1
+             RETURNING *",
+            &[&user_id],
+        )
+        .await?;
+    let user = UserSummary::from(parse_user(&row)?);
+    let revoked = sessions::revoke_all(&transaction, user_id, sessions::REVOKED_BY_ADMIN).await?;
+    let token = create_password_reset(&transaction, user_id).await?;
+
+    audit::record(
+        &transaction,
+        AuditEvent::new(&auth_user, "user.password_reset_required", "user", user_id)
+            .before(&before)
+            .after(&serde_json::json!({ "user": user, "revoked_sessions": revoked })),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    state
+        .mailer
+        .send(&user.email, Template::PasswordReset { token: &token });
+
+    Ok(Json(DataResponse::new().data(user).build()))
+}
+
+/// Delete a user along with their sessions. API keys they issued keep working.
+#[utoipa::path(
+    delete,
+    path = "/v1/orgs/{org_id}/users/{user_id}",
+    params(
+        ("org_id" = Uuid, Path, description = "Organization ID"),
+        ("user_id" = Uuid, Path, description = "User ID"),
+    ),
+    responses(
+        (status = 200, description = "User deleted", body = DataResponse<UserSummary>),
+        (status = 401, description = "Unauthorized", body = DataResponse<serde_json::Value>),
+        (status = 403, description = "Forbidden", body = DataResponse<serde_json::Value>),
+        (status = 404, description = "User not found", body = DataResponse<serde_json::Value>),
+        (status = 409, description = "The user owns projects", body = DataResponse<serde_json::Value>),
+        (status = 422, description = "Admins cannot delete themselves", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Users"
+)]
+async fn delete_user(
+    State(state): State<AppState>,
+    OrgMember(auth_user, _): OrgMember<require::UserManage>,
+    Path((org_id, user_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<DataResponse<UserSummary>>, AppError> {
+    ensure_not_self(&auth_user, user_id, "delete")?;
+
+    let mut client = state.db_pool.get().await?;
+    let transaction = client.transaction().await?;
+
+    let user = UserSummary::from(find_user(&transaction, org_id, user_id).await?);
+
+    // Projects are never left without an owner
+    let owned: i64 = transaction
+        .query_one(
+            "SELECT count(*) FROM project_owners WHERE user_id = $1 AND role = 'owner'",
+            &[&user_id],
+        )
+        .await?
+        .get(0);
+    if owned > 0 {
+        return Err(AppError::Conflict(
+            "Transfer ownership of the user's projects before deleting them".to_string(),
+        ));
+    }
+
+    // Sessions, memberships and pending links go with the user
+    transaction
+        .execute("DELETE FROM users WHERE id = $1", &[&user_id])
+        .await?;
+
+    audit::record(
+        &transaction,
+        AuditEvent::new(&auth_user, "user.deleted", "user", user_id).before(&user),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(DataResponse::new().data(user).build()))
+}
+
+pub fn router() -> Router<AppState> {
+    let user_routes = Router::new()
+        .route("/", axum::routing::get(list_users))
+        .route(
+            "/{user_id}",
+            axum::routing::get(get_user)
+                .patch(update_user)
+                .delete(delete_user),
+        )
+        .route(
+            "/{user_id}/deactivate",
+            axum::routing::post(deactivate_user),
+        )
+        .route(
+            "/{user_id}/reactivate",
+            axum::routing::post(reactivate_user),
+        )
+        .route(
+            "/{user_id}/password-reset",
+            axum::routing::post(force_password_reset),
+        );
+
+    Router::new().nest("/v1/orgs/{org_id}/users", user_routes)
+}
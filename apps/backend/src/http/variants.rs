@@ -0,0 +1,415 @@
+use crate::http::flags::ensure_flag;
+use crate::models::db::{FeatureFlagVariants, FeatureFlags};
+use crate::models::enums::{FeatureFlagType, ValueType};
+use crate::pkg::audit::{self, Actor, AuditEvent};
+use crate::pkg::auth::ProjectMember;
+use crate::pkg::error::AppError;
+use crate::pkg::permissions::require;
+use crate::pkg::response::DataResponse;
+use crate::pkg::state::AppState;
+use crate::pkg::variants::{self, ValueValidator};
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+};
+use axum_extra::extract::WithRejection;
+use pgmap::FromRow;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(utoipa::OpenApi)]
+#[openapi(
+    paths(
+        list_variants,
+        create_variant,
+        update_variant,
+        delete_variant,
+    ),
+    components(
+        schemas(
+            CreateVariantRequest,
+            UpdateVariantRequest,
+            FeatureFlagVariants,
+            ValueType,
+        ),
+    ),
+    tags(
+        (name = "Variants", description = "Multivariate flag variant endpoints"),
+    ),
+)]
+#[allow(dead_code)]
+pub struct VariantsApi;
+
+const MAX_NAME_LENGTH: usize = 100;
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CreateVariantRequest {
+    pub name: String,
+    pub value_type: ValueType,
+    /// Value served for this variant, must match `value_type`
+    pub value: serde_json::Value,
+    pub description: Option<String>,
+}
+
+/// Only the name and description can change, states and overrides refer to variants by value
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct UpdateVariantRequest {
+    pub name: Option<String>,
+    pub description: Option<String>,
+}
+
+fn validate_name(name: &str) -> Result<(), AppError> {
+    if name.trim().is_empty() || name.len() > MAX_NAME_LENGTH {
+        return Err(AppError::UnprocessableEntity(format!(
+            "name: must be between 1 and {} characters",
+            MAX_NAME_LENGTH
+        )));
+    }
+
+    Ok(())
+}
+
+fn parse_variant(row: &tokio_postgres::Row) -> Result<FeatureFlagVariants, AppError> {
+    FeatureFlagVariants::from_row(row)
+        .map_err(|_| AppError::InternalError("Failed to parse variant data".to_string()))
+}
+
+/// Load the variants of a flag, in the order they were declared
+pub(crate) async fn load_variants(
+    client: &impl deadpool_postgres::GenericClient,
+    flag_id: Uuid,
+) -> Result<Vec<FeatureFlagVariants>, AppError> {
+    let rows = client
+        .query(
+            "SELECT * FROM feature_flag_variants WHERE feature_flag_id = $1 ORDER BY created_at, id",
+            &[&flag_id],
+        )
+        .await?;
+
+    rows.iter().map(parse_variant).collect()
+}
+
+/// Build the validator for values written to a flag's states and overrides
+pub(crate) async fn value_validator(
+    client: &impl deadpool_postgres::GenericClient,
+    flag: &FeatureFlags,
+) -> Result<ValueValidator, AppError> {
+    let variants = match flag.r#type {
+        FeatureFlagType::Multivariate => load_variants(client, flag.id).await?,
+        _ => Vec::new(),
+    };
+
+    ValueValidator::new(flag, variants)
+}
+
+/// Insert and record a variant, checking its type, name and value are usable on the flag
+pub(crate) async fn insert_variant(
+    client: &impl deadpool_postgres::GenericClient,
+    actor: Actor,
+    flag: &FeatureFlags,
+    variant: &CreateVariantRequest,
+) -> Result<FeatureFlagVariants, AppError> {
+    if !matches!(flag.r#type, FeatureFlagType::Multivariate) {
+        return Err(AppError::UnprocessableEntity(
+            "Variants are only supported on multivariate flags".to_string(),
+        ));
+    }
+
+    validate_name(&variant.name)?;
+    variants::validate_variant_value(&variant.value_type, &variant.value)?;
+
+    let existing = client
+        .query_opt(
+            "SELECT name FROM feature_flag_variants
+             WHERE feature_flag_id = $1 AND (name = $2 OR value = $3)",
+            &[&flag.id, &variant.name, &variant.value],
+        )
+        .await?;
+
+    if let Some(existing) = existing {
+        let name: String = existing.get(0);
+        return Err(AppError::Conflict(if name == variant.name {
+            "Variant name already exists".to_string()
+        } else {
+            format!("Variant {} already has this value", name)
+        }));
+    }
+
+    let row = client
+        .query_one(
+            "INSERT INTO feature_flag_variants (feature_flag_id, name, value_type, value, description)
+             VALUES ($1, $2, $3, $4, $5)
+             RETURNING *",
+            &[
+                &flag.id,
+                &variant.name,
+                &variant.value_type,
+                &variant.value,
+                &variant.description,
+            ],
+        )
+        .await?;
+    let created = parse_variant(&row)?;
+
+    let mut event =
+        AuditEvent::new(actor, "variant.created", "variant", created.id).after(&created);
+    if let Some(project_id) = flag.project_id {
+        event = event.project(project_id);
+    }
+    audit::record(client, event).await?;
+
+    Ok(created)
+}
+
+/// List the variants of a multivariate flag
+#[utoipa::path(
+    get,
+    path = "/v1/projects/{project_id}/flags/{flag_id}/variants",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+        ("flag_id" = Uuid, Path, description = "Feature flag ID"),
+    ),
+    responses(
+        (status = 200, description = "Variants", body = DataResponse<Vec<FeatureFlagVariants>>),
+        (status = 404, description = "Feature flag not found", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Variants"
+)]
+async fn list_variants(
+    State(state): State<AppState>,
+    ProjectMember(_auth_user, _): ProjectMember<require::FlagRead>,
+    Path((project_id, flag_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<DataResponse<Vec<FeatureFlagVariants>>>, AppError> {
+    let client = state.db_pool.get().await?;
+
+    ensure_flag(&client, project_id, flag_id).await?;
+
+    let variants = load_variants(&client, flag_id).await?;
+
+    Ok(Json(DataResponse::new().data(variants).build()))
+}
+
+/// Declare a new variant on a multivariate flag
+#[utoipa::path(
+    post,
+    path = "/v1/projects/{project_id}/flags/{flag_id}/variants",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+        ("flag_id" = Uuid, Path, description = "Feature flag ID"),
+    ),
+    request_body = CreateVariantRequest,
+    responses(
+        (status = 200, description = "Variant created", body = DataResponse<FeatureFlagVariants>),
+        (status = 404, description = "Feature flag not found", body = DataResponse<serde_json::Value>),
+        (status = 409, description = "Variant name or value already exists", body = DataResponse<serde_json::Value>),
+        (status = 422, description = "Invalid variant", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Variants"
+)]
+async fn create_variant(
+    State(state): State<AppState>,
+    ProjectMember(auth_user, _): ProjectMember<require::FlagWrite>,
+    Path((project_id, flag_id)): Path<(Uuid, Uuid)>,
+    WithRejection(Json(payload), _): WithRejection<Json<CreateVariantRequest>, AppError>,
+) -> Result<Json<DataResponse<FeatureFlagVariants>>, AppError> {
+    let mut client = state.db_pool.get().await?;
+
+    let flag = ensure_flag(&client, project_id, flag_id).await?;
+
+    let transaction = client.transaction().await?;
+    let variant = insert_variant(&transaction, (&auth_user).into(), &flag, &payload).await?;
+    transaction.commit().await?;
+
+    Ok(Json(DataResponse::new().data(variant).build()))
+}
+
+/// Rename or describe a variant
+#[utoipa::path(
+    patch,
+    path = "/v1/projects/{project_id}/flags/{flag_id}/variants/{variant_id}",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+        ("flag_id" = Uuid, Path, description = "Feature flag ID"),
+        ("variant_id" = Uuid, Path, description = "Variant ID"),
+    ),
+    request_body = UpdateVariantRequest,
+    responses(
+        (status = 200, description = "Variant updated", body = DataResponse<FeatureFlagVariants>),
+        (status = 404, description = "Variant not found", body = DataResponse<serde_json::Value>),
+        (status = 409, description = "Variant name already exists", body = DataResponse<serde_json::Value>),
+        (status = 422, description = "Invalid variant", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Variants"
+)]
+async fn update_variant(
+    State(state): State<AppState>,
+    ProjectMember(auth_user, _): ProjectMember<require::FlagWrite>,
+    Path((project_id, flag_id, variant_id)): Path<(Uuid, Uuid, Uuid)>,
+    WithRejection(Json(payload), _): WithRejection<Json<UpdateVariantRequest>, AppError>,
+) -> Result<Json<DataResponse<FeatureFlagVariants>>, AppError> {
+    let mut client = state.db_pool.get().await?;
+
+    ensure_flag(&client, project_id, flag_id).await?;
+
+    if let Some(name) = &payload.name {
+        validate_name(name)?;
+
+        let existing = client
+            .query_opt(
+                "SELECT id FROM feature_flag_variants
+                 WHERE feature_flag_id = $1 AND name = $2 AND id <> $3",
+                &[&flag_id, name, &variant_id],
+            )
+            .await?;
+
+        if existing.is_some() {
+            return Err(AppError::Conflict(
+                "Variant name already exists".to_string(),
+            ));
+        }
+    }
+
+    let transaction = client.transaction().await?;
+
+    let before = transaction
+        .query_opt(
+            "SELECT * FROM feature_flag_variants
+             WHERE id = $1 AND feature_flag_id = $2
+             FOR UPDATE",
+            &[&variant_id, &flag_id],
+        )
+        .await?
+        .ok_or(AppError::NotFound("Variant not found".to_string()))?;
+    let before = parse_variant(&before)?;
+
+    let row = transaction
+        .query_one(
+            "UPDATE feature_flag_variants SET
+                name = COALESCE($2, name),
+                description = COALESCE($3, description),
+                updated_at = current_timestamp
+             WHERE id = $1
+             RETURNING *",
+            &[&variant_id, &payload.name, &payload.description],
+        )
+        .await?;
+    let variant = parse_variant(&row)?;
+
+    audit::record(
+        &transaction,
+        AuditEvent::new(&auth_user, "variant.updated", "variant", variant.id)
+            .project(project_id)
+            .before(&before)
+            .after(&variant),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(DataResponse::new().data(variant).build()))
+}
+
+/// Delete a variant that no flag state, override or rollout serves anymore
+#[utoipa::path(
+    delete,
+    path = "/v1/projects/{project_id}/flags/{flag_id}/variants/{variant_id}",
+    params(
+        ("project_id" = Uuid, Path, description = "Project ID"),
+        ("flag_id" = Uuid, Path, description = "Feature flag ID"),
+        ("variant_id" = Uuid, Path, description = "Variant ID"),
+    ),
+    responses(
+        (status = 200, description = "Variant deleted", body = DataResponse<FeatureFlagVariants>),
+        (status = 404, description = "Variant not found", body = DataResponse<serde_json::Value>),
+        (status = 409, description = "Variant is still in use", body = DataResponse<serde_json::Value>),
+    ),
+    tag = "Variants"
+)]
+async fn delete_variant(
+    State(state): State<AppState>,
+    ProjectMember(auth_user, _): ProjectMember<require::FlagWrite>,
+    Path((project_id, flag_id, variant_id)): Path<(Uuid, Uuid, Uuid)>,
+) -> Result<Json<DataResponse<FeatureFlagVariants>>, AppError> {
+    let mut client = state.db_pool.get().await?;
+
+    ensure_flag(&client, project_id, flag_id).await?;
+
+    let variant = client
+        .query_opt(
+            "SELECT * FROM feature_flag_variants WHERE id = $1 AND feature_flag_id = $2",
+            &[&variant_id, &flag_id],
+        )
+        .await?
+        .ok_or(AppError::NotFound("Variant not found".to_string()))?;
+    let variant = parse_variant(&variant)?;
+
+    // Served values are stored by value, so a variant in use cannot go away underneath them
+    let in_use = client
+        .query_one(
+            "SELECT EXISTS (
+                SELECT 1 FROM feature_flag_environments
+                WHERE feature_flag_id = $1
+                  AND (value = $2 OR rollout->'variants' @> jsonb_build_array(jsonb_build_object('value', $2)))
+                UNION ALL
+                SELECT 1 FROM feature_flag_overrides
+                WHERE feature_flag_id = $1
+                  AND (value = $2 OR rollout->'variants' @> jsonb_build_array(jsonb_build_object('value', $2)))
+             )",
+            &[&flag_id, &variant.value],
+        )
+        .await?;
+
+    if in_use.get::<_, bool>(0) {
+        return Err(AppError::Conflict(
+            "Variant is still served by a flag state, override or rollout".to_string(),
+        ));
+    }
+
+    let transaction = client.transaction().await?;
+
+    transaction
+        .execute(
+            "DELETE FROM feature_flag_variants WHERE id = $1",
+            &[&variant_id],
+        )
+        .await?;
+
+    audit::record(
+        &transaction,
+        AuditEvent::new(&auth_user, "variant.deleted", "variant", variant.id)
+            .project(project_id)
+            .before(&variant),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(DataResponse::new().data(variant).build()))
+}
+
+pub fn router() -> Router<AppState> {
+    let variant_routes = Router::new()
+        .route("/", axum::routing::get(list_variants).post(create_variant))
+        .route(
+            "/{variant_id}",
+            axum::routing::patch(update_variant).delete(delete_variant),
+        );
+
+    Router::new().nest(
+        "/v1/projects/{project_id}/flags/{flag_id}/variants",
+        variant_routes,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_name() {
+        assert!(validate_name("treatment").is_ok());
+        assert!(validate_name(" ").is_err());
+        assert!(validate_name(&"a".repeat(MAX_NAME_LENGTH + 1)).is_err());
+    }
+}
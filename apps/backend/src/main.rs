@@ -1,4 +1,5 @@
-use pkg::config::Config;
+use pkg::config::{Command, Config};
+use pkg::keys::KeyManager;
 use pkg::state::BaseState;
 
 mod http;
@@ -11,6 +12,28 @@ async fn main() {
     let config = Config::load();
     env_logger::init();
 
+    if let Some(Command::RotateKeys) = config.command {
+        if config.jwt_private_key.is_some() || config.jwt_private_key_file.is_some() {
+            eprintln!("The signing key is configured through the environment, rotate it there");
+            return;
+        }
+        let rotation = KeyManager::new(&config.keys_dir)
+            .rotate(config.jwt_algorithm, config.key_grace_period)
+            .expect("Failed to rotate signing keys");
+        println!("Signing with new key {}", rotation.promoted);
+        if let Some(retired) = rotation.retired {
+            println!(
+                "Retired key {}, it verifies tokens for another {} seconds",
+                retired, config.key_grace_period
+            );
+        }
+        for kid in rotation.pruned {
+            println!("Deleted expired key {}", kid);
+        }
+        println!("Restart running servers to start signing with the new key");
+        return;
+    }
+
     config.print_summary();
 
     // Initialize app state (handles database connection)
@@ -33,5 +56,10 @@ async fn main() {
 
     println!("Frontend available at {}", state.config.frontend_url);
 
-    axum::serve(listener, app).await.expect("Server error");
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+    )
+    .await
+    .expect("Server error");
 }
@@ -12,9 +12,13 @@ pub struct ApiKeys {
     pub user_id: Option<Uuid>,
     pub environment_id: Option<Uuid>,
     pub is_server_key: Option<bool>,
+    #[serde(skip_serializing)]
     pub key_hash: String,
     pub created_at: Option<DateTime<Utc>>,
     pub updated_at: Option<DateTime<Utc>>,
+    pub key_prefix: String,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
 }
 #[derive(FromRow, Serialize, Deserialize, ToSchema)]
 pub struct Audiences {
@@ -30,6 +34,32 @@ pub struct Audiences {
     pub updated_at: Option<DateTime<Utc>>,
 }
 #[derive(FromRow, Serialize, Deserialize, ToSchema)]
+pub struct AuditEvents {
+    pub id: Uuid,
+    pub actor_user_id: Option<Uuid>,
+    pub actor_api_key_id: Option<Uuid>,
+    pub action: String,
+    pub entity_type: String,
+    pub entity_id: Uuid,
+    pub project_id: Option<Uuid>,
+    pub environment_id: Option<Uuid>,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+    pub diff: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+    pub org_id: Option<Uuid>,
+}
+#[derive(FromRow, Serialize, Deserialize, ToSchema)]
+pub struct EmailVerifications {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+#[derive(FromRow, Serialize, Deserialize, ToSchema)]
 pub struct Environments {
     pub id: Uuid,
     pub project_id: Option<Uuid>,
@@ -38,6 +68,17 @@ pub struct Environments {
     pub updated_at: Option<DateTime<Utc>>,
 }
 #[derive(FromRow, Serialize, Deserialize, ToSchema)]
+pub struct FeatureFlagEnvironments {
+    pub id: Uuid,
+    pub feature_flag_id: Uuid,
+    pub environment_id: Uuid,
+    pub is_enabled: bool,
+    pub value: Option<serde_json::Value>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+    pub rollout: Option<serde_json::Value>,
+}
+#[derive(FromRow, Serialize, Deserialize, ToSchema)]
 pub struct FeatureFlagOverrides {
     pub id: Uuid,
     pub feature_flag_id: Option<Uuid>,
@@ -47,18 +88,30 @@ pub struct FeatureFlagOverrides {
     pub value: Option<serde_json::Value>,
     pub created_at: Option<DateTime<Utc>>,
     pub updated_at: Option<DateTime<Utc>>,
+    pub environment_id: Uuid,
+    pub rollout: Option<serde_json::Value>,
+}
+#[derive(FromRow, Serialize, Deserialize, ToSchema)]
+pub struct FeatureFlagVariants {
+    pub id: Uuid,
+    pub feature_flag_id: Uuid,
+    pub name: String,
+    pub value_type: ValueType,
+    pub value: serde_json::Value,
+    pub description: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
 }
 #[derive(FromRow, Serialize, Deserialize, ToSchema)]
 pub struct FeatureFlags {
     pub id: Uuid,
     pub org_id: Option<Uuid>,
     pub key: String,
-    pub is_enabled: Option<bool>,
     pub r#type: FeatureFlagType,
-    pub value: Option<serde_json::Value>,
     pub project_id: Option<Uuid>,
     pub created_at: Option<DateTime<Utc>>,
     pub updated_at: Option<DateTime<Utc>>,
+    pub json_schema: Option<serde_json::Value>,
 }
 #[derive(FromRow, Serialize, Deserialize, ToSchema)]
 pub struct MagicLinks {
@@ -69,6 +122,38 @@ pub struct MagicLinks {
     pub created_at: Option<DateTime<Utc>>,
 }
 #[derive(FromRow, Serialize, Deserialize, ToSchema)]
+pub struct MfaRecoveryCodes {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    #[serde(skip_serializing)]
+    pub code_hash: String,
+    pub used_at: Option<DateTime<Utc>>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+#[derive(FromRow, Serialize, Deserialize, ToSchema)]
+pub struct OidcLogins {
+    pub id: Uuid,
+    #[serde(skip_serializing)]
+    pub state_hash: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+#[derive(FromRow, Serialize, Deserialize, ToSchema)]
+pub struct OrgInvitations {
+    pub id: Uuid,
+    pub org_id: Uuid,
+    pub email: String,
+    pub role: UserRole,
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+    pub invited_by: Option<Uuid>,
+    pub expires_at: DateTime<Utc>,
+    pub accepted_at: Option<DateTime<Utc>>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+#[derive(FromRow, Serialize, Deserialize, ToSchema)]
 pub struct Orgs {
     pub id: Uuid,
     pub slug: String,
@@ -76,28 +161,80 @@ pub struct Orgs {
     pub region: String,
     pub created_at: Option<DateTime<Utc>>,
     pub updated_at: Option<DateTime<Utc>>,
+    pub require_mfa: bool,
+    pub registration_mode: RegistrationMode,
+    pub allowed_email_domains: serde_json::Value,
+}
+#[derive(FromRow, Serialize, Deserialize, ToSchema)]
+pub struct PasswordResets {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+    pub created_at: Option<DateTime<Utc>>,
 }
 #[derive(FromRow, Serialize, Deserialize, ToSchema)]
 pub struct ProjectOwners {
     pub project_id: Uuid,
     pub user_id: Uuid,
+    pub role: ProjectRole,
+    pub created_at: Option<DateTime<Utc>>,
 }
 #[derive(FromRow, Serialize, Deserialize, ToSchema)]
 pub struct Projects {
     pub id: Uuid,
-    pub org_id: Option<Uuid>,
+    pub org_id: Uuid,
     pub name: String,
     pub description: Option<String>,
     pub created_at: Option<DateTime<Utc>>,
     pub updated_at: Option<DateTime<Utc>>,
 }
 #[derive(FromRow, Serialize, Deserialize, ToSchema)]
+pub struct Sessions {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub refresh_jti: Uuid,
+    pub user_agent: Option<String>,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub revoked_reason: Option<String>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+#[derive(FromRow, Serialize, Deserialize, ToSchema)]
+pub struct UserIdentities {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub issuer: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub last_login_at: Option<DateTime<Utc>>,
+}
+#[derive(FromRow, Serialize, Deserialize, ToSchema)]
+pub struct UserMfa {
+    pub user_id: Uuid,
+    #[serde(skip_serializing)]
+    pub totp_secret: String,
+    pub enabled_at: Option<DateTime<Utc>>,
+    pub last_used_step: Option<i64>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+#[derive(FromRow, Serialize, Deserialize, ToSchema)]
 pub struct Users {
     pub id: Uuid,
-    pub org_id: Option<Uuid>,
+    pub org_id: Uuid,
     pub email: String,
+    #[serde(skip_serializing)]
     pub password_hash: String,
     pub role: UserRole,
     pub created_at: Option<DateTime<Utc>>,
     pub updated_at: Option<DateTime<Utc>>,
+    pub failed_login_attempts: i32,
+    pub locked_until: Option<DateTime<Utc>>,
+    pub email_verified_at: Option<DateTime<Utc>>,
+    pub deactivated_at: Option<DateTime<Utc>>,
+    pub password_reset_required: bool,
 }
@@ -25,6 +25,18 @@ postgres_enum!(MatchOperator, "match_operator",
     Ncontains => "ncontains",
 );
 
+postgres_enum!(ProjectRole, "project_role",
+    Owner => "owner",
+    Editor => "editor",
+    Viewer => "viewer",
+);
+
+postgres_enum!(RegistrationMode, "registration_mode",
+    Open => "open",
+    InviteOnly => "invite_only",
+    AllowedDomains => "allowed_domains",
+);
+
 postgres_enum!(UserRole, "user_role",
     Admin => "admin",
     User => "user",
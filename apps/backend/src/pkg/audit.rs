@@ -0,0 +1,182 @@
+use crate::pkg::auth::{ApiKeyAuth, AuthUser};
+use crate::pkg::error::AppError;
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+use uuid::Uuid;
+
+/// Who made a change
+#[derive(Debug, Clone, Copy)]
+pub enum Actor {
+    User(Uuid),
+    ApiKey(Uuid),
+}
+
+impl From<&AuthUser> for Actor {
+    fn from(user: &AuthUser) -> Self {
+        Actor::User(user.id)
+    }
+}
+
+impl From<&ApiKeyAuth> for Actor {
+    fn from(api_key: &ApiKeyAuth) -> Self {
+        Actor::ApiKey(api_key.id)
+    }
+}
+
+/// A configuration change to record, see [`record`]
+pub struct AuditEvent {
+    pub actor: Actor,
+    /// What happened, as `<entity_type>.<verb>`, e.g. `flag.updated`
+    pub action: &'static str,
+    pub entity_type: &'static str,
+    pub entity_id: Uuid,
+    pub project_id: Option<Uuid>,
+    pub environment_id: Option<Uuid>,
+    /// The entity before the change, `None` when it was created
+    pub before: Option<Value>,
+    /// The entity after the change, `None` when it was deleted
+    pub after: Option<Value>,
+}
+
+impl AuditEvent {
+    pub fn new(
+        actor: impl Into<Actor>,
+        action: &'static str,
+        entity_type: &'static str,
+        entity_id: Uuid,
+    ) -> Self {
+        AuditEvent {
+            actor: actor.into(),
+            action,
+            entity_type,
+            entity_id,
+            project_id: None,
+            environment_id: None,
+            before: None,
+            after: None,
+        }
+    }
+
+    pub fn project(mut self, project_id: Uuid) -> Self {
+        self.project_id = Some(project_id);
+        self
+    }
+
+    pub fn environment(mut self, environment_id: Uuid) -> Self {
+        self.environment_id = Some(environment_id);
+        self
+    }
+
+    pub fn before(mut self, before: &impl Serialize) -> Self {
+        self.before = serde_json::to_value(before).ok();
+        self
+    }
+
+    pub fn after(mut self, after: &impl Serialize) -> Self {
+        self.after = serde_json::to_value(after).ok();
+        self
+    }
+}
+
+/// Fields that differ between two snapshots of an entity, as `{ field: { before, after } }`.
+/// Timestamps maintained by the database are left out, they change on every write.
+pub fn diff(before: Option<&Value>, after: Option<&Value>) -> Value {
+    let empty = Map::new();
+    let before = before.and_then(Value::as_object).unwrap_or(&empty);
+    let after = after.and_then(Value::as_object).unwrap_or(&empty);
+
+    let mut changes = Map::new();
+    for key in before.keys().chain(after.keys()) {
+        if matches!(key.as_str(), "created_at" | "updated_at") || changes.contains_key(key) {
+            continue;
+        }
+
+        let old = before.get(key).unwrap_or(&Value::Null);
+        let new = after.get(key).unwrap_or(&Value::Null);
+        if old != new {
+            changes.insert(key.clone(), json!({ "before": old, "after": new }));
+        }
+    }
+
+    Value::Object(changes)
+}
+
+/// Record a change, with the same client or transaction as the change itself
+/// so the history never disagrees with the data.
+/// The event is attributed to the org of its project, or of the acting user for org-wide changes.
+pub async fn record(
+    client: &impl deadpool_postgres::GenericClient,
+    event: AuditEvent,
+) -> Result<(), AppError> {
+    let (actor_user_id, actor_api_key_id) = match event.actor {
+        Actor::User(id) => (Some(id), None),
+        Actor::ApiKey(id) => (None, Some(id)),
+    };
+    let diff = diff(event.before.as_ref(), event.after.as_ref());
+
+    client
+        .execute(
+            "INSERT INTO audit_events
+                (actor_user_id, actor_api_key_id, action, entity_type, entity_id,
+                 project_id, environment_id, before, after, diff, org_id)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, COALESCE(
+                (SELECT org_id FROM projects WHERE id = $6),
+                (SELECT org_id FROM users WHERE id = $1)
+             ))",
+            &[
+                &actor_user_id,
+                &actor_api_key_id,
+                &event.action,
+                &event.entity_type,
+                &event.entity_id,
+                &event.project_id,
+                &event.environment_id,
+                &event.before,
+                &event.after,
+                &diff,
+            ],
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let before = json!({
+            "is_enabled": true,
+            "value": { "a": 1 },
+            "key": "checkout",
+            "updated_at": "2026-01-01T00:00:00Z",
+        });
+        let after = json!({
+            "is_enabled": false,
+            "value": { "a": 1 },
+            "key": "checkout",
+            "updated_at": "2026-01-02T00:00:00Z",
+        });
+
+        assert_eq!(
+            diff(Some(&before), Some(&after)),
+            json!({ "is_enabled": { "before": true, "after": false } })
+        );
+    }
+
+    #[test]
+    fn test_diff_create_and_delete() {
+        let entity = json!({ "key": "checkout", "created_at": "2026-01-01T00:00:00Z" });
+
+        assert_eq!(
+            diff(None, Some(&entity)),
+            json!({ "key": { "before": null, "after": "checkout" } })
+        );
+        assert_eq!(
+            diff(Some(&entity), None),
+            json!({ "key": { "before": "checkout", "after": null } })
+        );
+    }
+}
@@ -1,14 +1,56 @@
+use crate::models::enums::{ProjectRole, UserRole};
+use crate::pkg::cache;
 use crate::pkg::error::AppError;
+use crate::pkg::jwt::{API_AUDIENCE, TokenType};
+use crate::pkg::permissions::{self, RequiredPermission};
 use crate::pkg::state::AppState;
-use axum::extract::{FromRef, FromRequestParts};
+use axum::extract::{FromRef, FromRequestParts, RawPathParams};
 use axum::http::request::Parts;
 use axum_auth::AuthBearer;
+use rand::TryRngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::marker::PhantomData;
 use uuid::Uuid;
 
+/// Header SDKs send their environment API key in
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Prefix of keys meant for backend SDKs
+pub const SERVER_KEY_PREFIX: &str = "vx_srv_";
+
+/// Prefix of keys meant for browser and mobile SDKs
+pub const CLIENT_KEY_PREFIX: &str = "vx_cli_";
+
+/// Number of random characters kept in the displayable key prefix
+const DISPLAY_PREFIX_CHARS: usize = 8;
+
 /// User context extracted from JWT access token
 #[derive(Clone, Debug)]
 pub struct AuthUser {
     pub id: Uuid,
+    /// Loaded on every request, so role changes apply without waiting for tokens to expire
+    pub role: UserRole,
+    /// The org the user belongs to, every query the user makes is confined to it
+    pub org_id: Uuid,
+    /// Session the access token was issued for, tokens without one predate sessions
+    pub session_id: Option<Uuid>,
+    /// The user's org requires MFA and the user has not enrolled yet,
+    /// only endpoints that plainly need an `AuthUser`, like enrollment, stay open
+    pub mfa_enrollment_required: bool,
+}
+
+impl AuthUser {
+    /// Reject users who have to enroll in MFA before doing anything else
+    fn require_mfa_enrollment(&self) -> Result<(), AppError> {
+        if self.mfa_enrollment_required {
+            return Err(AppError::Forbidden(
+                "Your organization requires multi-factor authentication, enroll first".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 impl<S> FromRequestParts<S> for AuthUser
@@ -30,16 +72,349 @@ where
                 })?;
 
         // Validate the token
-        let claims = app_state.jwt.validate_token(&token)?;
+        // Only access tokens, refresh tokens are exchanged at /v1/auth/refresh
+        let claims = app_state
+            .jwt
+            .validate_token(&token, TokenType::Access, API_AUDIENCE)?;
 
         // Parse user ID from claims
         let user_id = Uuid::parse_str(&claims.sub)
             .map_err(|_| AppError::InternalError("Invalid user ID in token".to_string()))?;
 
-        Ok(AuthUser { id: user_id })
+        let session_id = match claims.sid.as_deref() {
+            Some(sid) => Some(
+                Uuid::parse_str(sid)
+                    .map_err(|_| AppError::Unauthorized("Invalid session in token".to_string()))?,
+            ),
+            None => None,
+        };
+
+        // Revoking a session also cuts off the access tokens issued for it
+        let client = app_state.db_pool.get().await?;
+        let row = client
+            .query_opt(
+                "SELECT u.role, u.org_id,
+                        o.require_mfa AND NOT EXISTS (
+                            SELECT 1 FROM user_mfa m
+                            WHERE m.user_id = u.id AND m.enabled_at IS NOT NULL
+                        )
+                 FROM users u
+                 JOIN orgs o ON o.id = u.org_id
+                 WHERE u.id = $1 AND u.deactivated_at IS NULL
+                   AND ($2::uuid IS NULL OR EXISTS (
+                       SELECT 1 FROM sessions s
+                       WHERE s.id = $2 AND s.user_id = u.id AND s.revoked_at IS NULL
+                   ))",
+                &[&user_id, &session_id],
+            )
+            .await?
+            .ok_or(AppError::Unauthorized(
+                "User or session no longer exists or is deactivated".to_string(),
+            ))?;
+
+        Ok(AuthUser {
+            id: user_id,
+            role: row.get(0),
+            org_id: row.get(1),
+            session_id,
+            mfa_enrollment_required: row.get(2),
+        })
+    }
+}
+
+/// An authenticated user whose role grants the permission `P`,
+/// rejected with 403 otherwise
+pub struct Authorized<P>(pub AuthUser, pub PhantomData<P>);
+
+impl<S, P> FromRequestParts<S> for Authorized<P>
+where
+    S: Send + Sync,
+    AppState: FromRef<S>,
+    P: RequiredPermission,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let user = AuthUser::from_request_parts(parts, state).await?;
+        user.require_mfa_enrollment()?;
+
+        if !permissions::granted(user.role, P::PERMISSION) {
+            return Err(AppError::Forbidden(format!(
+                "Missing permission {}",
+                P::PERMISSION.as_str()
+            )));
+        }
+
+        Ok(Authorized(user, PhantomData))
+    }
+}
+
+/// Parse a uuid path parameter for extractors that run before `Path`
+async fn path_id<S: Send + Sync>(
+    parts: &mut Parts,
+    state: &S,
+    name: &str,
+) -> Result<Uuid, AppError> {
+    let params = RawPathParams::from_request_parts(parts, state)
+        .await
+        .map_err(|_| AppError::InternalError(format!("Missing {} path parameter", name)))?;
+
+    let (_, value) =
+        params
+            .iter()
+            .find(|(param, _)| *param == name)
+            .ok_or(AppError::InternalError(format!(
+                "Missing {} path parameter",
+                name
+            )))?;
+
+    Uuid::parse_str(value).map_err(|_| AppError::BadRequest(format!("Invalid {}", name)))
+}
+
+/// An authenticated user allowed to act on the project in the `project_id` path parameter
+/// with the permission `P`. The project must belong to the user's org. Admins can act on
+/// every project of their org, other users need a membership whose role also grants `P`.
+/// Projects the user cannot see are reported as missing.
+pub struct ProjectMember<P>(pub AuthUser, pub PhantomData<P>);
+
+impl<S, P> FromRequestParts<S> for ProjectMember<P>
+where
+    S: Send + Sync,
+    AppState: FromRef<S>,
+    P: RequiredPermission,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Authorized(user, _) = Authorized::<P>::from_request_parts(parts, state).await?;
+        let project_id = path_id(parts, state, "project_id").await?;
+
+        let app_state = AppState::from_ref(state);
+        let client = app_state.db_pool.get().await?;
+        let row = client
+            .query_opt(
+                "SELECT po.role FROM projects p
+                 LEFT JOIN project_owners po ON po.project_id = p.id AND po.user_id = $2
+                 WHERE p.id = $1 AND p.org_id = $3",
+                &[&project_id, &user.id, &user.org_id],
+            )
+            .await?
+            .ok_or(AppError::NotFound("Project not found".to_string()))?;
+
+        if matches!(user.role, UserRole::Admin) {
+            return Ok(ProjectMember(user, PhantomData));
+        }
+
+        let role: ProjectRole = row
+            .get::<_, Option<ProjectRole>>(0)
+            .ok_or(AppError::NotFound("Project not found".to_string()))?;
+
+        if !permissions::project_granted(role, P::PERMISSION) {
+            return Err(AppError::Forbidden(format!(
+                "Missing permission {} on this project",
+                P::PERMISSION.as_str()
+            )));
+        }
+
+        Ok(ProjectMember(user, PhantomData))
+    }
+}
+
+/// An authenticated user of the org in the `org_id` path parameter whose role grants
+/// the permission `P`. Other orgs are reported as missing.
+pub struct OrgMember<P>(pub AuthUser, pub PhantomData<P>);
+
+impl<S, P> FromRequestParts<S> for OrgMember<P>
+where
+    S: Send + Sync,
+    AppState: FromRef<S>,
+    P: RequiredPermission,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let user = AuthUser::from_request_parts(parts, state).await?;
+        user.require_mfa_enrollment()?;
+        let org_id = path_id(parts, state, "org_id").await?;
+
+        // Checked before the permission, so other orgs cannot be probed for existence
+        if org_id != user.org_id {
+            return Err(AppError::NotFound("Organization not found".to_string()));
+        }
+
+        if !permissions::granted(user.role, P::PERMISSION) {
+            return Err(AppError::Forbidden(format!(
+                "Missing permission {}",
+                P::PERMISSION.as_str()
+            )));
+        }
+
+        Ok(OrgMember(user, PhantomData))
+    }
+}
+
+/// Environment context extracted from an SDK API key
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[allow(dead_code)]
+pub struct ApiKeyAuth {
+    pub id: Uuid,
+    pub environment_id: Uuid,
+    pub project_id: Uuid,
+    pub is_server_key: bool,
+}
+
+/// Hash an API key for storage and lookup.
+/// Keys are random and high-entropy, so a plain SHA-256 digest is enough and
+/// keeps the lookup a single indexed equality match.
+pub fn hash_api_key(key: &str) -> String {
+    hash_token(key)
+}
+
+/// Hash a random single-use token, e.g. an invitation, the same way as API keys
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Generate a random token to hand out once and store as its [`hash_token`]
+pub fn generate_token() -> Result<String, AppError> {
+    let mut bytes = [0u8; 32];
+    rand::rngs::OsRng
+        .try_fill_bytes(&mut bytes)
+        .map_err(|_| AppError::InternalError("Failed to generate token".to_string()))?;
+
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Generate a new API key, returning `(plaintext, display_prefix)`.
+/// The plaintext is only ever shown once, the display prefix is safe to store.
+pub fn generate_api_key(is_server_key: bool) -> Result<(String, String), AppError> {
+    let mut bytes = [0u8; 32];
+    rand::rngs::OsRng
+        .try_fill_bytes(&mut bytes)
+        .map_err(|_| AppError::InternalError("Failed to generate API key".to_string()))?;
+
+    let prefix = if is_server_key {
+        SERVER_KEY_PREFIX
+    } else {
+        CLIENT_KEY_PREFIX
+    };
+    let secret: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    let key = format!("{}{}", prefix, secret);
+    let display_prefix = key[..prefix.len() + DISPLAY_PREFIX_CHARS].to_string();
+
+    Ok((key, display_prefix))
+}
+
+impl<S> FromRequestParts<S> for ApiKeyAuth
+where
+    S: Send + Sync,
+    AppState: FromRef<S>,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let app_state = AppState::from_ref(state);
+
+        let key = parts
+            .headers
+            .get(API_KEY_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.is_empty())
+            .ok_or(AppError::Unauthorized(
+                "Missing or invalid API key".to_string(),
+            ))?;
+
+        let key_hash = hash_api_key(key);
+
+        // SDK requests resolve keys through Redis, Postgres is only read on a miss
+        let cached = match cache::get_api_key(&app_state.redis_pool, &key_hash).await {
+            Ok(cached) => cached,
+            Err(e) => {
+                eprintln!("Failed to read cached API key: {}", e);
+                None
+            }
+        };
+
+        let api_key = match cached {
+            Some(api_key) => api_key,
+            None => {
+                let api_key = load_api_key(&app_state, &key_hash).await?;
+                if let Err(e) =
+                    cache::store_api_key(&app_state.redis_pool, &key_hash, &api_key).await
+                {
+                    eprintln!("Failed to cache API key: {}", e);
+                }
+                api_key
+            }
+        };
+
+        record_api_key_usage(&app_state, api_key.id).await;
+
+        Ok(api_key)
     }
 }
 
+/// Resolve an unrevoked API key by its hash
+async fn load_api_key(state: &AppState, key_hash: &str) -> Result<ApiKeyAuth, AppError> {
+    let client = state.db_pool.get().await?;
+
+    let row = client
+        .query_opt(
+            "SELECT k.id, k.environment_id, e.project_id, k.is_server_key
+             FROM api_keys k
+             JOIN environments e ON e.id = k.environment_id
+             WHERE k.key_hash = $1 AND k.revoked_at IS NULL",
+            &[&key_hash],
+        )
+        .await?
+        .ok_or(AppError::Unauthorized(
+            "Missing or invalid API key".to_string(),
+        ))?;
+
+    let project_id: Option<Uuid> = row.get(2);
+    let is_server_key: Option<bool> = row.get(3);
+
+    Ok(ApiKeyAuth {
+        id: row.get(0),
+        environment_id: row.get(1),
+        project_id: project_id.ok_or(AppError::InternalError(
+            "Environment is not attached to a project".to_string(),
+        ))?,
+        is_server_key: is_server_key.unwrap_or(false),
+    })
+}
+
+/// Update the key's `last_used_at` at most once per usage interval, in the background
+/// so a slow or unavailable Postgres never holds up the request
+async fn record_api_key_usage(state: &AppState, api_key_id: Uuid) {
+    match cache::claim_api_key_usage(&state.redis_pool, api_key_id).await {
+        Ok(true) => {}
+        Ok(false) => return,
+        // Without Redis keys are resolved from Postgres anyway, keep their usage current
+        Err(e) => eprintln!("Failed to throttle API key usage: {}", e),
+    }
+
+    let db_pool = state.db_pool.clone();
+    tokio::spawn(async move {
+        let result: Result<u64, AppError> = async {
+            let client = db_pool.get().await?;
+            Ok(client
+                .execute(
+                    "UPDATE api_keys SET last_used_at = current_timestamp WHERE id = $1",
+                    &[&api_key_id],
+                )
+                .await?)
+        }
+        .await;
+        if let Err(e) = result {
+            eprintln!("Failed to record usage of API key {}: {}", api_key_id, e);
+        }
+    });
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -47,7 +422,44 @@ mod tests {
     #[test]
     fn test_auth_user_creation() {
         let user_id = Uuid::new_v4();
-        let auth_user = AuthUser { id: user_id };
+        let auth_user = AuthUser {
+            id: user_id,
+            role: UserRole::Viewer,
+            org_id: Uuid::new_v4(),
+            session_id: None,
+            mfa_enrollment_required: false,
+        };
         assert_eq!(auth_user.id, user_id);
     }
+
+    #[test]
+    fn test_hash_api_key() {
+        let hash = hash_api_key("vx_srv_example");
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, hash_api_key("vx_srv_example"));
+        assert_ne!(hash, hash_api_key("vx_srv_other"));
+    }
+
+    #[test]
+    fn test_generate_token() {
+        let token = generate_token().unwrap();
+        assert_eq!(token.len(), 64);
+        assert_ne!(token, generate_token().unwrap());
+        assert_eq!(hash_token(&token), hash_api_key(&token));
+    }
+
+    #[test]
+    fn test_generate_api_key() {
+        let (server_key, server_prefix) = generate_api_key(true).unwrap();
+        assert!(server_key.starts_with(SERVER_KEY_PREFIX));
+        assert!(server_key.starts_with(&server_prefix));
+        assert_eq!(server_key.len(), SERVER_KEY_PREFIX.len() + 64);
+
+        let (client_key, _) = generate_api_key(false).unwrap();
+        assert!(client_key.starts_with(CLIENT_KEY_PREFIX));
+        assert_ne!(
+            server_key[SERVER_KEY_PREFIX.len()..],
+            client_key[CLIENT_KEY_PREFIX.len()..]
+        );
+    }
 }
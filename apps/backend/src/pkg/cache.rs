@@ -0,0 +1,182 @@
+use crate::pkg::auth::ApiKeyAuth;
+use crate::pkg::error::AppError;
+use crate::pkg::evaluation::Ruleset;
+use deadpool_redis::redis::{self, AsyncCommands};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+/// Cached rulesets expire on their own, bounding how long a missed invalidation can go unnoticed
+pub const RULESET_TTL_SECONDS: u64 = 60 * 60;
+
+/// Resolved API keys are cached briefly. Revoking a key drops its entry right away,
+/// the TTL bounds how long keys deleted along with their environment keep working.
+pub const API_KEY_TTL_SECONDS: u64 = 60;
+
+/// An API key's `last_used_at` is written at most once per interval
+pub const API_KEY_USAGE_INTERVAL_SECONDS: u64 = 60;
+
+fn ruleset_key(environment_id: Uuid) -> String {
+    format!("vexillum:env:{}:ruleset", environment_id)
+}
+
+fn version_key(environment_id: Uuid) -> String {
+    format!("vexillum:env:{}:ruleset_version", environment_id)
+}
+
+fn api_key_key(key_hash: &str) -> String {
+    format!("vexillum:api_key:{}", key_hash)
+}
+
+fn api_key_usage_key(api_key_id: Uuid) -> String {
+    format!("vexillum:api_key:{}:used", api_key_id)
+}
+
+/// A compiled ruleset together with the version of the environment it was loaded at
+#[derive(Serialize, Deserialize)]
+struct CachedRuleset {
+    version: u64,
+    ruleset: Ruleset,
+}
+
+/// The cached ruleset if it was stored at the current version, stale or unreadable entries are misses
+fn current(version: u64, payload: Option<String>) -> Option<Ruleset> {
+    payload
+        .and_then(|payload| serde_json::from_str::<CachedRuleset>(&payload).ok())
+        .filter(|cached| cached.version == version)
+        .map(|cached| cached.ruleset)
+}
+
+/// Look up the ruleset of an environment.
+/// Returns the environment's current version and the ruleset when the cache holds that version.
+pub async fn get(
+    redis_pool: &deadpool_redis::Pool,
+    environment_id: Uuid,
+) -> Result<(u64, Option<Ruleset>), AppError> {
+    let mut conn = redis_pool.get().await?;
+
+    let (version, payload): (Option<u64>, Option<String>) = redis::pipe()
+        .get(version_key(environment_id))
+        .get(ruleset_key(environment_id))
+        .query_async(&mut conn)
+        .await?;
+
+    let version = version.unwrap_or(0);
+    Ok((version, current(version, payload)))
+}
+
+/// Store a ruleset loaded at `version`. If the environment changed while it was loading,
+/// the version moved on and the entry is never served.
+pub async fn store(
+    redis_pool: &deadpool_redis::Pool,
+    environment_id: Uuid,
+    version: u64,
+    ruleset: &Ruleset,
+) -> Result<(), AppError> {
+    let mut conn = redis_pool.get().await?;
+
+    let payload = serde_json::to_string(&json!({ "version": version, "ruleset": ruleset }))?;
+    let _: () = conn
+        .set_ex(ruleset_key(environment_id), payload, RULESET_TTL_SECONDS)
+        .await?;
+
+    Ok(())
+}
+
+/// Bump the version of an environment and drop its cached ruleset,
+/// call after any change to its flags, overrides or audiences
+pub async fn invalidate(
+    redis_pool: &deadpool_redis::Pool,
+    environment_id: Uuid,
+) -> Result<u64, AppError> {
+    let mut conn = redis_pool.get().await?;
+
+    let (version,): (u64,) = redis::pipe()
+        .atomic()
+        .incr(version_key(environment_id), 1)
+        .del(ruleset_key(environment_id))
+        .ignore()
+        .query_async(&mut conn)
+        .await?;
+
+    Ok(version)
+}
+
+/// Look up the environment an API key resolves to by the key's hash
+pub async fn get_api_key(
+    redis_pool: &deadpool_redis::Pool,
+    key_hash: &str,
+) -> Result<Option<ApiKeyAuth>, AppError> {
+    let mut conn = redis_pool.get().await?;
+
+    let payload: Option<String> = conn.get(api_key_key(key_hash)).await?;
+    Ok(payload.and_then(|payload| serde_json::from_str(&payload).ok()))
+}
+
+/// Remember what an API key resolved to for `API_KEY_TTL_SECONDS`
+pub async fn store_api_key(
+    redis_pool: &deadpool_redis::Pool,
+    key_hash: &str,
+    api_key: &ApiKeyAuth,
+) -> Result<(), AppError> {
+    let mut conn = redis_pool.get().await?;
+
+    let payload = serde_json::to_string(api_key)?;
+    let _: () = conn
+        .set_ex(api_key_key(key_hash), payload, API_KEY_TTL_SECONDS)
+        .await?;
+
+    Ok(())
+}
+
+/// Drop a cached API key, call once it is revoked
+pub async fn forget_api_key(
+    redis_pool: &deadpool_redis::Pool,
+    key_hash: &str,
+) -> Result<(), AppError> {
+    let mut conn = redis_pool.get().await?;
+    let _: () = conn.del(api_key_key(key_hash)).await?;
+    Ok(())
+}
+
+/// Whether this request should record the API key's usage.
+/// Only the first caller in every `API_KEY_USAGE_INTERVAL_SECONDS`, across instances, gets `true`.
+pub async fn claim_api_key_usage(
+    redis_pool: &deadpool_redis::Pool,
+    api_key_id: Uuid,
+) -> Result<bool, AppError> {
+    let mut conn = redis_pool.get().await?;
+
+    let claimed: Option<String> = redis::cmd("SET")
+        .arg(api_key_usage_key(api_key_id))
+        .arg(1)
+        .arg("NX")
+        .arg("EX")
+        .arg(API_KEY_USAGE_INTERVAL_SECONDS)
+        .query_async(&mut conn)
+        .await?;
+
+    Ok(claimed.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(version: u64) -> Option<String> {
+        serde_json::to_string(&CachedRuleset {
+            version,
+            ruleset: Ruleset::default(),
+        })
+        .ok()
+    }
+
+    #[test]
+    fn test_current() {
+        assert!(current(3, payload(3)).is_some());
+        // Stored before the latest invalidation
+        assert!(current(4, payload(3)).is_none());
+        assert!(current(0, None).is_none());
+        assert!(current(0, Some("not json".to_string())).is_none());
+    }
+}
@@ -1,10 +1,16 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use dotenvy::dotenv;
+use std::path::PathBuf;
+
+use super::keys::SigningAlgorithm;
 
 #[derive(Parser, Debug, Clone)]
 #[command(name = "Vexillum Backend")]
 #[command(about = "A backend service built with Axum and Tokio Postgres")]
 pub struct Config {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// PostgreSQL host
     #[arg(env = "DATABASE_HOST", default_value = "localhost")]
     pub database_host: String,
@@ -61,9 +67,163 @@ pub struct Config {
     #[arg(env = "REFRESH_TOKEN_EXPIRY", default_value = "604800")]
     pub refresh_token_expiry: i64,
 
+    /// Issuer of the JWTs we sign and accept
+    #[arg(env = "JWT_ISSUER", default_value = "vexillum")]
+    pub jwt_issuer: String,
+
+    /// Algorithm of generated signing keys
+    #[arg(env = "JWT_ALGORITHM", value_enum, default_value = "RS256")]
+    pub jwt_algorithm: SigningAlgorithm,
+
+    /// Directory signing keys are generated in and loaded from
+    #[arg(env = "KEYS_DIR", default_value = ".keys")]
+    pub keys_dir: PathBuf,
+
+    /// Private key to sign with in PEM format, replaces the keys directory
+    #[arg(env = "JWT_PRIVATE_KEY")]
+    pub jwt_private_key: Option<String>,
+
+    /// File holding the private key to sign with, replaces the keys directory
+    #[arg(env = "JWT_PRIVATE_KEY_FILE")]
+    pub jwt_private_key_file: Option<PathBuf>,
+
+    /// Seconds a rotated out signing key keeps verifying tokens,
+    /// defaults to the lifetime of a remember me refresh token
+    #[arg(env = "KEY_GRACE_PERIOD", default_value = "4233600")]
+    pub key_grace_period: i64,
+
     /// Frontend URL
     #[arg(env = "FRONTEND_URL", default_value = "http://localhost:5173")]
     pub frontend_url: String,
+
+    /// How emails are delivered
+    #[arg(env = "MAIL_TRANSPORT", value_enum, default_value = "outbox")]
+    pub mail_transport: MailTransport,
+
+    /// Sender of the emails we send
+    #[arg(env = "MAIL_FROM", default_value = "Vexillum <no-reply@localhost>")]
+    pub mail_from: String,
+
+    /// Directory the outbox transport writes emails to, they are only logged when unset
+    #[arg(env = "MAIL_OUTBOX_DIR")]
+    pub mail_outbox_dir: Option<PathBuf>,
+
+    /// SMTP host
+    #[arg(env = "SMTP_HOST", default_value = "localhost")]
+    pub smtp_host: String,
+
+    /// SMTP port
+    #[arg(env = "SMTP_PORT", default_value = "587")]
+    pub smtp_port: u16,
+
+    /// SMTP user
+    #[arg(env = "SMTP_USERNAME")]
+    pub smtp_username: Option<String>,
+
+    /// SMTP password
+    #[arg(env = "SMTP_PASSWORD")]
+    pub smtp_password: Option<String>,
+
+    /// How the SMTP connection is encrypted
+    #[arg(env = "SMTP_TLS", value_enum, default_value = "starttls")]
+    pub smtp_tls: SmtpTls,
+
+    /// Issuer URL of the OIDC identity provider, single sign-on is off when unset
+    #[arg(env = "OIDC_ISSUER")]
+    pub oidc_issuer: Option<String>,
+
+    /// Client ID registered with the identity provider
+    #[arg(env = "OIDC_CLIENT_ID")]
+    pub oidc_client_id: Option<String>,
+
+    /// Client secret, unset for public clients that only rely on PKCE
+    #[arg(env = "OIDC_CLIENT_SECRET")]
+    pub oidc_client_secret: Option<String>,
+
+    /// Where the identity provider sends users back to, the frontend page posting the code to the API
+    #[arg(
+        env = "OIDC_REDIRECT_URL",
+        default_value = "http://localhost:5173/auth/oidc/callback"
+    )]
+    pub oidc_redirect_url: String,
+
+    /// Scopes requested from the identity provider
+    #[arg(env = "OIDC_SCOPES", default_value = "openid email profile")]
+    pub oidc_scopes: String,
+
+    /// Slug of the org new single sign-on users join as viewers, each gets an org of their own when unset
+    #[arg(env = "OIDC_ORG")]
+    pub oidc_org: Option<String>,
+
+    /// Requests an IP address can make to the sign in endpoints per window, 0 turns the limit off
+    #[arg(env = "RATE_LIMIT_IP", default_value = "30")]
+    pub rate_limit_ip: u64,
+
+    /// Requests naming the same account the sign in endpoints accept per window, 0 turns the limit off
+    #[arg(env = "RATE_LIMIT_ACCOUNT", default_value = "10")]
+    pub rate_limit_account: u64,
+
+    /// Length of a rate limit window in seconds
+    #[arg(env = "RATE_LIMIT_WINDOW", default_value = "60")]
+    pub rate_limit_window: u64,
+
+    /// Take the client address from the right-most X-Forwarded-For entry, only safe behind a proxy that appends it
+    #[arg(env = "TRUST_PROXY", default_value = "false", action = clap::ArgAction::Set)]
+    pub trust_proxy: bool,
+
+    /// Failed password attempts in a row before an account is locked
+    #[arg(env = "LOCKOUT_THRESHOLD", default_value = "5")]
+    pub lockout_threshold: i32,
+
+    /// Seconds the first lockout lasts, doubling with every further failed attempt
+    #[arg(env = "LOCKOUT_DURATION", default_value = "60")]
+    pub lockout_duration: i64,
+
+    /// Longest an account stays locked, in seconds
+    #[arg(env = "LOCKOUT_MAX_DURATION", default_value = "3600")]
+    pub lockout_max_duration: i64,
+
+    /// Accounts created by signing up must verify their email before signing in with a password
+    #[arg(env = "REQUIRE_EMAIL_VERIFICATION", default_value = "true", action = clap::ArgAction::Set)]
+    pub require_email_verification: bool,
+
+    /// Let anyone signing up without joining an org, by password or SSO, start one of their own.
+    /// Off by default so only invited people get in, the first account of an empty install always may.
+    #[arg(env = "ALLOW_ORG_CREATION", default_value = "false", action = clap::ArgAction::Set)]
+    pub allow_org_creation: bool,
+
+    /// Fewest characters a password may have
+    #[arg(env = "PASSWORD_MIN_LENGTH", default_value = "10")]
+    pub password_min_length: usize,
+
+    /// Fewest kinds of characters a password must mix, out of lowercase, uppercase, digits and others
+    #[arg(env = "PASSWORD_MIN_CHARACTER_CLASSES", default_value = "2")]
+    pub password_min_character_classes: usize,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub enum MailTransport {
+    /// Send through the SMTP server
+    Smtp,
+    /// Log emails and keep them in the outbox directory, for local development and tests
+    Outbox,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub enum SmtpTls {
+    /// Upgrade a plain connection with STARTTLS
+    Starttls,
+    /// Connect over TLS
+    Tls,
+    /// Unencrypted, only for local mail catchers
+    None,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Generate a new JWT signing key and promote it, retiring the current one.
+    /// Keys retired longer than the grace period are deleted.
+    RotateKeys,
 }
 
 impl Config {
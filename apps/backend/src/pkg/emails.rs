@@ -0,0 +1,159 @@
+use crate::models::enums::UserRole;
+use serde::{Deserialize, Serialize};
+
+/// An email ready to be sent, with a plain text and an HTML body
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+/// Messages we send, each with the token its link carries
+pub enum Template<'a> {
+    MagicLink {
+        token: &'a str,
+    },
+    EmailVerification {
+        token: &'a str,
+    },
+    PasswordReset {
+        token: &'a str,
+    },
+    Invitation {
+        org_name: &'a str,
+        role: &'a UserRole,
+        token: &'a str,
+    },
+}
+
+/// Frontend page a link leads to, with the query parameter carrying the token
+fn link(frontend_url: &str, path: &str, param: &str, token: &str) -> String {
+    format!(
+        "{}{}?{}={}",
+        frontend_url.trim_end_matches('/'),
+        path,
+        param,
+        token
+    )
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn html_body(intro: &str, action: &str, url: &str, outro: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+  <body style="font-family: sans-serif; color: #1f2937; line-height: 1.5">
+    <p>{intro}</p>
+    <p><a href="{url}" style="display: inline-block; padding: 10px 16px; background: #111827; color: #ffffff; border-radius: 6px; text-decoration: none">{action}</a></p>
+    <p style="font-size: 13px; color: #6b7280">Or open this link: <a href="{url}">{url}</a></p>
+    <p style="font-size: 13px; color: #6b7280">{outro}</p>
+  </body>
+</html>
+"#,
+        intro = escape_html(intro),
+        action = escape_html(action),
+        url = escape_html(url),
+        outro = escape_html(outro),
+    )
+}
+
+impl Template<'_> {
+    /// Render the message for `to`, with links into the frontend at `frontend_url`
+    pub fn render(&self, to: &str, frontend_url: &str) -> Email {
+        let (subject, intro, action, url, outro) = match self {
+            Template::MagicLink { token } => (
+                "Your Vexillum sign in link".to_string(),
+                "Use the link below to sign in to Vexillum.".to_string(),
+                "Sign in",
+                link(frontend_url, "/auth/magic", "token", token),
+                "The link can be used once. If you didn't ask to sign in, you can ignore this email.",
+            ),
+            Template::EmailVerification { token } => (
+                "Verify your email address".to_string(),
+                "Confirm this is your email address to finish setting up your Vexillum account."
+                    .to_string(),
+                "Verify email",
+                link(frontend_url, "/auth/verify-email", "token", token),
+                "If you didn't create an account, you can ignore this email.",
+            ),
+            Template::PasswordReset { token } => (
+                "Reset your Vexillum password".to_string(),
+                "Use the link below to choose a new password.".to_string(),
+                "Reset password",
+                link(frontend_url, "/auth/reset-password", "token", token),
+                "The link can be used once. If you didn't ask for a reset, your password stays as it is.",
+            ),
+            Template::Invitation {
+                org_name,
+                role,
+                token,
+            } => (
+                format!("You're invited to join {} on Vexillum", org_name),
+                format!(
+                    "You have been invited to join {} as {}.",
+                    org_name,
+                    format!("{:?}", role).to_lowercase()
+                ),
+                "Accept invitation",
+                link(frontend_url, "/auth/register", "invitation", token),
+                "If you weren't expecting this invitation, you can ignore this email.",
+            ),
+        };
+
+        Email {
+            to: to.to_string(),
+            text: format!("{}\n\n{}: {}\n\n{}\n", intro, action, url, outro),
+            html: html_body(&intro, action, &url, outro),
+            subject,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_links_use_the_frontend_url() {
+        let email = Template::PasswordReset { token: "abc" }
+            .render("user@example.com", "https://app.example.com/");
+        assert_eq!(email.to, "user@example.com");
+        assert!(
+            email
+                .text
+                .contains("https://app.example.com/auth/reset-password?token=abc")
+        );
+        assert!(
+            email
+                .html
+                .contains(r#"href="https://app.example.com/auth/reset-password?token=abc""#)
+        );
+    }
+
+    #[test]
+    fn test_invitation_escapes_org_name() {
+        let email = Template::Invitation {
+            org_name: "<Acme & Co>",
+            role: &UserRole::Viewer,
+            token: "abc",
+        }
+        .render("user@example.com", "http://localhost:5173");
+        assert_eq!(
+            email.subject,
+            "You're invited to join <Acme & Co> on Vexillum"
+        );
+        assert!(email.text.contains("as viewer"));
+        assert!(email.html.contains("&lt;Acme &amp; Co&gt;"));
+        assert!(!email.html.contains("<Acme"));
+    }
+}
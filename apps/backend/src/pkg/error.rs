@@ -1,7 +1,7 @@
 use axum::{
     Json,
     extract::rejection::JsonRejection,
-    http::StatusCode,
+    http::{HeaderValue, StatusCode, header::RETRY_AFTER},
     response::{IntoResponse, Response},
 };
 use serde::{Deserialize, Serialize};
@@ -19,6 +19,10 @@ pub enum AppError {
     #[error("{0}")]
     Unauthorized(String),
 
+    // 403 - Forbidden
+    #[error("{0}")]
+    Forbidden(String),
+
     // 404 - Not Found
     #[error("{0}")]
     NotFound(String),
@@ -31,6 +35,10 @@ pub enum AppError {
     #[error("{0}")]
     UnprocessableEntity(String),
 
+    // 429 - Too Many Requests, with the seconds to wait before retrying
+    #[error("{0}")]
+    TooManyRequests(String, u64),
+
     // 500 - Internal Server Error
     #[error("{0}")]
     InternalError(String),
@@ -47,12 +55,18 @@ pub struct ErrorResponse {
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        let mut retry_after = None;
         let (status, error_message) = match self {
             AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
             AppError::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, msg),
+            AppError::Forbidden(msg) => (StatusCode::FORBIDDEN, msg),
             AppError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
             AppError::Conflict(msg) => (StatusCode::CONFLICT, msg),
             AppError::UnprocessableEntity(msg) => (StatusCode::UNPROCESSABLE_ENTITY, msg),
+            AppError::TooManyRequests(msg, seconds) => {
+                retry_after = Some(seconds);
+                (StatusCode::TOO_MANY_REQUESTS, msg)
+            }
             AppError::InternalError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
             AppError::JsonExtractorRejection(rejection) => {
                 let status = rejection.status();
@@ -70,7 +84,14 @@ impl IntoResponse for AppError {
             .error_code(status.into())
             .build();
 
-        (status, Json(body)).into_response()
+        let mut response = (status, Json(body)).into_response();
+        if let Some(seconds) = retry_after {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(seconds));
+        }
+
+        response
     }
 }
 
@@ -92,6 +113,18 @@ impl From<deadpool_postgres::PoolError> for AppError {
     }
 }
 
+impl From<deadpool_redis::redis::RedisError> for AppError {
+    fn from(err: deadpool_redis::redis::RedisError) -> Self {
+        AppError::InternalError(format!("Redis error: {}", err))
+    }
+}
+
+impl From<deadpool_redis::PoolError> for AppError {
+    fn from(_err: deadpool_redis::PoolError) -> Self {
+        AppError::InternalError("Failed to get Redis connection".to_string())
+    }
+}
+
 impl From<argon2::password_hash::Error> for AppError {
     fn from(_err: argon2::password_hash::Error) -> Self {
         AppError::InternalError("Failed to process password".to_string())
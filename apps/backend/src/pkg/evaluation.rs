@@ -0,0 +1,636 @@
+use std::collections::HashMap;
+
+use crate::models::db::{Audiences, FeatureFlagEnvironments, FeatureFlagOverrides, FeatureFlags};
+use crate::models::enums::MatchOperator;
+use crate::pkg::rollout::{Allocation, Rollout};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Attributes describing the subject a flag is evaluated for (user id, country, plan, ...)
+pub type EvaluationContext = HashMap<String, Value>;
+
+/// Why a flag resolved to the value it did
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum EvaluationReason {
+    /// No override matched, the flag's own value was used
+    Default,
+    /// An override matched because the context belongs to the given audience
+    Override {
+        audience_id: Uuid,
+        audience_name: String,
+    },
+    /// The flag is switched off in this environment, overrides are not considered
+    Disabled,
+    /// No override matched and the flag's rollout decided the outcome.
+    /// `bucket` is missing when the context lacks the rollout's `bucket_by` attribute.
+    Rollout { bucket: Option<u32> },
+}
+
+/// Resolved state of a single flag for a context
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Evaluation {
+    pub key: String,
+    pub is_enabled: bool,
+    pub value: Option<Value>,
+    pub reason: EvaluationReason,
+}
+
+/// Everything needed to evaluate the flags of an environment without touching the database
+#[derive(Default, Serialize, Deserialize, ToSchema)]
+pub struct Ruleset {
+    pub flags: Vec<FeatureFlags>,
+    /// State of each flag in the environment
+    pub states: Vec<FeatureFlagEnvironments>,
+    /// Overrides of all flags in the environment, in evaluation order
+    pub overrides: Vec<FeatureFlagOverrides>,
+    pub audiences: Vec<Audiences>,
+}
+
+impl Ruleset {
+    /// Evaluate every flag in the ruleset for the given context
+    pub fn evaluate_all(&self, context: &EvaluationContext) -> Vec<Evaluation> {
+        self.flags
+            .iter()
+            .map(|flag| self.evaluate_flag(flag, context))
+            .collect()
+    }
+
+    /// Evaluate a single flag of the ruleset, `None` when the ruleset does not contain it
+    pub fn evaluate_one(&self, flag_id: Uuid, context: &EvaluationContext) -> Option<Evaluation> {
+        self.flags
+            .iter()
+            .find(|flag| flag.id == flag_id)
+            .map(|flag| self.evaluate_flag(flag, context))
+    }
+
+    fn evaluate_flag(&self, flag: &FeatureFlags, context: &EvaluationContext) -> Evaluation {
+        let state = self.states.iter().find(|s| s.feature_flag_id == flag.id);
+        let overrides = self
+            .overrides
+            .iter()
+            .filter(|o| o.feature_flag_id == Some(flag.id));
+        evaluate(flag, state, overrides, &self.audiences, context)
+    }
+}
+
+/// Evaluate a flag in an environment for the given context.
+///
+/// A flag that is disabled in the environment, or has no state there at all,
+/// acts as a kill switch and always resolves to `Disabled`. Otherwise overrides
+/// are checked in the order given and the first one whose audience matches the
+/// context wins. An override with a rollout only applies to the subjects its
+/// rollout includes, everyone else falls through to the next override.
+/// Overrides pointing at an audience that is not in `audiences` are skipped.
+///
+/// When no override applies, the flag state's own rollout, if any, decides
+/// between the state value (or a variant) and the flag being off.
+pub fn evaluate<'a>(
+    flag: &FeatureFlags,
+    state: Option<&FeatureFlagEnvironments>,
+    overrides: impl IntoIterator<Item = &'a FeatureFlagOverrides>,
+    audiences: &[Audiences],
+    context: &EvaluationContext,
+) -> Evaluation {
+    let Some(state) = state.filter(|s| s.is_enabled) else {
+        return Evaluation {
+            key: flag.key.clone(),
+            is_enabled: false,
+            value: None,
+            reason: EvaluationReason::Disabled,
+        };
+    };
+
+    for flag_override in overrides {
+        let Some(audience) = flag_override
+            .audience_id
+            .and_then(|id| audiences.iter().find(|a| a.id == id))
+        else {
+            continue;
+        };
+
+        if !audience_matches(audience, context) {
+            continue;
+        }
+
+        let value = match Rollout::from_column(flag_override.rollout.as_ref()) {
+            None => flag_override.value.clone(),
+            Some(rollout) => match rollout.allocate(&flag.key, context) {
+                Some((_, Allocation::Included)) => flag_override.value.clone(),
+                Some((_, Allocation::Variant(variant))) => Some(variant.value.clone()),
+                Some((_, Allocation::Excluded)) | None => continue,
+            },
+        };
+
+        return Evaluation {
+            key: flag.key.clone(),
+            is_enabled: flag_override.is_enabled.unwrap_or(false),
+            value,
+            reason: EvaluationReason::Override {
+                audience_id: audience.id,
+                audience_name: audience.name.clone(),
+            },
+        };
+    }
+
+    let Some(rollout) = Rollout::from_column(state.rollout.as_ref()) else {
+        return Evaluation {
+            key: flag.key.clone(),
+            is_enabled: true,
+            value: state.value.clone(),
+            reason: EvaluationReason::Default,
+        };
+    };
+
+    let (bucket, is_enabled, value) = match rollout.allocate(&flag.key, context) {
+        Some((bucket, Allocation::Included)) => (Some(bucket), true, state.value.clone()),
+        Some((bucket, Allocation::Variant(variant))) => {
+            (Some(bucket), true, Some(variant.value.clone()))
+        }
+        Some((bucket, Allocation::Excluded)) => (Some(bucket), false, None),
+        None => (None, false, None),
+    };
+
+    Evaluation {
+        key: flag.key.clone(),
+        is_enabled,
+        value,
+        reason: EvaluationReason::Rollout { bucket },
+    }
+}
+
+/// Check whether a context belongs to an audience.
+/// A missing or `null` attribute never matches, not even for negated operators.
+pub fn audience_matches(audience: &Audiences, context: &EvaluationContext) -> bool {
+    match context.get(&audience.attribute) {
+        None | Some(Value::Null) => false,
+        Some(attribute) => matches_operator(attribute, audience.operator, &audience.value),
+    }
+}
+
+/// Apply a match operator to a context attribute and the audience's raw value.
+///
+/// - `eq` / `neq`: strings compare exactly, numbers and booleans are compared after
+///   parsing the expected value. A list attribute is equal if any element is equal.
+/// - `in` / `nin`: the expected value is a JSON array or a comma separated list.
+///   A list attribute is "in" if any of its elements is in the expected list.
+/// - `gt` / `lt` / `gte` / `lte`: numeric when both sides are numbers (strings that
+///   parse as numbers included), otherwise lexicographic for string attributes.
+///   Lists and booleans never match.
+/// - `contains` / `ncontains`: substring for strings, element membership for lists.
+///   Numbers and booleans never match.
+pub fn matches_operator(attribute: &Value, operator: MatchOperator, expected: &str) -> bool {
+    match operator {
+        MatchOperator::Eq => equals(attribute, expected),
+        MatchOperator::Neq => !equals(attribute, expected),
+        MatchOperator::In => is_in(attribute, expected),
+        MatchOperator::Nin => !is_in(attribute, expected),
+        MatchOperator::Gt => compare(attribute, expected).is_some_and(|o| o.is_gt()),
+        MatchOperator::Lt => compare(attribute, expected).is_some_and(|o| o.is_lt()),
+        MatchOperator::Gte => compare(attribute, expected).is_some_and(|o| o.is_ge()),
+        MatchOperator::Lte => compare(attribute, expected).is_some_and(|o| o.is_le()),
+        MatchOperator::Contains => contains(attribute, expected).unwrap_or(false),
+        MatchOperator::Ncontains => contains(attribute, expected).is_some_and(|c| !c),
+    }
+}
+
+fn scalar_equals(attribute: &Value, expected: &str) -> bool {
+    match attribute {
+        Value::String(s) => s == expected,
+        Value::Number(n) => match (n.as_f64(), expected.trim().parse::<f64>()) {
+            (Some(a), Ok(b)) => a == b,
+            _ => false,
+        },
+        Value::Bool(b) => expected.trim().parse::<bool>() == Ok(*b),
+        _ => false,
+    }
+}
+
+fn equals(attribute: &Value, expected: &str) -> bool {
+    match attribute {
+        Value::Array(items) => items.iter().any(|item| scalar_equals(item, expected)),
+        other => scalar_equals(other, expected),
+    }
+}
+
+/// Parse an audience value holding a list, either `["a","b"]` or `a, b`
+fn parse_list(expected: &str) -> Vec<String> {
+    if let Ok(Value::Array(items)) = serde_json::from_str::<Value>(expected) {
+        return items
+            .into_iter()
+            .map(|item| match item {
+                Value::String(s) => s,
+                other => other.to_string(),
+            })
+            .collect();
+    }
+
+    expected
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+fn is_in(attribute: &Value, expected: &str) -> bool {
+    let list = parse_list(expected);
+    let in_list = |value: &Value| list.iter().any(|item| scalar_equals(value, item));
+
+    match attribute {
+        Value::Array(items) => items.iter().any(in_list),
+        other => in_list(other),
+    }
+}
+
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse::<f64>().ok(),
+        _ => None,
+    }
+}
+
+fn compare(attribute: &Value, expected: &str) -> Option<std::cmp::Ordering> {
+    if let (Some(a), Ok(b)) = (as_number(attribute), expected.trim().parse::<f64>()) {
+        return a.partial_cmp(&b);
+    }
+
+    match attribute {
+        Value::String(s) => Some(s.as_str().cmp(expected)),
+        _ => None,
+    }
+}
+
+fn contains(attribute: &Value, expected: &str) -> Option<bool> {
+    match attribute {
+        Value::String(s) => Some(s.contains(expected)),
+        Value::Array(items) => Some(items.iter().any(|item| scalar_equals(item, expected))),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::enums::{AudienceScope, FeatureFlagType};
+    use serde_json::json;
+
+    fn flag() -> FeatureFlags {
+        FeatureFlags {
+            id: Uuid::new_v4(),
+            org_id: None,
+            key: "checkout".to_string(),
+            r#type: FeatureFlagType::Json,
+            project_id: None,
+            created_at: None,
+            updated_at: None,
+            json_schema: None,
+        }
+    }
+
+    fn state(flag: &FeatureFlags, is_enabled: bool, value: Value) -> FeatureFlagEnvironments {
+        FeatureFlagEnvironments {
+            id: Uuid::new_v4(),
+            feature_flag_id: flag.id,
+            environment_id: Uuid::nil(),
+            is_enabled,
+            value: Some(value),
+            created_at: None,
+            updated_at: None,
+            rollout: None,
+        }
+    }
+
+    fn audience(attribute: &str, operator: MatchOperator, value: &str) -> Audiences {
+        Audiences {
+            id: Uuid::new_v4(),
+            org_id: None,
+            name: format!("{} {:?} {}", attribute, operator, value),
+            attribute: attribute.to_string(),
+            operator,
+            value: value.to_string(),
+            scope: AudienceScope::Global,
+            project_id: None,
+            created_at: None,
+            updated_at: None,
+        }
+    }
+
+    fn flag_override(audience: &Audiences, is_enabled: bool, value: Value) -> FeatureFlagOverrides {
+        FeatureFlagOverrides {
+            id: Uuid::new_v4(),
+            feature_flag_id: None,
+            audience_id: Some(audience.id),
+            is_enabled: Some(is_enabled),
+            r#type: FeatureFlagType::Json,
+            value: Some(value),
+            created_at: None,
+            updated_at: None,
+            environment_id: Uuid::nil(),
+            rollout: None,
+        }
+    }
+
+    fn rollout(percentage: f64) -> Option<Value> {
+        Some(json!({ "bucket_by": "user_id", "percentage": percentage }))
+    }
+
+    fn context(attributes: Value) -> EvaluationContext {
+        serde_json::from_value(attributes).unwrap()
+    }
+
+    #[test]
+    fn test_eq() {
+        assert!(matches_operator(&json!("us"), MatchOperator::Eq, "us"));
+        assert!(!matches_operator(&json!("US"), MatchOperator::Eq, "us"));
+        assert!(matches_operator(&json!(42), MatchOperator::Eq, "42"));
+        assert!(matches_operator(&json!(1.5), MatchOperator::Eq, "1.50"));
+        assert!(!matches_operator(
+            &json!(42),
+            MatchOperator::Eq,
+            "forty-two"
+        ));
+        assert!(matches_operator(&json!(true), MatchOperator::Eq, "true"));
+        assert!(matches_operator(&json!(["a", "b"]), MatchOperator::Eq, "b"));
+        assert!(!matches_operator(
+            &json!(["a", "b"]),
+            MatchOperator::Eq,
+            "c"
+        ));
+    }
+
+    #[test]
+    fn test_neq() {
+        assert!(matches_operator(&json!("eu"), MatchOperator::Neq, "us"));
+        assert!(!matches_operator(&json!("us"), MatchOperator::Neq, "us"));
+        assert!(matches_operator(&json!(41), MatchOperator::Neq, "42"));
+        assert!(!matches_operator(
+            &json!(["a", "b"]),
+            MatchOperator::Neq,
+            "a"
+        ));
+        assert!(matches_operator(
+            &json!(["a", "b"]),
+            MatchOperator::Neq,
+            "c"
+        ));
+    }
+
+    #[test]
+    fn test_in() {
+        assert!(matches_operator(
+            &json!("pro"),
+            MatchOperator::In,
+            "free, pro"
+        ));
+        assert!(matches_operator(
+            &json!("pro"),
+            MatchOperator::In,
+            r#"["pro","team"]"#
+        ));
+        assert!(matches_operator(&json!(3), MatchOperator::In, "1,2,3"));
+        assert!(matches_operator(&json!(3), MatchOperator::In, "[1, 2, 3]"));
+        assert!(!matches_operator(
+            &json!("enterprise"),
+            MatchOperator::In,
+            "free,pro"
+        ));
+        assert!(matches_operator(
+            &json!(["x", "pro"]),
+            MatchOperator::In,
+            "free,pro"
+        ));
+        assert!(!matches_operator(
+            &json!(["x", "y"]),
+            MatchOperator::In,
+            "free,pro"
+        ));
+    }
+
+    #[test]
+    fn test_nin() {
+        assert!(matches_operator(
+            &json!("enterprise"),
+            MatchOperator::Nin,
+            "free,pro"
+        ));
+        assert!(!matches_operator(
+            &json!("pro"),
+            MatchOperator::Nin,
+            "free,pro"
+        ));
+        assert!(!matches_operator(&json!(2), MatchOperator::Nin, "1,2"));
+        assert!(matches_operator(
+            &json!(["x", "y"]),
+            MatchOperator::Nin,
+            "free,pro"
+        ));
+        assert!(!matches_operator(
+            &json!(["x", "pro"]),
+            MatchOperator::Nin,
+            "free,pro"
+        ));
+    }
+
+    #[test]
+    fn test_gt() {
+        assert!(matches_operator(&json!(10), MatchOperator::Gt, "9"));
+        assert!(!matches_operator(&json!(9), MatchOperator::Gt, "9"));
+        assert!(matches_operator(&json!("10"), MatchOperator::Gt, "9"));
+        assert!(matches_operator(
+            &json!("2026-02-01"),
+            MatchOperator::Gt,
+            "2026-01-31"
+        ));
+        assert!(!matches_operator(&json!([10]), MatchOperator::Gt, "9"));
+        assert!(!matches_operator(&json!(true), MatchOperator::Gt, "false"));
+    }
+
+    #[test]
+    fn test_lt() {
+        assert!(matches_operator(&json!(8), MatchOperator::Lt, "9"));
+        assert!(!matches_operator(&json!(9), MatchOperator::Lt, "9"));
+        assert!(matches_operator(&json!("abc"), MatchOperator::Lt, "abd"));
+        assert!(!matches_operator(&json!(["a"]), MatchOperator::Lt, "b"));
+    }
+
+    #[test]
+    fn test_gte() {
+        assert!(matches_operator(&json!(9), MatchOperator::Gte, "9"));
+        assert!(matches_operator(&json!(9.5), MatchOperator::Gte, "9"));
+        assert!(!matches_operator(&json!(8), MatchOperator::Gte, "9"));
+        assert!(matches_operator(&json!("b"), MatchOperator::Gte, "b"));
+    }
+
+    #[test]
+    fn test_lte() {
+        assert!(matches_operator(&json!(9), MatchOperator::Lte, "9"));
+        assert!(!matches_operator(&json!(10), MatchOperator::Lte, "9"));
+        assert!(matches_operator(&json!("a"), MatchOperator::Lte, "b"));
+        assert!(!matches_operator(&json!(null), MatchOperator::Lte, "9"));
+    }
+
+    #[test]
+    fn test_contains() {
+        assert!(matches_operator(
+            &json!("alice@acme.com"),
+            MatchOperator::Contains,
+            "@acme."
+        ));
+        assert!(!matches_operator(
+            &json!("bob@other.com"),
+            MatchOperator::Contains,
+            "@acme."
+        ));
+        assert!(matches_operator(
+            &json!(["beta", "staff"]),
+            MatchOperator::Contains,
+            "staff"
+        ));
+        assert!(matches_operator(
+            &json!([1, 2]),
+            MatchOperator::Contains,
+            "2"
+        ));
+        assert!(!matches_operator(&json!(123), MatchOperator::Contains, "2"));
+    }
+
+    #[test]
+    fn test_ncontains() {
+        assert!(matches_operator(
+            &json!("bob@other.com"),
+            MatchOperator::Ncontains,
+            "@acme."
+        ));
+        assert!(!matches_operator(
+            &json!("alice@acme.com"),
+            MatchOperator::Ncontains,
+            "@acme."
+        ));
+        assert!(matches_operator(
+            &json!(["beta"]),
+            MatchOperator::Ncontains,
+            "staff"
+        ));
+        assert!(!matches_operator(
+            &json!(["staff"]),
+            MatchOperator::Ncontains,
+            "staff"
+        ));
+        assert!(!matches_operator(
+            &json!(123),
+            MatchOperator::Ncontains,
+            "2"
+        ));
+    }
+
+    #[test]
+    fn test_missing_attribute_never_matches() {
+        let negated = audience("country", MatchOperator::Neq, "us");
+        assert!(!audience_matches(&negated, &context(json!({}))));
+        assert!(!audience_matches(
+            &negated,
+            &context(json!({ "country": null }))
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_disabled() {
+        let beta = audience("plan", MatchOperator::Eq, "beta");
+        let overrides = [flag_override(&beta, true, json!("on"))];
+        let audiences = [beta];
+        let flag = flag();
+        let disabled = state(&flag, false, json!("off"));
+        let context = context(json!({ "plan": "beta" }));
+
+        let result = evaluate(&flag, Some(&disabled), &overrides, &audiences, &context);
+        assert!(!result.is_enabled);
+        assert_eq!(result.value, None);
+        assert_eq!(result.reason, EvaluationReason::Disabled);
+
+        // A flag without state in the environment is treated as disabled
+        let result = evaluate(&flag, None, &overrides, &audiences, &context);
+        assert_eq!(result.reason, EvaluationReason::Disabled);
+    }
+
+    #[test]
+    fn test_evaluate_override_then_default() {
+        let beta = audience("plan", MatchOperator::Eq, "beta");
+        let staff = audience("email", MatchOperator::Contains, "@acme.com");
+        let overrides = [
+            flag_override(&staff, false, json!("staff")),
+            flag_override(&beta, true, json!("beta")),
+        ];
+        let audiences = [beta, staff];
+        let flag = flag();
+        let enabled = state(&flag, true, json!("default"));
+
+        let result = evaluate(
+            &flag,
+            Some(&enabled),
+            &overrides,
+            &audiences,
+            &context(json!({ "plan": "beta", "email": "jo@acme.com" })),
+        );
+        assert!(!result.is_enabled);
+        assert_eq!(result.value, Some(json!("staff")));
+        assert_eq!(
+            result.reason,
+            EvaluationReason::Override {
+                audience_id: audiences[1].id,
+                audience_name: audiences[1].name.clone(),
+            }
+        );
+
+        let result = evaluate(
+            &flag,
+            Some(&enabled),
+            &overrides,
+            &audiences,
+            &context(json!({ "plan": "free" })),
+        );
+        assert!(result.is_enabled);
+        assert_eq!(result.value, Some(json!("default")));
+        assert_eq!(result.reason, EvaluationReason::Default);
+    }
+
+    #[test]
+    fn test_evaluate_rollouts() {
+        let beta = audience("plan", MatchOperator::Eq, "beta");
+        let mut excluded_override = flag_override(&beta, true, json!("beta"));
+        excluded_override.rollout = rollout(0.0);
+        let overrides = [excluded_override];
+        let audiences = [beta];
+        let flag = flag();
+        let mut rolled_out = state(&flag, true, json!("default"));
+        rolled_out.rollout = rollout(100.0);
+
+        // The override's rollout excludes everyone, so the state rollout applies
+        let result = evaluate(
+            &flag,
+            Some(&rolled_out),
+            &overrides,
+            &audiences,
+            &context(json!({ "plan": "beta", "user_id": "u-1" })),
+        );
+        assert!(result.is_enabled);
+        assert_eq!(result.value, Some(json!("default")));
+        assert!(matches!(
+            result.reason,
+            EvaluationReason::Rollout { bucket: Some(_) }
+        ));
+
+        // Without a bucketing attribute the subject is left out of the rollout
+        let result = evaluate(
+            &flag,
+            Some(&rolled_out),
+            &overrides,
+            &audiences,
+            &context(json!({ "plan": "free" })),
+        );
+        assert!(!result.is_enabled);
+        assert_eq!(result.reason, EvaluationReason::Rollout { bucket: None });
+    }
+}
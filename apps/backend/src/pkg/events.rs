@@ -0,0 +1,172 @@
+use crate::pkg::error::AppError;
+use crate::pkg::evaluation::Ruleset;
+use deadpool_redis::redis::{self, AsyncCommands};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Number of past events kept per environment for `Last-Event-ID` resumes
+pub const EVENT_HISTORY: isize = 1000;
+
+/// Pub/sub channel flag changes of an environment are published on
+pub fn channel(environment_id: Uuid) -> String {
+    format!("vexillum:env:{}:events", environment_id)
+}
+
+fn sequence_key(environment_id: Uuid) -> String {
+    format!("vexillum:env:{}:sequence", environment_id)
+}
+
+fn history_key(environment_id: Uuid) -> String {
+    format!("vexillum:env:{}:history", environment_id)
+}
+
+/// A flag changed in an environment
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct FlagChange {
+    pub flag_id: Uuid,
+    pub key: String,
+    /// The flag with its state, overrides and audiences, missing when the flag was deleted
+    pub ruleset: Option<Ruleset>,
+}
+
+/// A flag change numbered in the order it happened in its environment
+#[derive(Serialize, Deserialize)]
+pub struct FlagEvent {
+    pub id: u64,
+    #[serde(flatten)]
+    pub change: FlagChange,
+}
+
+/// Numbers an event, records it in the history and publishes it in one step, so
+/// concurrent writers can never publish ids out of order.
+/// The change's JSON object gets the id spliced in as its first field.
+const PUBLISH_SCRIPT: &str = r#"
+local id = redis.call('INCR', KEYS[1])
+local payload = '{"id":' .. id .. ',' .. string.sub(ARGV[1], 2)
+redis.call('ZADD', KEYS[2], id, payload)
+redis.call('ZREMRANGEBYRANK', KEYS[2], 0, -(tonumber(ARGV[2]) + 1))
+redis.call('PUBLISH', ARGV[3], payload)
+return id
+"#;
+
+/// Number the change, record it in the environment's history and fan it out
+/// to every instance streaming that environment
+pub async fn publish(
+    redis_pool: &deadpool_redis::Pool,
+    environment_id: Uuid,
+    change: FlagChange,
+) -> Result<u64, AppError> {
+    let mut conn = redis_pool.get().await?;
+
+    let change = serde_json::to_string(&change)?;
+
+    let id: u64 = redis::cmd("EVAL")
+        .arg(PUBLISH_SCRIPT)
+        .arg(2)
+        .arg(sequence_key(environment_id))
+        .arg(history_key(environment_id))
+        .arg(&change)
+        .arg(EVENT_HISTORY)
+        .arg(channel(environment_id))
+        .query_async(&mut conn)
+        .await?;
+
+    Ok(id)
+}
+
+/// Id of the latest event of an environment, 0 before the first change
+pub async fn current_id(
+    redis_pool: &deadpool_redis::Pool,
+    environment_id: Uuid,
+) -> Result<u64, AppError> {
+    let mut conn = redis_pool.get().await?;
+    let id: Option<u64> = conn.get(sequence_key(environment_id)).await?;
+    Ok(id.unwrap_or(0))
+}
+
+/// Events that happened after `last_id`, oldest first.
+/// Returns `None` when the history no longer reaches back that far and a snapshot is needed.
+pub async fn events_since(
+    redis_pool: &deadpool_redis::Pool,
+    environment_id: Uuid,
+    last_id: u64,
+) -> Result<Option<Vec<FlagEvent>>, AppError> {
+    let current = current_id(redis_pool, environment_id).await?;
+    if last_id > current {
+        return Ok(None);
+    }
+
+    let mut conn = redis_pool.get().await?;
+    let payloads: Vec<String> = conn
+        .zrangebyscore(history_key(environment_id), format!("({}", last_id), "+inf")
+        .await?;
+
+    let events = payloads
+        .iter()
+        .map(|payload| serde_json::from_str::<FlagEvent>(payload))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| AppError::InternalError("Failed to parse event data".to_string()))?;
+
+    // Events are recorded without gaps and trimmed oldest first, so the history
+    // reaches back far enough exactly when it starts right after `last_id`
+    let reaches_back = match events.first() {
+        Some(first) => first.id == last_id + 1,
+        None => current == last_id,
+    };
+    if !reaches_back {
+        return Ok(None);
+    }
+
+    Ok(Some(events))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_event_serialization() {
+        let event = FlagEvent {
+            id: 7,
+            change: FlagChange {
+                flag_id: Uuid::nil(),
+                key: "checkout".to_string(),
+                ruleset: None,
+            },
+        };
+
+        let value = serde_json::to_value(&event).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "id": 7,
+                "flag_id": Uuid::nil(),
+                "key": "checkout",
+                "ruleset": null,
+            })
+        );
+
+        let parsed: FlagEvent = serde_json::from_value(value).unwrap();
+        assert_eq!(parsed.id, 7);
+        assert_eq!(parsed.change.key, "checkout");
+    }
+
+    #[test]
+    fn test_publish_script_payload() {
+        let change = serde_json::to_string(&FlagChange {
+            flag_id: Uuid::nil(),
+            key: "checkout".to_string(),
+            ruleset: None,
+        })
+        .unwrap();
+
+        // The same splice the publish script does on the serialized change
+        let payload = format!("{{\"id\":{},{}", 12, &change[1..]);
+
+        let parsed: FlagEvent = serde_json::from_str(&payload).unwrap();
+        assert_eq!(parsed.id, 12);
+        assert_eq!(parsed.change.key, "checkout");
+    }
+}
@@ -1,97 +1,632 @@
 use std::fs;
-use std::path::Path;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{DateTime, Utc};
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters,
+    EllipticCurveKeyType, Jwk, OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse,
+    RSAKeyParameters, RSAKeyType,
+};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs8::{DecodePrivateKey, EncodePrivateKey, EncodePublicKey, LineEnding};
+use rsa::rand_core::OsRng;
+use rsa::traits::PublicKeyParts;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::pkg::config::Config;
 use crate::pkg::error::AppError;
 
-const PRIVATE_KEY_PATH: &str = ".keys/private.pem";
-const PUBLIC_KEY_PATH: &str = ".keys/public.pem";
+const MANIFEST_FILE: &str = "keys.json";
+
+/// Single pair written by earlier versions, adopted as the first key of the manifest
+const LEGACY_PRIVATE_KEY_FILE: &str = "private.pem";
+const LEGACY_PUBLIC_KEY_FILE: &str = "public.pem";
+
+const RSA_KEY_BITS: usize = 2048;
+
+/// Algorithm tokens are signed with, EdDSA and ES256 keys give much smaller tokens than RS256
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum SigningAlgorithm {
+    #[value(name = "RS256")]
+    RS256,
+    #[value(name = "ES256")]
+    ES256,
+    #[value(name = "EdDSA")]
+    EdDSA,
+}
+
+impl SigningAlgorithm {
+    pub fn jwt_algorithm(self) -> Algorithm {
+        match self {
+            SigningAlgorithm::RS256 => Algorithm::RS256,
+            SigningAlgorithm::ES256 => Algorithm::ES256,
+            SigningAlgorithm::EdDSA => Algorithm::EdDSA,
+        }
+    }
+
+    fn jwk_algorithm(self) -> jsonwebtoken::jwk::KeyAlgorithm {
+        match self {
+            SigningAlgorithm::RS256 => jsonwebtoken::jwk::KeyAlgorithm::RS256,
+            SigningAlgorithm::ES256 => jsonwebtoken::jwk::KeyAlgorithm::ES256,
+            SigningAlgorithm::EdDSA => jsonwebtoken::jwk::KeyAlgorithm::EdDSA,
+        }
+    }
+}
+
+/// A private key of one of the supported algorithms
+enum PrivateKey {
+    Rsa(rsa::RsaPrivateKey),
+    Ec(p256::SecretKey),
+    Ed(ed25519_dalek::SigningKey),
+}
+
+impl PrivateKey {
+    fn generate(algorithm: SigningAlgorithm) -> Result<Self, AppError> {
+        Ok(match algorithm {
+            SigningAlgorithm::RS256 => PrivateKey::Rsa(
+                rsa::RsaPrivateKey::new(&mut OsRng, RSA_KEY_BITS).map_err(|e| {
+                    AppError::InternalError(format!("Failed to generate RSA key: {}", e))
+                })?,
+            ),
+            SigningAlgorithm::ES256 => PrivateKey::Ec(p256::SecretKey::random(&mut OsRng)),
+            SigningAlgorithm::EdDSA => {
+                PrivateKey::Ed(ed25519_dalek::SigningKey::generate(&mut OsRng))
+            }
+        })
+    }
+
+    /// Parse a PKCS#8 key of any supported algorithm, or a PKCS#1 RSA or SEC1 EC key
+    fn from_pem(pem: &str) -> Option<Self> {
+        rsa::RsaPrivateKey::from_pkcs8_pem(pem)
+            .or_else(|_| rsa::RsaPrivateKey::from_pkcs1_pem(pem))
+            .map(PrivateKey::Rsa)
+            .ok()
+            .or_else(|| {
+                p256::SecretKey::from_pkcs8_pem(pem)
+                    .or_else(|_| p256::SecretKey::from_sec1_pem(pem))
+                    .map(PrivateKey::Ec)
+                    .ok()
+            })
+            .or_else(|| {
+                ed25519_dalek::SigningKey::from_pkcs8_pem(pem)
+                    .map(PrivateKey::Ed)
+                    .ok()
+            })
+    }
+
+    fn algorithm(&self) -> SigningAlgorithm {
+        match self {
+            PrivateKey::Rsa(_) => SigningAlgorithm::RS256,
+            PrivateKey::Ec(_) => SigningAlgorithm::ES256,
+            PrivateKey::Ed(_) => SigningAlgorithm::EdDSA,
+        }
+    }
+
+    fn to_pkcs8_pem(&self) -> Result<String, AppError> {
+        match self {
+            PrivateKey::Rsa(key) => key.to_pkcs8_pem(LineEnding::LF),
+            PrivateKey::Ec(key) => key.to_pkcs8_pem(LineEnding::LF),
+            PrivateKey::Ed(key) => key.to_pkcs8_pem(LineEnding::LF),
+        }
+        .map(|pem| pem.to_string())
+        .map_err(|e| AppError::InternalError(format!("Failed to encode private key: {}", e)))
+    }
+
+    fn public_key_pem(&self) -> Result<String, AppError> {
+        match self {
+            PrivateKey::Rsa(key) => key.to_public_key().to_public_key_pem(LineEnding::LF),
+            PrivateKey::Ec(key) => key.public_key().to_public_key_pem(LineEnding::LF),
+            PrivateKey::Ed(key) => key.verifying_key().to_public_key_pem(LineEnding::LF),
+        }
+        .map_err(|e| AppError::InternalError(format!("Failed to encode public key: {}", e)))
+    }
+
+    fn public_parameters(&self) -> AlgorithmParameters {
+        match self {
+            PrivateKey::Rsa(key) => AlgorithmParameters::RSA(RSAKeyParameters {
+                key_type: RSAKeyType::RSA,
+                n: URL_SAFE_NO_PAD.encode(key.n().to_bytes_be()),
+                e: URL_SAFE_NO_PAD.encode(key.e().to_bytes_be()),
+            }),
+            PrivateKey::Ec(key) => {
+                let point = key.public_key().to_encoded_point(false);
+                let coordinate = |c: Option<&p256::FieldBytes>| {
+                    URL_SAFE_NO_PAD.encode(c.map(|c| c.as_slice()).unwrap_or_default())
+                };
+                AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                    key_type: EllipticCurveKeyType::EC,
+                    curve: EllipticCurve::P256,
+                    x: coordinate(point.x()),
+                    y: coordinate(point.y()),
+                })
+            }
+            PrivateKey::Ed(key) => AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: URL_SAFE_NO_PAD.encode(key.verifying_key().as_bytes()),
+            }),
+        }
+    }
+}
+
+/// RFC 7638 thumbprint of a public JWK, the required members in lexicographic order
+fn thumbprint(parameters: &AlgorithmParameters) -> String {
+    let canonical = match parameters {
+        AlgorithmParameters::RSA(rsa) => {
+            format!(r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#, rsa.e, rsa.n)
+        }
+        AlgorithmParameters::EllipticCurve(ec) => format!(
+            r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#,
+            ec.x, ec.y
+        ),
+        AlgorithmParameters::OctetKeyPair(okp) => {
+            format!(r#"{{"crv":"Ed25519","kty":"OKP","x":"{}"}}"#, okp.x)
+        }
+        // Symmetric keys are never used for signing tokens
+        AlgorithmParameters::OctetKey(_) => unreachable!(),
+    };
+
+    URL_SAFE_NO_PAD.encode(Sha256::digest(canonical.as_bytes()))
+}
+
+/// A key pair in PEM format, identified by the thumbprint of its public key
+pub struct KeyPair {
+    pub kid: String,
+    pub algorithm: SigningAlgorithm,
+    /// PKCS#8, whatever format the key was loaded from
+    pub private_key: Vec<u8>,
+    pub public_key: Vec<u8>,
+    parameters: AlgorithmParameters,
+}
+
+impl KeyPair {
+    /// Generate a new key pair in process
+    pub fn generate(algorithm: SigningAlgorithm) -> Result<Self, AppError> {
+        Self::from_private_key(PrivateKey::generate(algorithm)?)
+    }
+
+    /// Load a private key, detecting its algorithm and deriving its public key
+    pub fn from_private_pem(pem: &[u8]) -> Result<Self, AppError> {
+        let private_key = std::str::from_utf8(pem)
+            .ok()
+            .and_then(PrivateKey::from_pem)
+            .ok_or(AppError::InternalError(
+                "Failed to load private key: expected an RSA, P-256 or Ed25519 key in PEM format"
+                    .to_string(),
+            ))?;
+
+        Self::from_private_key(private_key)
+    }
+
+    fn from_private_key(private_key: PrivateKey) -> Result<Self, AppError> {
+        let parameters = private_key.public_parameters();
+
+        Ok(Self {
+            kid: thumbprint(&parameters),
+            algorithm: private_key.algorithm(),
+            private_key: private_key.to_pkcs8_pem()?.into_bytes(),
+            public_key: private_key.public_key_pem()?.into_bytes(),
+            parameters,
+        })
+    }
+
+    /// The public key as a JWK, for publishing in the JWKS
+    pub fn jwk(&self) -> Jwk {
+        Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                key_algorithm: Some(self.algorithm.jwk_algorithm()),
+                key_id: Some(self.kid.clone()),
+                ..Default::default()
+            },
+            algorithm: self.parameters.clone(),
+        }
+    }
+
+    pub fn encoding_key(&self) -> Result<EncodingKey, AppError> {
+        match self.algorithm {
+            SigningAlgorithm::RS256 => EncodingKey::from_rsa_pem(&self.private_key),
+            SigningAlgorithm::ES256 => EncodingKey::from_ec_pem(&self.private_key),
+            SigningAlgorithm::EdDSA => EncodingKey::from_ed_pem(&self.private_key),
+        }
+        .map_err(|e| AppError::InternalError(format!("Failed to load private key: {}", e)))
+    }
+
+    pub fn decoding_key(&self) -> Result<DecodingKey, AppError> {
+        match self.algorithm {
+            SigningAlgorithm::RS256 => DecodingKey::from_rsa_pem(&self.public_key),
+            SigningAlgorithm::ES256 => DecodingKey::from_ec_pem(&self.public_key),
+            SigningAlgorithm::EdDSA => DecodingKey::from_ed_pem(&self.public_key),
+        }
+        .map_err(|e| AppError::InternalError(format!("Failed to load public key: {}", e)))
+    }
+}
+
+/// The key new tokens are signed with, and the keys tokens signed earlier are still verified with
+pub struct KeyRing {
+    pub active: KeyPair,
+    pub previous: Vec<KeyPair>,
+}
+
+/// Load the signing keys. A key given in the config is used as is,
+/// otherwise keys are managed in the key directory.
+pub fn load_key_ring(config: &Config) -> Result<KeyRing, AppError> {
+    let configured = match (&config.jwt_private_key, &config.jwt_private_key_file) {
+        // Env vars often can't hold newlines, so escaped ones are accepted too
+        (Some(pem), _) => Some(pem.replace("\\n", "\n").into_bytes()),
+        (None, Some(path)) => Some(fs::read(path).map_err(|e| io_error("read", path, e))?),
+        (None, None) => None,
+    };
+
+    match configured {
+        Some(pem) => {
+            let active = KeyPair::from_private_pem(&pem)?;
+            println!(
+                "Using configured {:?} signing key {}",
+                active.algorithm, active.kid
+            );
+            Ok(KeyRing {
+                active,
+                previous: Vec::new(),
+            })
+        }
+        None => KeyManager::new(&config.keys_dir)
+            .load_or_generate_keys(config.jwt_algorithm, config.key_grace_period),
+    }
+}
+
+/// Keys on disk, newest first. The active key is the one that was never retired.
+#[derive(Serialize, Deserialize, Default)]
+struct Manifest {
+    keys: Vec<ManifestEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ManifestEntry {
+    kid: String,
+    created_at: DateTime<Utc>,
+    retired_at: Option<DateTime<Utc>>,
+}
+
+/// Outcome of a key rotation
+pub struct Rotation {
+    pub promoted: String,
+    pub retired: Option<String>,
+    pub pruned: Vec<String>,
+}
+
+fn io_error(action: &str, path: &Path, e: std::io::Error) -> AppError {
+    AppError::InternalError(format!("Failed to {} {}: {}", action, path.display(), e))
+}
+
+/// Write a file only its owner can read
+fn write_private(path: &Path, content: &[u8]) -> Result<(), AppError> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
 
-/// Key management - generates or loads RSA keys for JWT signing
-pub struct KeyManager;
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(content))
+        .map_err(|e| io_error("write", path, e))
+}
+
+/// Key management - generates, loads and rotates the JWT signing keys in a directory
+pub struct KeyManager {
+    dir: PathBuf,
+}
 
 impl KeyManager {
-    /// Load or generate RSA key pair
-    /// Returns (private_key_bytes, public_key_bytes)
-    pub fn load_or_generate_keys() -> Result<(Vec<u8>, Vec<u8>), AppError> {
-        // Try to load existing keys
-        if Path::new(PRIVATE_KEY_PATH).exists() && Path::new(PUBLIC_KEY_PATH).exists() {
-            println!("Loading existing RSA keys from disk...");
-            let private_key = fs::read(PRIVATE_KEY_PATH).map_err(|e| {
-                AppError::InternalError(format!("Failed to read private key: {}", e))
-            })?;
-            let public_key = fs::read(PUBLIC_KEY_PATH).map_err(|e| {
-                AppError::InternalError(format!("Failed to read public key: {}", e))
-            })?;
-            return Ok((private_key, public_key));
-        }
-
-        // Generate new keys
-        println!("Generating new RSA keys...");
-        Self::generate_keys()
-    }
-
-    /// Generate new RSA key pair using openssl
-    fn generate_keys() -> Result<(Vec<u8>, Vec<u8>), AppError> {
-        use std::process::Command;
-
-        // Create keys directory if it doesn't exist
-        fs::create_dir_all(".keys").map_err(|e| {
-            AppError::InternalError(format!("Failed to create .keys directory: {}", e))
-        })?;
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
 
-        // Generate private key (2048-bit RSA)
-        let output = Command::new("openssl")
-            .args(&["genrsa", "-out", PRIVATE_KEY_PATH, "2048"])
-            .output()
-            .map_err(|e| {
-                AppError::InternalError(format!("Failed to execute openssl genrsa: {}", e))
-            })?;
+    fn private_key_path(&self, kid: &str) -> PathBuf {
+        self.dir.join(format!("{}.pem", kid))
+    }
 
-        if !output.status.success() {
-            let err = String::from_utf8_lossy(&output.stderr);
-            return Err(AppError::InternalError(format!(
-                "openssl genrsa failed: {}",
-                err
-            )));
+    fn public_key_path(&self, kid: &str) -> PathBuf {
+        self.dir.join(format!("{}.pub.pem", kid))
+    }
+
+    fn manifest_path(&self) -> PathBuf {
+        self.dir.join(MANIFEST_FILE)
+    }
+
+    /// Load the key ring, generating a first `algorithm` key when there is none.
+    /// Keys retired more than `grace_period` seconds ago are no longer trusted.
+    pub fn load_or_generate_keys(
+        &self,
+        algorithm: SigningAlgorithm,
+        grace_period: i64,
+    ) -> Result<KeyRing, AppError> {
+        let mut manifest = self.read_manifest()?;
+
+        if manifest.keys.is_empty() {
+            println!("Generating new {:?} signing key...", algorithm);
+            let pair = self.generate_keys(algorithm)?;
+            Self::add_key(&mut manifest, &pair);
+            self.write_manifest(&manifest)?;
+        } else {
+            println!(
+                "Loading existing signing keys from {}...",
+                self.dir.display()
+            );
+        }
+
+        self.load_ring(&manifest, grace_period)
+    }
+
+    /// Generate a new `algorithm` key and sign with it from now on. The previous active key keeps
+    /// verifying tokens until its grace period ends, keys past theirs are deleted.
+    /// Running servers pick the new key up on restart.
+    pub fn rotate(
+        &self,
+        algorithm: SigningAlgorithm,
+        grace_period: i64,
+    ) -> Result<Rotation, AppError> {
+        let mut manifest = self.read_manifest()?;
+        let now = Utc::now();
+
+        let retired = manifest
+            .keys
+            .iter_mut()
+            .find(|entry| entry.retired_at.is_none())
+            .map(|entry| {
+                entry.retired_at = Some(now);
+                entry.kid.clone()
+            });
+
+        let pair = self.generate_keys(algorithm)?;
+        Self::add_key(&mut manifest, &pair);
+
+        let (expired, kept): (Vec<_>, Vec<_>) = manifest
+            .keys
+            .into_iter()
+            .partition(|entry| Self::is_expired(entry, grace_period));
+        manifest.keys = kept;
+        self.write_manifest(&manifest)?;
+
+        // Removed only after the manifest stops referencing them
+        for entry in &expired {
+            for path in [
+                self.private_key_path(&entry.kid),
+                self.public_key_path(&entry.kid),
+            ] {
+                if path.exists() {
+                    fs::remove_file(&path).map_err(|e| io_error("remove", &path, e))?;
+                }
+            }
+        }
+
+        Ok(Rotation {
+            promoted: pair.kid,
+            retired,
+            pruned: expired.into_iter().map(|entry| entry.kid).collect(),
+        })
+    }
+
+    fn is_expired(entry: &ManifestEntry, grace_period: i64) -> bool {
+        entry.retired_at.is_some_and(|retired_at| {
+            Utc::now() - retired_at > chrono::Duration::seconds(grace_period)
+        })
+    }
+
+    fn add_key(manifest: &mut Manifest, pair: &KeyPair) {
+        manifest.keys.insert(
+            0,
+            ManifestEntry {
+                kid: pair.kid.clone(),
+                created_at: Utc::now(),
+                retired_at: None,
+            },
+        );
+    }
+
+    fn load_ring(&self, manifest: &Manifest, grace_period: i64) -> Result<KeyRing, AppError> {
+        let mut active = None;
+        let mut previous = Vec::new();
+
+        for entry in &manifest.keys {
+            if Self::is_expired(entry, grace_period) {
+                continue;
+            }
+
+            let pair = self.read_key(&entry.kid)?;
+            match entry.retired_at {
+                None if active.is_none() => active = Some(pair),
+                _ => previous.push(pair),
+            }
         }
 
-        // Extract public key from private key
-        let output = Command::new("openssl")
-            .args(&[
-                "rsa",
-                "-in",
-                PRIVATE_KEY_PATH,
-                "-pubout",
-                "-out",
-                PUBLIC_KEY_PATH,
-            ])
-            .output()
-            .map_err(|e| {
-                AppError::InternalError(format!("Failed to execute openssl rsa: {}", e))
-            })?;
-
-        if !output.status.success() {
-            let err = String::from_utf8_lossy(&output.stderr);
+        let active = active.ok_or(AppError::InternalError(
+            "No active signing key, run `backend rotate-keys`".to_string(),
+        ))?;
+
+        Ok(KeyRing { active, previous })
+    }
+
+    fn read_key(&self, kid: &str) -> Result<KeyPair, AppError> {
+        let path = self.private_key_path(kid);
+        let pair =
+            KeyPair::from_private_pem(&fs::read(&path).map_err(|e| io_error("read", &path, e))?)?;
+
+        if pair.kid != kid {
             return Err(AppError::InternalError(format!(
-                "openssl rsa failed: {}",
-                err
+                "Key {} does not match its file {}",
+                pair.kid,
+                path.display()
             )));
         }
 
-        // Read the generated keys
-        let private_key = fs::read(PRIVATE_KEY_PATH).map_err(|e| {
-            AppError::InternalError(format!("Failed to read generated private key: {}", e))
-        })?;
-        let public_key = fs::read(PUBLIC_KEY_PATH).map_err(|e| {
-            AppError::InternalError(format!("Failed to read generated public key: {}", e))
+        Ok(pair)
+    }
+
+    /// Read the manifest, adopting a legacy `private.pem`/`public.pem` pair when there is none
+    fn read_manifest(&self) -> Result<Manifest, AppError> {
+        let path = self.manifest_path();
+        if path.exists() {
+            let content = fs::read(&path).map_err(|e| io_error("read", &path, e))?;
+            return serde_json::from_slice(&content).map_err(|e| {
+                AppError::InternalError(format!("Invalid key manifest {}: {}", path.display(), e))
+            });
+        }
+
+        let mut manifest = Manifest::default();
+        let legacy_private = self.dir.join(LEGACY_PRIVATE_KEY_FILE);
+        let legacy_public = self.dir.join(LEGACY_PUBLIC_KEY_FILE);
+        if legacy_private.exists() && legacy_public.exists() {
+            let pair = KeyPair::from_private_pem(
+                &fs::read(&legacy_private).map_err(|e| io_error("read", &legacy_private, e))?,
+            )?;
+
+            // Rewritten rather than moved, so the key ends up with restrictive permissions
+            write_private(&self.private_key_path(&pair.kid), &pair.private_key)?;
+            fs::write(self.public_key_path(&pair.kid), &pair.public_key)
+                .map_err(|e| io_error("write", &self.public_key_path(&pair.kid), e))?;
+            for legacy in [&legacy_private, &legacy_public] {
+                fs::remove_file(legacy).map_err(|e| io_error("remove", legacy, e))?;
+            }
+
+            println!("Adopted existing signing key as {}", pair.kid);
+            manifest.keys.push(ManifestEntry {
+                kid: pair.kid,
+                created_at: Utc::now(),
+                retired_at: None,
+            });
+            self.write_manifest(&manifest)?;
+        }
+
+        Ok(manifest)
+    }
+
+    fn write_manifest(&self, manifest: &Manifest) -> Result<(), AppError> {
+        let path = self.manifest_path();
+        let content = serde_json::to_vec_pretty(manifest).map_err(|e| {
+            AppError::InternalError(format!("Failed to encode key manifest: {}", e))
         })?;
 
+        // Written aside and renamed, so a crash never leaves a truncated manifest
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, content).map_err(|e| io_error("write", &tmp_path, e))?;
+        fs::rename(&tmp_path, &path).map_err(|e| io_error("write", &path, e))
+    }
+
+    /// Generate a new key pair, stored under its key ID
+    fn generate_keys(&self, algorithm: SigningAlgorithm) -> Result<KeyPair, AppError> {
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder
+            .create(&self.dir)
+            .map_err(|e| io_error("create", &self.dir, e))?;
+
+        let pair = KeyPair::generate(algorithm)?;
+
+        let private_path = self.private_key_path(&pair.kid);
+        let public_path = self.public_key_path(&pair.kid);
+        write_private(&private_path, &pair.private_key)?;
+        fs::write(&public_path, &pair.public_key)
+            .map_err(|e| io_error("write", &public_path, e))?;
+
         println!(
-            "RSA keys generated successfully at {} and {}",
-            PRIVATE_KEY_PATH, PUBLIC_KEY_PATH
+            "{:?} keys generated successfully at {} and {}",
+            algorithm,
+            private_path.display(),
+            public_path.display()
+        );
+
+        Ok(pair)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pkg::jwt::tests::{TEST_PRIVATE_KEY, TEST_PUBLIC_KEY};
+    use jsonwebtoken::jwk::ThumbprintHash;
+
+    #[test]
+    fn test_load_rsa_key() {
+        let pair = KeyPair::from_private_pem(TEST_PRIVATE_KEY).unwrap();
+        assert_eq!(pair.algorithm, SigningAlgorithm::RS256);
+        assert_eq!(
+            String::from_utf8(pair.public_key.clone()).unwrap().trim(),
+            std::str::from_utf8(TEST_PUBLIC_KEY).unwrap().trim()
         );
-        println!("Keys are stored for persistence across restarts.");
 
-        Ok((private_key, public_key))
+        // Same key ID as jsonwebtoken derives, so keys keep their ID across versions
+        let jwk = Jwk::from_encoding_key(&pair.encoding_key().unwrap(), Algorithm::RS256).unwrap();
+        assert_eq!(pair.kid, jwk.thumbprint(ThumbprintHash::SHA256));
+
+        // Normalized to PKCS#8, loading that again gives the same key
+        let reloaded = KeyPair::from_private_pem(&pair.private_key).unwrap();
+        assert_eq!(reloaded.kid, pair.kid);
+    }
+
+    #[test]
+    fn test_generated_keys() {
+        for (algorithm, kty) in [
+            (SigningAlgorithm::RS256, "RSA"),
+            (SigningAlgorithm::ES256, "EC"),
+            (SigningAlgorithm::EdDSA, "OKP"),
+        ] {
+            let pair = KeyPair::generate(algorithm).unwrap();
+            assert_eq!(pair.algorithm, algorithm);
+            assert!(pair.encoding_key().is_ok());
+            assert!(pair.decoding_key().is_ok());
+            assert_eq!(
+                KeyPair::from_private_pem(&pair.private_key).unwrap().kid,
+                pair.kid
+            );
+
+            let jwk = serde_json::to_value(pair.jwk()).unwrap();
+            assert_eq!(jwk["kty"], kty);
+            assert_eq!(jwk["kid"], pair.kid);
+            assert_eq!(jwk["use"], "sig");
+            assert!(jwk.get("d").is_none());
+        }
+
+        assert!(KeyPair::from_private_pem(b"not a key").is_err());
+    }
+
+    #[test]
+    fn test_key_directory() {
+        let dir = std::env::temp_dir().join(format!("vexillum-keys-{}", uuid::Uuid::new_v4()));
+        let manager = KeyManager::new(&dir);
+
+        let ring = manager
+            .load_or_generate_keys(SigningAlgorithm::EdDSA, 60)
+            .unwrap();
+        assert_eq!(ring.active.algorithm, SigningAlgorithm::EdDSA);
+        assert!(ring.previous.is_empty());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode(&dir), 0o700);
+            assert_eq!(mode(&manager.private_key_path(&ring.active.kid)), 0o600);
+        }
+
+        let rotation = manager.rotate(SigningAlgorithm::ES256, 60).unwrap();
+        assert_eq!(rotation.retired.as_deref(), Some(ring.active.kid.as_str()));
+
+        let ring = manager
+            .load_or_generate_keys(SigningAlgorithm::EdDSA, 60)
+            .unwrap();
+        assert_eq!(ring.active.kid, rotation.promoted);
+        assert_eq!(ring.active.algorithm, SigningAlgorithm::ES256);
+        assert_eq!(ring.previous.len(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
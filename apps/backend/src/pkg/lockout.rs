@@ -0,0 +1,124 @@
+use crate::models::db::Users;
+use crate::pkg::audit::{self, Actor, AuditEvent};
+use crate::pkg::config::Config;
+use crate::pkg::error::AppError;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+/// Seconds an account is locked for after `failures` failed passwords in a row, none below the threshold.
+/// Every failure past the threshold doubles the lockout, up to the maximum.
+pub fn duration(config: &Config, failures: i32) -> Option<i64> {
+    let past_threshold = failures.checked_sub(config.lockout_threshold.max(1))?;
+    if past_threshold < 0 {
+        return None;
+    }
+
+    let factor = 1i64
+        .checked_shl(past_threshold.min(62) as u32)
+        .unwrap_or(i64::MAX);
+    Some(
+        config
+            .lockout_duration
+            .saturating_mul(factor)
+            .min(config.lockout_max_duration),
+    )
+}
+
+/// Reject sign ins to a locked account, whether or not the password is right
+pub fn check(user: &Users) -> Result<(), AppError> {
+    match user.locked_until {
+        Some(until) if until > Utc::now() => Err(AppError::TooManyRequests(
+            "Too many failed sign in attempts, the account is temporarily locked".to_string(),
+            (until - Utc::now()).num_seconds().max(1) as u64,
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Count a failed password, locking the account once there were too many.
+/// Lockouts are recorded in the audit log of the user's org.
+pub async fn record_failure(
+    client: &impl deadpool_postgres::GenericClient,
+    config: &Config,
+    user_id: Uuid,
+) -> Result<(), AppError> {
+    let failures: i32 = client
+        .query_one(
+            "UPDATE users SET failed_login_attempts = failed_login_attempts + 1
+             WHERE id = $1
+             RETURNING failed_login_attempts",
+            &[&user_id],
+        )
+        .await?
+        .get(0);
+
+    let Some(seconds) = duration(config, failures) else {
+        return Ok(());
+    };
+
+    let locked_until: DateTime<Utc> = client
+        .query_one(
+            "UPDATE users SET locked_until = current_timestamp + make_interval(secs => $2)
+             WHERE id = $1
+             RETURNING locked_until",
+            &[&user_id, &(seconds as f64)],
+        )
+        .await?
+        .get(0);
+
+    eprintln!(
+        "Locked user {} until {} after {} failed sign in attempts",
+        user_id, locked_until, failures
+    );
+    audit::record(
+        client,
+        AuditEvent::new(Actor::User(user_id), "user.locked_out", "user", user_id).after(
+            &serde_json::json!({
+                "failed_login_attempts": failures,
+                "locked_until": locked_until,
+            }),
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Forget failed attempts after a successful sign in
+pub async fn reset(
+    client: &impl deadpool_postgres::GenericClient,
+    user: &Users,
+) -> Result<(), AppError> {
+    if user.failed_login_attempts > 0 || user.locked_until.is_some() {
+        client
+            .execute(
+                "UPDATE users SET failed_login_attempts = 0, locked_until = NULL WHERE id = $1",
+                &[&user.id],
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_lockouts_grow() {
+        let mut config = Config::parse_from(["backend"]);
+        config.lockout_threshold = 5;
+        config.lockout_duration = 60;
+        config.lockout_max_duration = 3600;
+
+        assert_eq!(duration(&config, 0), None);
+        assert_eq!(duration(&config, 4), None);
+        assert_eq!(duration(&config, 5), Some(60));
+        assert_eq!(duration(&config, 6), Some(120));
+        assert_eq!(duration(&config, 8), Some(480));
+        assert_eq!(duration(&config, 11), Some(3600));
+        assert_eq!(duration(&config, i32::MAX), Some(3600));
+    }
+}
@@ -0,0 +1,157 @@
+use std::path::PathBuf;
+
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use tokio::task::JoinHandle;
+
+use crate::pkg::config::{Config, MailTransport, SmtpTls};
+use crate::pkg::emails::{Email, Template};
+use crate::pkg::error::AppError;
+
+/// Where emails go
+#[derive(Clone)]
+enum Transport {
+    Smtp(AsyncSmtpTransport<Tokio1Executor>),
+    /// Logged, and written to a directory as JSON when one is configured. For local development and tests.
+    Outbox(Option<PathBuf>),
+}
+
+/// Sends templated emails in the background, so requests never wait on the mail server
+#[derive(Clone)]
+pub struct Mailer {
+    transport: Transport,
+    from: Mailbox,
+    frontend_url: String,
+}
+
+impl Mailer {
+    pub fn from_config(config: &Config) -> Result<Self, AppError> {
+        let from = config.mail_from.parse().map_err(|e| {
+            AppError::InternalError(format!("Invalid MAIL_FROM {}: {}", config.mail_from, e))
+        })?;
+
+        let transport = match config.mail_transport {
+            MailTransport::Smtp => {
+                let smtp_error =
+                    |e| AppError::InternalError(format!("Invalid SMTP configuration: {}", e));
+                let mut builder = match config.smtp_tls {
+                    SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.smtp_host)
+                        .map_err(smtp_error)?,
+                    SmtpTls::Starttls => {
+                        AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.smtp_host)
+                            .map_err(smtp_error)?
+                    }
+                    SmtpTls::None => {
+                        AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.smtp_host)
+                    }
+                }
+                .port(config.smtp_port);
+
+                if let (Some(username), Some(password)) =
+                    (&config.smtp_username, &config.smtp_password)
+                {
+                    builder =
+                        builder.credentials(Credentials::new(username.clone(), password.clone()));
+                }
+
+                Transport::Smtp(builder.build())
+            }
+            MailTransport::Outbox => Transport::Outbox(config.mail_outbox_dir.clone()),
+        };
+
+        Ok(Self {
+            transport,
+            from,
+            frontend_url: config.frontend_url.clone(),
+        })
+    }
+
+    /// Render `template` for `to` and send it in a background task.
+    /// Failures are logged, the returned handle only matters to callers that want to wait.
+    pub fn send(&self, to: &str, template: Template<'_>) -> JoinHandle<()> {
+        let email = template.render(to, &self.frontend_url);
+        let mailer = self.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = mailer.deliver(&email).await {
+                eprintln!(
+                    "Failed to send \"{}\" to {}: {}",
+                    email.subject, email.to, e
+                );
+            }
+        })
+    }
+
+    async fn deliver(&self, email: &Email) -> Result<(), AppError> {
+        match &self.transport {
+            Transport::Smtp(smtp) => {
+                let message = Message::builder()
+                    .from(self.from.clone())
+                    .to(email.to.parse().map_err(|e| {
+                        AppError::BadRequest(format!("Invalid recipient {}: {}", email.to, e))
+                    })?)
+                    .subject(&email.subject)
+                    .multipart(MultiPart::alternative_plain_html(
+                        email.text.clone(),
+                        email.html.clone(),
+                    ))
+                    .map_err(|e| {
+                        AppError::InternalError(format!("Failed to build email: {}", e))
+                    })?;
+
+                smtp.send(message)
+                    .await
+                    .map_err(|e| AppError::InternalError(format!("SMTP error: {}", e)))?;
+            }
+            Transport::Outbox(dir) => {
+                println!(
+                    "Outbox: \"{}\" to {}\n{}",
+                    email.subject, email.to, email.text
+                );
+
+                if let Some(dir) = dir {
+                    let path = dir.join(format!(
+                        "{}-{}.json",
+                        chrono::Utc::now().format("%Y%m%d%H%M%S%6f"),
+                        uuid::Uuid::new_v4()
+                    ));
+                    tokio::fs::create_dir_all(dir).await?;
+                    tokio::fs::write(&path, serde_json::to_vec_pretty(email)?).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[tokio::test]
+    async fn test_outbox_writes_emails() {
+        let dir = std::env::temp_dir().join(format!("vexillum-outbox-{}", uuid::Uuid::new_v4()));
+        let mut config = Config::parse_from(["backend"]);
+        config.mail_outbox_dir = Some(dir.clone());
+
+        let mailer = Mailer::from_config(&config).unwrap();
+        mailer
+            .send("user@example.com", Template::MagicLink { token: "abc" })
+            .await
+            .unwrap();
+
+        let entry = std::fs::read_dir(&dir).unwrap().next().unwrap().unwrap();
+        let email: Email = serde_json::from_slice(&std::fs::read(entry.path()).unwrap()).unwrap();
+        assert_eq!(email.to, "user@example.com");
+        assert!(
+            email
+                .text
+                .contains(&format!("{}/auth/magic?token=abc", config.frontend_url))
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,283 @@
+use crate::models::db::UserMfa;
+use crate::pkg::auth::hash_token;
+use crate::pkg::error::AppError;
+use crate::pkg::jwt::MFA_CHALLENGE_EXPIRY;
+use deadpool_redis::redis::{self, AsyncCommands};
+use pgmap::FromRow;
+use rand::TryRngCore;
+use totp_rs::{Builder, Secret, Totp};
+use uuid::Uuid;
+
+/// Issuer shown next to the account in authenticator apps
+const TOTP_ISSUER: &str = "Vexillum";
+
+/// Bytes of randomness in a TOTP secret, the size RFC 4226 recommends
+const SECRET_BYTES: usize = 20;
+
+/// Number of recovery codes handed out at once
+const RECOVERY_CODES: usize = 10;
+
+/// Wrong codes a single login challenge takes before it stops working
+pub const MAX_CHALLENGE_FAILURES: u64 = 5;
+
+/// Characters recovery codes are made of, without the easily confused 0, 1, i, l and o
+const RECOVERY_ALPHABET: &[u8] = b"23456789abcdefghjkmnpqrstuvwxyz";
+
+/// The factor a user proved themselves with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SecondFactor {
+    Totp,
+    RecoveryCode,
+}
+
+/// Generate a new TOTP secret, base32 encoded the way authenticator apps expect it
+pub fn generate_secret() -> Result<String, AppError> {
+    let mut bytes = [0u8; SECRET_BYTES];
+    rand::rngs::OsRng
+        .try_fill_bytes(&mut bytes)
+        .map_err(|_| AppError::InternalError("Failed to generate TOTP secret".to_string()))?;
+
+    Ok(Secret::from(bytes.to_vec()).to_base32())
+}
+
+fn totp(secret: &str, account: &str) -> Result<Totp, AppError> {
+    let secret = Secret::try_from_base32(secret)
+        .map_err(|e| AppError::InternalError(format!("Invalid TOTP secret: {}", e)))?;
+
+    Builder::new()
+        .with_secret(secret)
+        .with_account_name(account)
+        .with_issuer(Some(TOTP_ISSUER))
+        .build()
+        .map_err(|e| AppError::InternalError(format!("Invalid TOTP parameters: {}", e)))
+}
+
+/// `otpauth://` URI authenticator apps enroll with, usually shown as a QR code
+pub fn provisioning_uri(secret: &str, account: &str) -> Result<String, AppError> {
+    totp(secret, account)?
+        .to_url()
+        .map_err(|e| AppError::InternalError(format!("Failed to build provisioning URI: {}", e)))
+}
+
+/// Step the TOTP `code` is valid for right now, allowing one step of clock drift
+fn totp_step(secret: &str, code: &str) -> Result<Option<i64>, AppError> {
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    Ok(totp(secret, "")?
+        .check_current(&code)
+        .map(|step| step as i64))
+}
+
+/// Recovery codes are compared without case, dashes or spaces
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn generate_recovery_code() -> Result<String, AppError> {
+    // Bytes at or above the largest multiple of the alphabet size are thrown away,
+    // otherwise `byte % len` would favour the first characters
+    let limit = 256 - 256 % RECOVERY_ALPHABET.len();
+    let mut chars = String::with_capacity(10);
+    let mut bytes = [0u8; 16];
+
+    while chars.len() < 10 {
+        rand::rngs::OsRng
+            .try_fill_bytes(&mut bytes)
+            .map_err(|_| AppError::InternalError("Failed to generate recovery code".to_string()))?;
+
+        chars.extend(
+            bytes
+                .iter()
+                .filter(|b| (**b as usize) < limit)
+                .map(|b| RECOVERY_ALPHABET[*b as usize % RECOVERY_ALPHABET.len()] as char)
+                .take(10 - chars.len()),
+        );
+    }
+
+    Ok(format!("{}-{}", &chars[..5], &chars[5..]))
+}
+
+fn parse_mfa(row: &tokio_postgres::Row) -> Result<UserMfa, AppError> {
+    UserMfa::from_row(row)
+        .map_err(|_| AppError::InternalError("Failed to parse MFA data".to_string()))
+}
+
+/// Whether the user confirmed a TOTP enrollment
+pub async fn is_enabled(
+    client: &impl deadpool_postgres::GenericClient,
+    user_id: Uuid,
+) -> Result<bool, AppError> {
+    Ok(client
+        .query_opt(
+            "SELECT 1 FROM user_mfa WHERE user_id = $1 AND enabled_at IS NOT NULL",
+            &[&user_id],
+        )
+        .await?
+        .is_some())
+}
+
+/// Check `code` against the user's TOTP secret, accepting every step once,
+/// and against their recovery codes, using one up.
+/// Unconfirmed enrollments are checked too, so the first code can confirm them.
+pub async fn verify_code(
+    client: &impl deadpool_postgres::GenericClient,
+    user_id: Uuid,
+    code: &str,
+) -> Result<Option<SecondFactor>, AppError> {
+    let Some(row) = client
+        .query_opt("SELECT * FROM user_mfa WHERE user_id = $1", &[&user_id])
+        .await?
+    else {
+        return Ok(None);
+    };
+    let mfa = parse_mfa(&row)?;
+
+    if let Some(step) = totp_step(&mfa.totp_secret, code)? {
+        // Conditional update, so a code cannot be replayed even by concurrent requests
+        let accepted = client
+            .execute(
+                "UPDATE user_mfa SET last_used_step = $2
+                 WHERE user_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)",
+                &[&user_id, &step],
+            )
+            .await?;
+
+        return Ok((accepted == 1).then_some(SecondFactor::Totp));
+    }
+
+    let used = client
+        .execute(
+            "UPDATE mfa_recovery_codes SET used_at = current_timestamp
+             WHERE id = (
+                 SELECT id FROM mfa_recovery_codes
+                 WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+                 LIMIT 1
+             ) AND used_at IS NULL",
+            &[&user_id, &hash_token(&normalize_recovery_code(code))],
+        )
+        .await?;
+
+    Ok((used == 1).then_some(SecondFactor::RecoveryCode))
+}
+
+/// Replace the user's recovery codes with new ones, returning them in plaintext to show once
+pub async fn replace_recovery_codes(
+    client: &impl deadpool_postgres::GenericClient,
+    user_id: Uuid,
+) -> Result<Vec<String>, AppError> {
+    client
+        .execute(
+            "DELETE FROM mfa_recovery_codes WHERE user_id = $1",
+            &[&user_id],
+        )
+        .await?;
+
+    let mut codes = Vec::with_capacity(RECOVERY_CODES);
+    for _ in 0..RECOVERY_CODES {
+        let code = generate_recovery_code()?;
+        client
+            .execute(
+                "INSERT INTO mfa_recovery_codes (user_id, code_hash) VALUES ($1, $2)",
+                &[&user_id, &hash_token(&normalize_recovery_code(&code))],
+            )
+            .await?;
+        codes.push(code);
+    }
+
+    Ok(codes)
+}
+
+/// Number of recovery codes the user has left
+pub async fn remaining_recovery_codes(
+    client: &impl deadpool_postgres::GenericClient,
+    user_id: Uuid,
+) -> Result<i64, AppError> {
+    Ok(client
+        .query_one(
+            "SELECT count(*) FROM mfa_recovery_codes WHERE user_id = $1 AND used_at IS NULL",
+            &[&user_id],
+        )
+        .await?
+        .get(0))
+}
+
+fn challenge_failures_key(challenge_id: &str) -> String {
+    format!("vexillum:mfa_challenge:{}:failures", challenge_id)
+}
+
+/// Wrong codes tried so far against the challenge with the token id `challenge_id`
+pub async fn challenge_failures(
+    redis_pool: &deadpool_redis::Pool,
+    challenge_id: &str,
+) -> Result<u64, AppError> {
+    let mut conn = redis_pool.get().await?;
+    let failures: Option<u64> = conn.get(challenge_failures_key(challenge_id)).await?;
+    Ok(failures.unwrap_or(0))
+}
+
+/// Count a wrong code against a challenge, kept for as long as the challenge is valid
+pub async fn record_challenge_failure(
+    redis_pool: &deadpool_redis::Pool,
+    challenge_id: &str,
+) -> Result<(), AppError> {
+    let mut conn = redis_pool.get().await?;
+    let key = challenge_failures_key(challenge_id);
+    let _: () = redis::pipe()
+        .atomic()
+        .incr(&key, 1)
+        .ignore()
+        .expire(&key, MFA_CHALLENGE_EXPIRY)
+        .ignore()
+        .query_async(&mut conn)
+        .await?;
+    Ok(())
+}
+
+/// The current code for `secret`, as an authenticator app would show it
+#[cfg(test)]
+pub fn current_code(secret: &str) -> String {
+    totp(secret, "").unwrap().generate_current().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_totp_codes() {
+        let secret = generate_secret().unwrap();
+        assert_eq!(
+            Secret::try_from_base32(&secret).unwrap().len(),
+            SECRET_BYTES
+        );
+
+        let code = current_code(&secret);
+        assert_eq!(code.len(), 6);
+        assert!(totp_step(&secret, &code).unwrap().is_some());
+        assert!(totp_step(&secret, "not a code").unwrap().is_none());
+
+        let uri = provisioning_uri(&secret, "user@example.com").unwrap();
+        assert!(uri.starts_with("otpauth://totp/Vexillum:user%40example.com?"));
+        assert!(uri.contains(&format!("secret={}", secret)));
+        assert!(uri.contains("issuer=Vexillum"));
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let code = generate_recovery_code().unwrap();
+        assert_eq!(code.len(), 11);
+        assert_eq!(code.as_bytes()[5], b'-');
+        assert!(
+            code.replace('-', "")
+                .bytes()
+                .all(|b| RECOVERY_ALPHABET.contains(&b))
+        );
+        assert_eq!(
+            normalize_recovery_code(&code.to_uppercase().replace('-', " ")),
+            code.replace('-', "")
+        );
+        assert_ne!(code, generate_recovery_code().unwrap());
+    }
+}
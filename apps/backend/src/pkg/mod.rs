@@ -1,7 +1,22 @@
+pub mod audit;
 pub mod auth;
+pub mod cache;
 pub mod config;
+pub mod emails;
 pub mod error;
+pub mod evaluation;
+pub mod events;
 pub mod jwt;
 pub mod keys;
+pub mod lockout;
+pub mod mailer;
+pub mod mfa;
+pub mod oidc;
+pub mod permissions;
+pub mod rate_limit;
+pub mod registration;
 pub mod response;
+pub mod rollout;
+pub mod sessions;
 pub mod state;
+pub mod variants;
@@ -0,0 +1,253 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header};
+use reqwest::Url;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::OnceCell;
+
+use crate::pkg::config::Config;
+use crate::pkg::error::AppError;
+
+/// Signature algorithms accepted on ID tokens, symmetric ones would need the client secret as key
+const ID_TOKEN_ALGORITHMS: &[Algorithm] = &[
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+/// The parts of the provider's discovery document we use
+#[derive(Debug, Clone, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// Verified claims of an ID token
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    nonce: Option<String>,
+}
+
+/// An OIDC identity provider users sign in with, using the authorization code flow with PKCE
+pub struct OidcProvider {
+    issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_url: String,
+    scopes: String,
+    http: reqwest::Client,
+    /// Discovered on first use, so the API starts while the provider is down
+    metadata: OnceCell<ProviderMetadata>,
+}
+
+/// PKCE S256 challenge of a code verifier
+pub fn pkce_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+fn provider_error(e: impl std::fmt::Display) -> AppError {
+    AppError::InternalError(format!("Identity provider request failed: {}", e))
+}
+
+impl OidcProvider {
+    /// The configured provider, none when single sign-on is off
+    pub fn from_config(config: &Config) -> Result<Option<Self>, AppError> {
+        let Some(issuer) = &config.oidc_issuer else {
+            return Ok(None);
+        };
+        let client_id = config
+            .oidc_client_id
+            .clone()
+            .ok_or(AppError::InternalError(
+                "OIDC_CLIENT_ID is required with OIDC_ISSUER".to_string(),
+            ))?;
+
+        Ok(Some(Self {
+            issuer: issuer.trim_end_matches('/').to_string(),
+            client_id,
+            client_secret: config.oidc_client_secret.clone(),
+            redirect_url: config.oidc_redirect_url.clone(),
+            scopes: config.oidc_scopes.clone(),
+            http: reqwest::Client::new(),
+            metadata: OnceCell::new(),
+        }))
+    }
+
+    /// Issuer identities of this provider are recorded with
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    async fn metadata(&self) -> Result<&ProviderMetadata, AppError> {
+        self.metadata
+            .get_or_try_init(|| async {
+                let metadata: ProviderMetadata = self
+                    .http
+                    .get(format!("{}/.well-known/openid-configuration", self.issuer))
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map_err(provider_error)?
+                    .json()
+                    .await
+                    .map_err(provider_error)?;
+
+                // A document claiming another issuer could hand out someone else's identities
+                if metadata.issuer.trim_end_matches('/') != self.issuer {
+                    return Err(AppError::InternalError(format!(
+                        "Identity provider reports issuer {} instead of {}",
+                        metadata.issuer, self.issuer
+                    )));
+                }
+
+                Ok(metadata)
+            })
+            .await
+    }
+
+    /// URL to send the user to for signing in at the provider
+    pub async fn authorization_url(
+        &self,
+        state: &str,
+        nonce: &str,
+        code_verifier: &str,
+    ) -> Result<String, AppError> {
+        let metadata = self.metadata().await?;
+        let url = Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", &self.client_id),
+                ("redirect_uri", &self.redirect_url),
+                ("scope", &self.scopes),
+                ("state", state),
+                ("nonce", nonce),
+                ("code_challenge", &pkce_challenge(code_verifier)),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(provider_error)?;
+
+        Ok(url.to_string())
+    }
+
+    /// Exchange the authorization code of a callback for the user's verified ID token claims
+    pub async fn authenticate(
+        &self,
+        code: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, AppError> {
+        let metadata = self.metadata().await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.redirect_url),
+            ("code_verifier", code_verifier),
+        ];
+        let mut request = self.http.post(&metadata.token_endpoint);
+        match &self.client_secret {
+            Some(secret) => request = request.basic_auth(&self.client_id, Some(secret)),
+            None => form.push(("client_id", &self.client_id)),
+        }
+
+        let response = request.form(&form).send().await.map_err(provider_error)?;
+        if !response.status().is_success() {
+            return Err(AppError::Unauthorized(format!(
+                "Identity provider rejected the authorization code with status {}",
+                response.status()
+            )));
+        }
+        let tokens: TokenResponse = response.json().await.map_err(provider_error)?;
+
+        self.verify_id_token(metadata, &tokens.id_token, nonce)
+            .await
+    }
+
+    async fn verify_id_token(
+        &self,
+        metadata: &ProviderMetadata,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, AppError> {
+        let invalid =
+            |reason: String| AppError::Unauthorized(format!("Invalid ID token: {}", reason));
+
+        let header = decode_header(id_token).map_err(|e| invalid(e.to_string()))?;
+        if !ID_TOKEN_ALGORITHMS.contains(&header.alg) {
+            return Err(invalid(format!(
+                "{:?} signatures are not accepted",
+                header.alg
+            )));
+        }
+
+        // Fetched every time, so keys the provider rotated in are picked up
+        let jwks: JwkSet = self
+            .http
+            .get(&metadata.jwks_uri)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(provider_error)?
+            .json()
+            .await
+            .map_err(provider_error)?;
+        let jwk = match &header.kid {
+            Some(kid) => jwks.find(kid),
+            None if jwks.keys.len() == 1 => jwks.keys.first(),
+            None => None,
+        }
+        .ok_or(invalid("signed with an unknown key".to_string()))?;
+        let key = DecodingKey::from_jwk(jwk).map_err(|e| invalid(e.to_string()))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_audience(&[&self.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        let claims = decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map_err(|e| invalid(e.to_string()))?
+            .claims;
+
+        // Binds the token to the login it was requested for, so it cannot be replayed
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(invalid("nonce mismatch".to_string()));
+        }
+
+        Ok(claims)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pkce_challenge() {
+        // RFC 7636, appendix B
+        assert_eq!(
+            pkce_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+}
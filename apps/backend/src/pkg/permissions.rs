@@ -0,0 +1,184 @@
+use crate::models::enums::{ProjectRole, UserRole};
+
+/// An action a user can be allowed to take
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    /// Read flags, their states, overrides and variants
+    FlagRead,
+    /// Create, change and delete flags, their states, overrides and variants
+    FlagWrite,
+    /// Issue and revoke environment API keys
+    ApiKeyManage,
+    /// Read the audit log
+    AuditRead,
+    /// Manage the members of a project and transfer its ownership
+    ProjectManage,
+    /// See the org the user belongs to
+    OrgRead,
+    /// Rename or delete the org
+    OrgManage,
+    /// Invite users into the org and manage their roles
+    UserManage,
+}
+
+impl Permission {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Permission::FlagRead => "flag.read",
+            Permission::FlagWrite => "flag.write",
+            Permission::ApiKeyManage => "apikey.manage",
+            Permission::AuditRead => "audit.read",
+            Permission::ProjectManage => "project.manage",
+            Permission::OrgRead => "org.read",
+            Permission::OrgManage => "org.manage",
+            Permission::UserManage => "user.manage",
+        }
+    }
+}
+
+/// Whether a role grants a permission.
+/// Viewers can only look at flags, users run them day to day, admins also manage the org and its users.
+pub fn granted(role: UserRole, permission: Permission) -> bool {
+    match role {
+        UserRole::Admin => true,
+        UserRole::User => !matches!(permission, Permission::OrgManage | Permission::UserManage),
+        UserRole::Viewer => matches!(permission, Permission::FlagRead | Permission::OrgRead),
+    }
+}
+
+/// Whether a role within a project grants a permission on that project.
+/// Only owners manage members, editors run flags and keys, viewers can only look.
+/// Org-wide permissions are never granted by a project role.
+pub fn project_granted(role: ProjectRole, permission: Permission) -> bool {
+    match role {
+        ProjectRole::Owner => matches!(
+            permission,
+            Permission::FlagRead
+                | Permission::FlagWrite
+                | Permission::ApiKeyManage
+                | Permission::AuditRead
+                | Permission::ProjectManage
+        ),
+        ProjectRole::Editor => matches!(
+            permission,
+            Permission::FlagRead
+                | Permission::FlagWrite
+                | Permission::ApiKeyManage
+                | Permission::AuditRead
+        ),
+        ProjectRole::Viewer => matches!(permission, Permission::FlagRead),
+    }
+}
+
+/// A permission known at compile time, so handlers can require it in their signature
+/// with [`crate::pkg::auth::Authorized`]
+pub trait RequiredPermission {
+    const PERMISSION: Permission;
+}
+
+/// Marker types naming each [`Permission`], e.g. `Authorized<require::FlagWrite>`
+pub mod require {
+    use super::{Permission, RequiredPermission};
+
+    macro_rules! required_permission {
+        ($($(#[$meta:meta])* $name:ident),* $(,)?) => {
+            $(
+                $(#[$meta])*
+                pub struct $name;
+
+                impl RequiredPermission for $name {
+                    const PERMISSION: Permission = Permission::$name;
+                }
+            )*
+        };
+    }
+
+    required_permission!(
+        FlagRead,
+        FlagWrite,
+        ApiKeyManage,
+        AuditRead,
+        ProjectManage,
+        OrgRead,
+        OrgManage,
+        UserManage,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Permission; 8] = [
+        Permission::FlagRead,
+        Permission::FlagWrite,
+        Permission::ApiKeyManage,
+        Permission::AuditRead,
+        Permission::ProjectManage,
+        Permission::OrgRead,
+        Permission::OrgManage,
+        Permission::UserManage,
+    ];
+
+    fn permissions(role: UserRole) -> Vec<&'static str> {
+        ALL.iter()
+            .filter(|permission| granted(role, **permission))
+            .map(|permission| permission.as_str())
+            .collect()
+    }
+
+    fn project_permissions(role: ProjectRole) -> Vec<&'static str> {
+        ALL.iter()
+            .filter(|permission| project_granted(role, **permission))
+            .map(|permission| permission.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_role_permissions() {
+        assert_eq!(
+            permissions(UserRole::Admin),
+            vec![
+                "flag.read",
+                "flag.write",
+                "apikey.manage",
+                "audit.read",
+                "project.manage",
+                "org.read",
+                "org.manage",
+                "user.manage"
+            ]
+        );
+        assert_eq!(
+            permissions(UserRole::User),
+            vec![
+                "flag.read",
+                "flag.write",
+                "apikey.manage",
+                "audit.read",
+                "project.manage",
+                "org.read"
+            ]
+        );
+        assert_eq!(permissions(UserRole::Viewer), vec!["flag.read", "org.read"]);
+    }
+
+    #[test]
+    fn test_project_role_permissions() {
+        assert_eq!(
+            project_permissions(ProjectRole::Owner),
+            vec![
+                "flag.read",
+                "flag.write",
+                "apikey.manage",
+                "audit.read",
+                "project.manage"
+            ]
+        );
+        assert_eq!(
+            project_permissions(ProjectRole::Editor),
+            vec!["flag.read", "flag.write", "apikey.manage", "audit.read"]
+        );
+        assert_eq!(project_permissions(ProjectRole::Viewer), vec!["flag.read"]);
+    }
+}
@@ -0,0 +1,191 @@
+use crate::pkg::auth::hash_token;
+use crate::pkg::error::AppError;
+use crate::pkg::state::AppState;
+use axum::body::{Body, Bytes};
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::middleware::Next;
+use axum::response::Response;
+use deadpool_redis::redis;
+use std::net::SocketAddr;
+
+/// Largest body read to find the account a request is about
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// A counter and how many hits it allows per window
+struct Limit {
+    key: String,
+    max: u64,
+}
+
+/// Redis key of the counter for `key` in the window `now` falls into
+fn bucket_key(key: &str, now: u64, window: u64) -> String {
+    format!("vexillum:ratelimit:{}:{}", key, now / window)
+}
+
+/// Seconds until the window `now` falls into ends
+fn retry_after(now: u64, window: u64) -> u64 {
+    window - now % window
+}
+
+/// Count a hit on every limit in the current fixed window,
+/// returning the seconds to wait when one of them is exceeded
+async fn hit(
+    redis_pool: &deadpool_redis::Pool,
+    limits: &[Limit],
+    window: u64,
+) -> Result<Option<u64>, AppError> {
+    let limits: Vec<&Limit> = limits.iter().filter(|limit| limit.max > 0).collect();
+    if limits.is_empty() {
+        return Ok(None);
+    }
+
+    let now = chrono::Utc::now().timestamp() as u64;
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    for limit in &limits {
+        let key = bucket_key(&limit.key, now, window);
+        pipe.incr(&key, 1).expire(&key, window as i64).ignore();
+    }
+
+    let mut conn = redis_pool.get().await?;
+    let counts: Vec<u64> = pipe.query_async(&mut conn).await?;
+
+    let exceeded = limits
+        .iter()
+        .zip(counts)
+        .any(|(limit, count)| count > limit.max);
+
+    Ok(exceeded.then(|| retry_after(now, window)))
+}
+
+/// Address the request came from, taken from `X-Forwarded-For` only when the proxy is trusted.
+/// The proxy appends the address it saw to the right, everything before it is client supplied.
+fn client_ip(request: &Request, trust_proxy: bool) -> String {
+    let forwarded = trust_proxy
+        .then(|| request.headers().get("x-forwarded-for"))
+        .flatten()
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.rsplit(',').next())
+        .map(|ip| ip.trim().to_string())
+        .filter(|ip| !ip.is_empty());
+
+    forwarded
+        .or_else(|| {
+            request
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip().to_string())
+        })
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// The email a JSON body names, the account the request is about
+fn account(body: &Bytes) -> Option<String> {
+    serde_json::from_slice::<serde_json::Value>(body)
+        .ok()?
+        .get("email")?
+        .as_str()
+        .map(|email| email.trim().to_lowercase())
+}
+
+/// Limit requests per client IP and per account named in the body, answering 429 with
+/// `Retry-After` when either is exceeded. Requests pass when Redis is unavailable,
+/// failed passwords still lock accounts then.
+pub async fn limit(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let config = &state.config;
+    let mut limits = vec![Limit {
+        key: format!("ip:{}", client_ip(&request, config.trust_proxy)),
+        max: config.rate_limit_ip,
+    }];
+
+    // The body is read to find the account and handed on untouched
+    let is_json = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    let request = if is_json {
+        let (parts, body) = request.into_parts();
+        let bytes = axum::body::to_bytes(body, MAX_BODY_BYTES)
+            .await
+            .map_err(|_| AppError::BadRequest("Request body is too large".to_string()))?;
+        if let Some(email) = account(&bytes) {
+            limits.push(Limit {
+                key: format!("account:{}", hash_token(&email)),
+                max: config.rate_limit_account,
+            });
+        }
+        Request::from_parts(parts, Body::from(bytes))
+    } else {
+        request
+    };
+
+    match hit(&state.redis_pool, &limits, config.rate_limit_window.max(1)).await {
+        Ok(Some(seconds)) => {
+            return Err(AppError::TooManyRequests(
+                "Too many requests, try again later".to_string(),
+                seconds,
+            ));
+        }
+        Ok(None) => {}
+        Err(e) => eprintln!("Failed to check rate limits: {}", e),
+    }
+
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_windows() {
+        assert_eq!(
+            bucket_key("ip:1.2.3.4", 119, 60),
+            "vexillum:ratelimit:ip:1.2.3.4:1"
+        );
+        assert_eq!(
+            bucket_key("ip:1.2.3.4", 120, 60),
+            "vexillum:ratelimit:ip:1.2.3.4:2"
+        );
+        assert_eq!(retry_after(120, 60), 60);
+        assert_eq!(retry_after(179, 60), 1);
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let request = || {
+            let mut request = Request::builder()
+                .header("x-forwarded-for", "203.0.113.7, 10.0.0.1")
+                .body(Body::empty())
+                .unwrap();
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([172, 16, 0, 1], 4000))));
+            request
+        };
+
+        // Only the entry the proxy appended counts, clients can send any others
+        assert_eq!(client_ip(&request(), true), "10.0.0.1");
+        // Anyone can send the header, it only counts behind a proxy
+        assert_eq!(client_ip(&request(), false), "172.16.0.1");
+        assert_eq!(client_ip(&Request::new(Body::empty()), false), "unknown");
+    }
+
+    #[test]
+    fn test_account() {
+        assert_eq!(
+            account(&Bytes::from(
+                r#"{"email": " User@Example.com ", "password": "x"}"#
+            )),
+            Some("user@example.com".to_string())
+        );
+        assert_eq!(account(&Bytes::from(r#"{"token": "abc"}"#)), None);
+        assert_eq!(account(&Bytes::from("not json")), None);
+    }
+}
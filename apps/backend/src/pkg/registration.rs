@@ -0,0 +1,281 @@
+use crate::models::db::Users;
+use crate::models::enums::RegistrationMode;
+use crate::pkg::auth::{generate_token, hash_token};
+use crate::pkg::config::Config;
+use crate::pkg::error::AppError;
+use uuid::Uuid;
+
+/// How long an email verification link works
+const EMAIL_VERIFICATION_TTL_HOURS: i64 = 48;
+
+/// Longest password accepted, hashing is slow on purpose
+const PASSWORD_MAX_LENGTH: usize = 128;
+
+/// Reject strings that cannot be an email address we can send to
+pub fn validate_email(email: &str) -> Result<(), AppError> {
+    let invalid = || AppError::UnprocessableEntity("Invalid email address".to_string());
+
+    if email.len() > 254 || email.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(invalid());
+    }
+    let (local, domain) = email.rsplit_once('@').ok_or_else(invalid)?;
+    let labels: Vec<&str> = domain.split('.').collect();
+    if local.is_empty()
+        || local.contains('@')
+        || labels.len() < 2
+        || labels.iter().any(|label| label.is_empty())
+    {
+        return Err(invalid());
+    }
+
+    Ok(())
+}
+
+/// Check a new password against the configured policy
+pub fn check_password(config: &Config, password: &str, email: &str) -> Result<(), AppError> {
+    let length = password.chars().count();
+    if length < config.password_min_length {
+        return Err(AppError::UnprocessableEntity(format!(
+            "Password must be at least {} characters",
+            config.password_min_length
+        )));
+    }
+    if length > PASSWORD_MAX_LENGTH {
+        return Err(AppError::UnprocessableEntity(format!(
+            "Password must be at most {} characters",
+            PASSWORD_MAX_LENGTH
+        )));
+    }
+
+    let classes = [
+        password.chars().any(|c| c.is_lowercase()),
+        password.chars().any(|c| c.is_uppercase()),
+        password.chars().any(|c| c.is_numeric()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ];
+    if classes.iter().filter(|&&class| class).count() < config.password_min_character_classes {
+        return Err(AppError::UnprocessableEntity(format!(
+            "Password must mix at least {} of lowercase letters, uppercase letters, digits and other characters",
+            config.password_min_character_classes
+        )));
+    }
+
+    let local = email.split('@').next().unwrap_or_default().to_lowercase();
+    if local.len() >= 3 && password.to_lowercase().contains(&local) {
+        return Err(AppError::UnprocessableEntity(
+            "Password must not contain the email address".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Domain of an email address, lowercased
+pub fn email_domain(email: &str) -> String {
+    email
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.to_lowercase())
+        .unwrap_or_default()
+}
+
+/// Whether an org lets `email` join by signing up, and if the address then needs verifying
+/// to prove it belongs to an allowed domain
+pub fn may_join(
+    mode: &RegistrationMode,
+    allowed_domains: &serde_json::Value,
+    email: &str,
+) -> Option<bool> {
+    match mode {
+        RegistrationMode::Open => Some(false),
+        RegistrationMode::InviteOnly => None,
+        RegistrationMode::AllowedDomains => {
+            let domain = email_domain(email);
+            allowed_domains
+                .as_array()?
+                .iter()
+                .filter_map(|allowed| allowed.as_str())
+                .any(|allowed| {
+                    allowed
+                        .trim_start_matches('@')
+                        .eq_ignore_ascii_case(&domain)
+                })
+                .then_some(true)
+        }
+    }
+}
+
+/// Whether signing up without an org to join may start a new one. Off unless
+/// `ALLOW_ORG_CREATION` is set, except for the first account of an empty install.
+pub async fn may_create_org(
+    client: &impl deadpool_postgres::GenericClient,
+    config: &Config,
+) -> Result<bool, AppError> {
+    if config.allow_org_creation {
+        return Ok(true);
+    }
+
+    let row = client
+        .query_one("SELECT EXISTS (SELECT 1 FROM users)", &[])
+        .await?;
+    Ok(!row.get::<_, bool>(0))
+}
+
+/// Normalize the domains an org allows, rejecting anything that is not a domain
+pub fn normalize_domains(domains: &[String]) -> Result<serde_json::Value, AppError> {
+    let mut normalized = Vec::new();
+    for domain in domains {
+        let domain = domain.trim().trim_start_matches('@').to_lowercase();
+        validate_email(&format!("user@{}", domain)).map_err(|_| {
+            AppError::UnprocessableEntity(format!("Invalid email domain: {}", domain))
+        })?;
+        if !normalized.contains(&domain) {
+            normalized.push(domain);
+        }
+    }
+
+    Ok(serde_json::json!(normalized))
+}
+
+/// Create a verification token for the email of a user, replacing any earlier one.
+/// Send it with `Template::EmailVerification` once the token is committed.
+pub async fn create_verification(
+    client: &impl deadpool_postgres::GenericClient,
+    user_id: Uuid,
+) -> Result<String, AppError> {
+    let token = generate_token()?;
+    let expires_at = chrono::Utc::now() + chrono::Duration::hours(EMAIL_VERIFICATION_TTL_HOURS);
+
+    client
+        .execute(
+            "DELETE FROM email_verifications WHERE user_id = $1 AND used_at IS NULL",
+            &[&user_id],
+        )
+        .await?;
+    client
+        .execute(
+            "INSERT INTO email_verifications (user_id, token_hash, expires_at) VALUES ($1, $2, $3)",
+            &[&user_id, &hash_token(&token), &expires_at],
+        )
+        .await?;
+
+    Ok(token)
+}
+
+/// Mark the email of a user verified, once they proved they receive mail sent to it
+pub async fn mark_verified(
+    client: &impl deadpool_postgres::GenericClient,
+    user_id: Uuid,
+) -> Result<(), AppError> {
+    client
+        .execute(
+            "UPDATE users SET email_verified_at = current_timestamp
+             WHERE id = $1 AND email_verified_at IS NULL",
+            &[&user_id],
+        )
+        .await?;
+    client
+        .execute(
+            "DELETE FROM email_verifications WHERE user_id = $1 AND used_at IS NULL",
+            &[&user_id],
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Whether a user still has to verify their email before signing in with a password.
+/// Members of orgs joined by email domain always do, the domain is only proven by the link.
+pub async fn verification_pending(
+    client: &impl deadpool_postgres::GenericClient,
+    config: &Config,
+    user: &Users,
+) -> Result<bool, AppError> {
+    if user.email_verified_at.is_some() {
+        return Ok(false);
+    }
+    if config.require_email_verification {
+        return Ok(true);
+    }
+
+    let mode: RegistrationMode = client
+        .query_one(
+            "SELECT registration_mode FROM orgs WHERE id = $1",
+            &[&user.org_id],
+        )
+        .await?
+        .get(0);
+
+    Ok(matches!(mode, RegistrationMode::AllowedDomains))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_validate_email() {
+        assert!(validate_email("user@example.com").is_ok());
+        assert!(validate_email("first.last+tag@mail.example.co.uk").is_ok());
+
+        for email in [
+            "",
+            "user",
+            "user@",
+            "@example.com",
+            "user@localhost",
+            "user@example..com",
+            "us er@example.com",
+            "user@@example.com",
+        ] {
+            assert!(validate_email(email).is_err(), "{}", email);
+        }
+    }
+
+    #[test]
+    fn test_check_password() {
+        let mut config = Config::parse_from(["backend"]);
+        config.password_min_length = 10;
+        config.password_min_character_classes = 2;
+        let email = "alice@example.com";
+
+        assert!(check_password(&config, "correct horse battery staple", email).is_ok());
+        assert!(check_password(&config, "Tr0ub4dor&3x", email).is_ok());
+        assert!(check_password(&config, "short1", email).is_err());
+        assert!(check_password(&config, "onlylowercaseletters", email).is_err());
+        assert!(check_password(&config, "1234567890123", email).is_err());
+        assert!(check_password(&config, "Alice-is-my-password", email).is_err());
+        assert!(check_password(&config, &"a1".repeat(100), email).is_err());
+    }
+
+    #[test]
+    fn test_may_join() {
+        let domains = serde_json::json!(["example.com"]);
+
+        assert_eq!(
+            may_join(&RegistrationMode::Open, &domains, "a@other.org"),
+            Some(false)
+        );
+        assert_eq!(
+            may_join(&RegistrationMode::InviteOnly, &domains, "a@example.com"),
+            None
+        );
+        assert_eq!(
+            may_join(&RegistrationMode::AllowedDomains, &domains, "a@Example.COM"),
+            Some(true)
+        );
+        assert_eq!(
+            may_join(
+                &RegistrationMode::AllowedDomains,
+                &domains,
+                "a@sub.example.com"
+            ),
+            None
+        );
+        assert_eq!(
+            normalize_domains(&["@Example.com".to_string(), "example.com".to_string()]).unwrap(),
+            serde_json::json!(["example.com"])
+        );
+        assert!(normalize_domains(&["not a domain".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,294 @@
+use crate::pkg::error::AppError;
+use crate::pkg::evaluation::EvaluationContext;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+/// Number of buckets a subject can fall in, gives rollouts a 0.001% resolution
+pub const BUCKET_COUNT: u32 = 100_000;
+
+/// Gradual rollout of a flag state or override, stored as JSON in its `rollout` column.
+/// Exactly one of `percentage` or `variants` is set.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Rollout {
+    /// Context attribute subjects are bucketed by, usually `user_id`
+    pub bucket_by: String,
+    /// Changing the salt reshuffles which subjects land in which bucket
+    #[serde(default)]
+    pub salt: String,
+    /// Share of subjects, from 0 to 100, that get the flag
+    pub percentage: Option<f64>,
+    /// Weighted values to split subjects between, multivariate flags only
+    pub variants: Option<Vec<RolloutVariant>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct RolloutVariant {
+    pub value: Value,
+    pub weight: u32,
+}
+
+/// Outcome of placing a subject in a rollout
+#[derive(Debug, PartialEq)]
+pub enum Allocation<'a> {
+    Included,
+    Excluded,
+    Variant(&'a RolloutVariant),
+}
+
+/// Deterministically place a value in one of `BUCKET_COUNT` buckets.
+/// Only depends on its inputs, so a subject keeps its bucket across requests and instances.
+pub fn bucket(flag_key: &str, salt: &str, value: &str) -> u32 {
+    let digest = Sha256::digest(format!("{}.{}.{}", flag_key, salt, value).as_bytes());
+    let mut prefix = [0u8; 8];
+    prefix.copy_from_slice(&digest[..8]);
+    (u64::from_be_bytes(prefix) % BUCKET_COUNT as u64) as u32
+}
+
+impl Rollout {
+    /// Read a rollout from its stored JSON, ignoring values that do not parse
+    pub fn from_column(value: Option<&Value>) -> Option<Rollout> {
+        value.and_then(|v| serde_json::from_value(v.clone()).ok())
+    }
+
+    /// Check the rollout is well formed before it is stored
+    pub fn validate(&self, multivariate: bool) -> Result<(), AppError> {
+        if self.bucket_by.is_empty() {
+            return Err(AppError::UnprocessableEntity(
+                "rollout.bucket_by must not be empty".to_string(),
+            ));
+        }
+
+        match (self.percentage, &self.variants) {
+            (Some(percentage), None) => {
+                if !(0.0..=100.0).contains(&percentage) {
+                    return Err(AppError::UnprocessableEntity(
+                        "rollout.percentage must be between 0 and 100".to_string(),
+                    ));
+                }
+            }
+            (None, Some(variants)) => {
+                if !multivariate {
+                    return Err(AppError::UnprocessableEntity(
+                        "rollout.variants is only supported on multivariate flags".to_string(),
+                    ));
+                }
+                if variants.iter().map(|v| v.weight as u64).sum::<u64>() == 0 {
+                    return Err(AppError::UnprocessableEntity(
+                        "rollout.variants must have a total weight above 0".to_string(),
+                    ));
+                }
+            }
+            _ => {
+                return Err(AppError::UnprocessableEntity(
+                    "rollout must set exactly one of percentage or variants".to_string(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Place the subject described by `context` in the rollout.
+    /// Returns `None` when the context has no usable `bucket_by` attribute.
+    pub fn allocate(
+        &self,
+        flag_key: &str,
+        context: &EvaluationContext,
+    ) -> Option<(u32, Allocation<'_>)> {
+        let value = match context.get(&self.bucket_by)? {
+            Value::String(s) => s.clone(),
+            Value::Number(n) => n.to_string(),
+            _ => return None,
+        };
+        let bucket = bucket(flag_key, &self.salt, &value);
+
+        if let Some(variants) = &self.variants {
+            let total: u64 = variants.iter().map(|v| v.weight as u64).sum();
+            if total == 0 {
+                return Some((bucket, Allocation::Excluded));
+            }
+
+            // Scale the bucket onto the total weight and walk the cumulative weights
+            let point = bucket as u64 * total / BUCKET_COUNT as u64;
+            let mut cumulative = 0;
+            for variant in variants {
+                cumulative += variant.weight as u64;
+                if point < cumulative {
+                    return Some((bucket, Allocation::Variant(variant)));
+                }
+            }
+            return Some((bucket, Allocation::Excluded));
+        }
+
+        let threshold = (self.percentage.unwrap_or(0.0) * (BUCKET_COUNT as f64 / 100.0)) as u32;
+        if bucket < threshold {
+            Some((bucket, Allocation::Included))
+        } else {
+            Some((bucket, Allocation::Excluded))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const SAMPLE_SIZE: usize = 100_000;
+    /// Allowed deviation from the expected share, well above 4 standard deviations at this sample size
+    const TOLERANCE: f64 = 0.01;
+
+    fn context(user_id: usize) -> EvaluationContext {
+        EvaluationContext::from([("user_id".to_string(), json!(format!("user-{}", user_id)))])
+    }
+
+    fn percentage(percentage: f64) -> Rollout {
+        Rollout {
+            bucket_by: "user_id".to_string(),
+            salt: "salt".to_string(),
+            percentage: Some(percentage),
+            variants: None,
+        }
+    }
+
+    #[test]
+    fn test_bucket_is_deterministic() {
+        assert_eq!(
+            bucket("checkout", "s", "user-1"),
+            bucket("checkout", "s", "user-1")
+        );
+        assert!(bucket("checkout", "s", "user-1") < BUCKET_COUNT);
+        assert_ne!(
+            bucket("checkout", "s", "user-1"),
+            bucket("checkout", "other", "user-1")
+        );
+    }
+
+    #[test]
+    fn test_percentage_distribution() {
+        for expected in [1.0, 25.0, 50.0, 90.0] {
+            let rollout = percentage(expected);
+            let included = (0..SAMPLE_SIZE)
+                .filter(|i| {
+                    matches!(
+                        rollout.allocate("checkout", &context(*i)),
+                        Some((_, Allocation::Included))
+                    )
+                })
+                .count();
+
+            let share = included as f64 / SAMPLE_SIZE as f64;
+            assert!(
+                (share - expected / 100.0).abs() < TOLERANCE,
+                "{}% rollout included {:.4}",
+                expected,
+                share
+            );
+        }
+    }
+
+    #[test]
+    fn test_percentage_edges() {
+        let none = percentage(0.0);
+        let all = percentage(100.0);
+        for i in 0..1000 {
+            assert_eq!(
+                none.allocate("checkout", &context(i)).unwrap().1,
+                Allocation::Excluded
+            );
+            assert_eq!(
+                all.allocate("checkout", &context(i)).unwrap().1,
+                Allocation::Included
+            );
+        }
+    }
+
+    #[test]
+    fn test_rollouts_grow_monotonically() {
+        // Raising the percentage must only add subjects, never swap them out
+        let small = percentage(10.0);
+        let large = percentage(30.0);
+        for i in 0..10_000 {
+            if small.allocate("checkout", &context(i)).unwrap().1 == Allocation::Included {
+                assert_eq!(
+                    large.allocate("checkout", &context(i)).unwrap().1,
+                    Allocation::Included
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_variant_distribution() {
+        let rollout = Rollout {
+            bucket_by: "user_id".to_string(),
+            salt: String::new(),
+            percentage: None,
+            variants: Some(vec![
+                RolloutVariant {
+                    value: json!("a"),
+                    weight: 50,
+                },
+                RolloutVariant {
+                    value: json!("b"),
+                    weight: 30,
+                },
+                RolloutVariant {
+                    value: json!("c"),
+                    weight: 20,
+                },
+            ]),
+        };
+
+        let mut counts = [0usize; 3];
+        for i in 0..SAMPLE_SIZE {
+            match rollout.allocate("pricing", &context(i)) {
+                Some((_, Allocation::Variant(v))) if v.value == json!("a") => counts[0] += 1,
+                Some((_, Allocation::Variant(v))) if v.value == json!("b") => counts[1] += 1,
+                Some((_, Allocation::Variant(v))) if v.value == json!("c") => counts[2] += 1,
+                other => panic!("unexpected allocation {:?}", other),
+            }
+        }
+
+        for (count, expected) in counts.iter().zip([0.5, 0.3, 0.2]) {
+            let share = *count as f64 / SAMPLE_SIZE as f64;
+            assert!(
+                (share - expected).abs() < TOLERANCE,
+                "expected {} got {:.4}",
+                expected,
+                share
+            );
+        }
+    }
+
+    #[test]
+    fn test_missing_bucket_attribute() {
+        let rollout = percentage(100.0);
+        assert!(
+            rollout
+                .allocate("checkout", &EvaluationContext::new())
+                .is_none()
+        );
+        let context = EvaluationContext::from([("user_id".to_string(), json!(["list"]))]);
+        assert!(rollout.allocate("checkout", &context).is_none());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(percentage(50.0).validate(false).is_ok());
+        assert!(percentage(150.0).validate(false).is_err());
+
+        let mut variants = percentage(50.0);
+        variants.variants = Some(vec![RolloutVariant {
+            value: json!(1),
+            weight: 1,
+        }]);
+        assert!(variants.validate(true).is_err());
+
+        variants.percentage = None;
+        assert!(variants.validate(true).is_ok());
+        assert!(variants.validate(false).is_err());
+    }
+}
@@ -0,0 +1,226 @@
+use crate::models::db::Sessions;
+use crate::models::enums::UserRole;
+use crate::pkg::error::AppError;
+use crate::pkg::jwt::{API_AUDIENCE, JwtService, TokenType};
+use pgmap::FromRow;
+use uuid::Uuid;
+
+/// Revoked by logging out
+pub const REVOKED_LOGOUT: &str = "logout";
+
+/// Revoked by the user from their list of sessions
+pub const REVOKED_BY_USER: &str = "revoked";
+
+/// Revoked because a refresh token that was already rotated was presented again
+pub const REVOKED_REUSE: &str = "reuse_detected";
+
+/// Revoked because the user's password was reset
+pub const REVOKED_PASSWORD_RESET: &str = "password_reset";
+
+/// Revoked by an admin deactivating the user or requiring a new password
+pub const REVOKED_BY_ADMIN: &str = "revoked_by_admin";
+
+/// Tokens handed out for a session
+pub struct SessionTokens {
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+async fn issue(
+    client: &impl deadpool_postgres::GenericClient,
+    jwt: &JwtService,
+    session: &Sessions,
+    refresh_type: TokenType,
+) -> Result<SessionTokens, AppError> {
+    let user = client
+        .query_opt(
+            "SELECT org_id, role, deactivated_at IS NOT NULL FROM users WHERE id = $1",
+            &[&session.user_id],
+        )
+        .await?
+        .ok_or(AppError::Unauthorized("User no longer exists".to_string()))?;
+    let org_id: Uuid = user.get(0);
+    let role: UserRole = user.get(1);
+
+    // Every way of signing in and refreshing ends here
+    if user.get::<_, bool>(2) {
+        return Err(AppError::Forbidden(
+            "This account has been deactivated".to_string(),
+        ));
+    }
+
+    // Rotated refresh tokens never outlive the session they belong to
+    let remaining = (session.expires_at - chrono::Utc::now()).num_seconds();
+
+    Ok(SessionTokens {
+        access_token: jwt.encode(
+            &jwt.claims(session.user_id, TokenType::Access, API_AUDIENCE)
+                .session(session.id)
+                .org(org_id, role),
+        )?,
+        refresh_token: jwt.encode(
+            &jwt.claims(session.user_id, refresh_type, API_AUDIENCE)
+                .session(session.id)
+                .jti(session.refresh_jti)
+                .expires_in(remaining),
+        )?,
+    })
+}
+
+fn parse_session(row: &tokio_postgres::Row) -> Result<Sessions, AppError> {
+    Sessions::from_row(row)
+        .map_err(|_| AppError::InternalError("Failed to parse session data".to_string()))
+}
+
+/// Start a session lasting as long as a refresh token of `refresh_type` and issue its first tokens
+pub async fn create(
+    client: &impl deadpool_postgres::GenericClient,
+    jwt: &JwtService,
+    user_id: Uuid,
+    refresh_type: TokenType,
+    user_agent: Option<&str>,
+) -> Result<SessionTokens, AppError> {
+    let refresh_jti = Uuid::new_v4();
+    let expires_at = chrono::Utc::now() + chrono::Duration::seconds(jwt.get_length(&refresh_type));
+
+    let row = client
+        .query_one(
+            "INSERT INTO sessions (user_id, refresh_jti, user_agent, expires_at)
+             VALUES ($1, $2, $3, $4)
+             RETURNING *",
+            &[&user_id, &refresh_jti, &user_agent, &expires_at],
+        )
+        .await?;
+
+    issue(client, jwt, &parse_session(&row)?, refresh_type).await
+}
+
+/// Exchange a refresh token for a new pair, invalidating the one presented.
+/// Presenting a token that was already rotated means it leaked, so the whole session is revoked.
+pub async fn rotate(
+    client: &impl deadpool_postgres::GenericClient,
+    jwt: &JwtService,
+    refresh_token: &str,
+) -> Result<SessionTokens, AppError> {
+    let claims = jwt.validate_token(refresh_token, TokenType::Refresh, API_AUDIENCE)?;
+
+    let invalid = || AppError::Unauthorized("Invalid refresh token".to_string());
+    let session_id = claims
+        .sid
+        .as_deref()
+        .and_then(|sid| Uuid::parse_str(sid).ok())
+        .ok_or_else(invalid)?;
+    let jti = Uuid::parse_str(&claims.jti).map_err(|_| invalid())?;
+
+    let refresh_jti = Uuid::new_v4();
+    let row = client
+        .query_opt(
+            "UPDATE sessions SET refresh_jti = $3, last_used_at = current_timestamp
+             WHERE id = $1 AND refresh_jti = $2
+               AND revoked_at IS NULL AND expires_at > current_timestamp
+             RETURNING *",
+            &[&session_id, &jti, &refresh_jti],
+        )
+        .await?;
+
+    if let Some(row) = row {
+        return issue(client, jwt, &parse_session(&row)?, claims.typ).await;
+    }
+
+    let reused = client
+        .execute(
+            "UPDATE sessions SET revoked_at = current_timestamp, revoked_reason = $3
+             WHERE id = $1 AND refresh_jti <> $2 AND revoked_at IS NULL",
+            &[&session_id, &jti, &REVOKED_REUSE],
+        )
+        .await?;
+    if reused > 0 {
+        eprintln!(
+            "Refresh token reuse detected, revoked session {}",
+            session_id
+        );
+    }
+
+    Err(AppError::Unauthorized(
+        "Session expired or revoked".to_string(),
+    ))
+}
+
+/// Revoke the session a refresh token belongs to.
+/// Invalid tokens and tokens that predate sessions have nothing to revoke and are ignored.
+pub async fn revoke_by_token(
+    client: &impl deadpool_postgres::GenericClient,
+    jwt: &JwtService,
+    refresh_token: &str,
+) -> Result<(), AppError> {
+    let Some(session_id) = jwt
+        .validate_token(refresh_token, TokenType::Refresh, API_AUDIENCE)
+        .ok()
+        .and_then(|claims| claims.sid)
+        .and_then(|sid| Uuid::parse_str(&sid).ok())
+    else {
+        return Ok(());
+    };
+
+    client
+        .execute(
+            "UPDATE sessions SET revoked_at = current_timestamp, revoked_reason = $2
+             WHERE id = $1 AND revoked_at IS NULL",
+            &[&session_id, &REVOKED_LOGOUT],
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Revoke one of a user's active sessions
+pub async fn revoke(
+    client: &impl deadpool_postgres::GenericClient,
+    user_id: Uuid,
+    session_id: Uuid,
+) -> Result<Sessions, AppError> {
+    let row = client
+        .query_opt(
+            "UPDATE sessions SET revoked_at = current_timestamp, revoked_reason = $3
+             WHERE id = $1 AND user_id = $2
+               AND revoked_at IS NULL AND expires_at > current_timestamp
+             RETURNING *",
+            &[&session_id, &user_id, &REVOKED_BY_USER],
+        )
+        .await?
+        .ok_or(AppError::NotFound("Session not found".to_string()))?;
+
+    parse_session(&row)
+}
+
+/// Revoke every session of a user, returning how many were still active
+pub async fn revoke_all(
+    client: &impl deadpool_postgres::GenericClient,
+    user_id: Uuid,
+    reason: &str,
+) -> Result<u64, AppError> {
+    Ok(client
+        .execute(
+            "UPDATE sessions SET revoked_at = current_timestamp, revoked_reason = $2
+             WHERE user_id = $1 AND revoked_at IS NULL",
+            &[&user_id, &reason],
+        )
+        .await?)
+}
+
+/// A user's sessions that can still be refreshed, most recently used first
+pub async fn list_active(
+    client: &impl deadpool_postgres::GenericClient,
+    user_id: Uuid,
+) -> Result<Vec<Sessions>, AppError> {
+    let rows = client
+        .query(
+            "SELECT * FROM sessions
+             WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > current_timestamp
+             ORDER BY last_used_at DESC",
+            &[&user_id],
+        )
+        .await?;
+
+    rows.iter().map(parse_session).collect()
+}
@@ -1,5 +1,7 @@
 use super::config::Config;
 use super::jwt::JwtService;
+use super::mailer::Mailer;
+use super::oidc::OidcProvider;
 use crate::models::enums::UserRole;
 use argon2::password_hash::{PasswordHasher, SaltString};
 use deadpool_postgres::{self, ManagerConfig, RecyclingMethod};
@@ -14,6 +16,9 @@ pub struct BaseState {
     pub config: Arc<Config>,
     pub argon2: argon2::Argon2<'static>,
     pub jwt: Arc<JwtService>,
+    pub mailer: Mailer,
+    /// Identity provider for single sign-on, when configured
+    pub oidc: Option<Arc<OidcProvider>>,
 }
 
 pub type AppState = Arc<BaseState>;
@@ -49,21 +54,29 @@ impl BaseState {
 
         let redis_pool = redis_pool.unwrap();
 
-        // Initialize JWT service with auto-generated or loaded RSA keys
-        let (private_key, public_key) = super::keys::KeyManager::load_or_generate_keys()?;
-        let jwt = JwtService::new(
-            &private_key,
-            &public_key,
-            config.access_token_expiry,
-            config.refresh_token_expiry,
+        // Initialize JWT service with configured, loaded or generated signing keys
+        let keys = super::keys::load_key_ring(&config)?;
+        let jwt = keys.previous.iter().try_fold(
+            JwtService::new(
+                &keys.active,
+                config.access_token_expiry,
+                config.refresh_token_expiry,
+            )?
+            .with_issuer(&config.jwt_issuer),
+            |jwt, key| jwt.with_previous_key(key),
         )?;
 
+        let mailer = Mailer::from_config(&config)?;
+        let oidc = OidcProvider::from_config(&config)?.map(Arc::new);
+
         let base_state = BaseState {
             db_pool: pg_pool,
             config: Arc::new(config),
             redis_pool,
             argon2: argon2::Argon2::default(),
             jwt: Arc::new(jwt),
+            mailer,
+            oidc,
         };
 
         base_state.init_admin_user().await?;
@@ -101,9 +114,17 @@ impl BaseState {
                 .map_err(|e| format!("Failed to hash password: {}", e))?
                 .to_string();
 
+            // The bootstrap admin administers the default org, recreated if it was deleted
             client
                 .execute(
-                    "INSERT INTO users (email, password_hash, role) VALUES ($1, $2, $3)",
+                    "WITH org AS (
+                        INSERT INTO orgs (name, slug, region)
+                        VALUES ('Default Org', 'default-org', 'us-east-1')
+                        ON CONFLICT (slug) DO UPDATE SET slug = EXCLUDED.slug
+                        RETURNING id
+                     )
+                     INSERT INTO users (email, password_hash, role, org_id, email_verified_at)
+                     SELECT $1, $2, $3, id, current_timestamp FROM org",
                     &[&admin_email, &hashed_password, &UserRole::Admin],
                 )
                 .await?;
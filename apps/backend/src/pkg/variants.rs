@@ -0,0 +1,252 @@
+use crate::models::db::{FeatureFlagVariants, FeatureFlags};
+use crate::models::enums::{FeatureFlagType, ValueType};
+use crate::pkg::error::AppError;
+use crate::pkg::rollout::Rollout;
+use serde_json::Value;
+
+/// Whether a value has the JSON shape a variant's `ValueType` declares
+pub fn matches_value_type(value_type: &ValueType, value: &Value) -> bool {
+    match value_type {
+        ValueType::String => value.is_string(),
+        ValueType::Number => value.is_number(),
+        ValueType::Boolean => value.is_boolean(),
+        ValueType::Json => true,
+    }
+}
+
+/// Check a variant's value matches its declared type
+pub fn validate_variant_value(value_type: &ValueType, value: &Value) -> Result<(), AppError> {
+    if !matches_value_type(value_type, value) {
+        let expected = match value_type {
+            ValueType::String => "a string",
+            ValueType::Number => "a number",
+            ValueType::Boolean => "a boolean",
+            ValueType::Json => "a JSON value",
+        };
+        return Err(AppError::UnprocessableEntity(format!(
+            "value: expected {}",
+            expected
+        )));
+    }
+
+    Ok(())
+}
+
+/// Compile a JSON Schema, rejecting schemas that are not valid themselves
+pub fn compile_schema(schema: &Value) -> Result<jsonschema::Validator, AppError> {
+    jsonschema::validator_for(schema)
+        .map_err(|e| AppError::UnprocessableEntity(format!("json_schema: {}", e)))
+}
+
+/// Append a JSON pointer from a schema error to a request path, `/limits/0` becomes `.limits[0]`
+fn join_path(path: &str, pointer: &str) -> String {
+    let mut joined = path.to_string();
+    for segment in pointer.split('/').skip(1) {
+        let segment = segment.replace("~1", "/").replace("~0", "~");
+        if segment.parse::<usize>().is_ok() {
+            joined.push_str(&format!("[{}]", segment));
+        } else {
+            joined.push_str(&format!(".{}", segment));
+        }
+    }
+    joined
+}
+
+/// Checks values written to a flag, in its states, overrides and rollouts,
+/// against the flag's type, variants and JSON Schema
+pub struct ValueValidator {
+    flag_type: FeatureFlagType,
+    variants: Vec<FeatureFlagVariants>,
+    schema: Option<jsonschema::Validator>,
+}
+
+impl ValueValidator {
+    pub fn new(flag: &FeatureFlags, variants: Vec<FeatureFlagVariants>) -> Result<Self, AppError> {
+        Ok(ValueValidator {
+            flag_type: flag.r#type,
+            variants,
+            schema: flag.json_schema.as_ref().map(compile_schema).transpose()?,
+        })
+    }
+
+    /// Validate a value written at `path` of the request, the error names the offending path
+    pub fn validate(&self, path: &str, value: &Value) -> Result<(), AppError> {
+        match self.flag_type {
+            FeatureFlagType::Boolean => {
+                if !value.is_boolean() {
+                    return Err(AppError::UnprocessableEntity(format!(
+                        "{}: expected a boolean",
+                        path
+                    )));
+                }
+            }
+            FeatureFlagType::Multivariate => {
+                if !self.variants.iter().any(|v| &v.value == value) {
+                    let names = self
+                        .variants
+                        .iter()
+                        .map(|v| v.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    return Err(AppError::UnprocessableEntity(format!(
+                        "{}: expected the value of one of the flag's variants ({})",
+                        path, names
+                    )));
+                }
+            }
+            FeatureFlagType::Json => {
+                if let Some(error) = self
+                    .schema
+                    .as_ref()
+                    .and_then(|schema| schema.iter_errors(value).next())
+                {
+                    return Err(AppError::UnprocessableEntity(format!(
+                        "{}: {}",
+                        join_path(path, error.instance_path().as_str()),
+                        error
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Validate the value of every weighted variant in a rollout
+    pub fn validate_rollout(&self, path: &str, rollout: &Rollout) -> Result<(), AppError> {
+        for (i, variant) in rollout.variants.iter().flatten().enumerate() {
+            self.validate(&format!("{}.variants[{}].value", path, i), &variant.value)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    fn flag(r#type: FeatureFlagType, json_schema: Option<Value>) -> FeatureFlags {
+        FeatureFlags {
+            id: Uuid::new_v4(),
+            org_id: None,
+            key: "checkout".to_string(),
+            r#type,
+            project_id: None,
+            created_at: None,
+            updated_at: None,
+            json_schema,
+        }
+    }
+
+    fn variant(name: &str, value_type: ValueType, value: Value) -> FeatureFlagVariants {
+        FeatureFlagVariants {
+            id: Uuid::new_v4(),
+            feature_flag_id: Uuid::nil(),
+            name: name.to_string(),
+            value_type,
+            value,
+            description: None,
+            created_at: None,
+            updated_at: None,
+        }
+    }
+
+    fn message(error: AppError) -> String {
+        match error {
+            AppError::UnprocessableEntity(msg) => msg,
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_matches_value_type() {
+        assert!(matches_value_type(&ValueType::String, &json!("a")));
+        assert!(!matches_value_type(&ValueType::String, &json!(1)));
+        assert!(matches_value_type(&ValueType::Number, &json!(1.5)));
+        assert!(!matches_value_type(&ValueType::Number, &json!("1")));
+        assert!(matches_value_type(&ValueType::Boolean, &json!(false)));
+        assert!(matches_value_type(&ValueType::Json, &json!({ "a": [1] })));
+    }
+
+    #[test]
+    fn test_validate_boolean() {
+        let validator = ValueValidator::new(&flag(FeatureFlagType::Boolean, None), vec![]).unwrap();
+        assert!(validator.validate("value", &json!(true)).is_ok());
+        assert_eq!(
+            message(validator.validate("value", &json!("true")).unwrap_err()),
+            "value: expected a boolean"
+        );
+    }
+
+    #[test]
+    fn test_validate_multivariate() {
+        let validator = ValueValidator::new(
+            &flag(FeatureFlagType::Multivariate, None),
+            vec![
+                variant("control", ValueType::String, json!("control")),
+                variant("treatment", ValueType::String, json!("treatment")),
+            ],
+        )
+        .unwrap();
+
+        assert!(validator.validate("value", &json!("treatment")).is_ok());
+        assert_eq!(
+            message(validator.validate("value", &json!("other")).unwrap_err()),
+            "value: expected the value of one of the flag's variants (control, treatment)"
+        );
+
+        let rollout: Rollout = serde_json::from_value(json!({
+            "bucket_by": "user_id",
+            "variants": [
+                { "value": "control", "weight": 1 },
+                { "value": "missing", "weight": 1 },
+            ],
+        }))
+        .unwrap();
+        assert!(
+            message(validator.validate_rollout("rollout", &rollout).unwrap_err())
+                .starts_with("rollout.variants[1].value:")
+        );
+    }
+
+    #[test]
+    fn test_validate_json_schema() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "limits": { "type": "array", "items": { "type": "integer" } },
+            },
+            "required": ["limits"],
+        });
+        let validator =
+            ValueValidator::new(&flag(FeatureFlagType::Json, Some(schema)), vec![]).unwrap();
+
+        assert!(
+            validator
+                .validate("value", &json!({ "limits": [1, 2] }))
+                .is_ok()
+        );
+        assert!(
+            message(
+                validator
+                    .validate("value", &json!({ "limits": [1, "2"] }))
+                    .unwrap_err()
+            )
+            .starts_with("value.limits[1]:")
+        );
+        assert!(validator.validate("value", &json!({})).is_err());
+
+        // Without a schema any JSON value is accepted
+        let validator = ValueValidator::new(&flag(FeatureFlagType::Json, None), vec![]).unwrap();
+        assert!(validator.validate("value", &json!([1, "a"])).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_schema() {
+        assert!(compile_schema(&json!({ "type": "not-a-type" })).is_err());
+        assert!(compile_schema(&json!({ "type": "string" })).is_ok());
+    }
+}
@@ -2,6 +2,7 @@ extern crate proc_macro;
 
 use proc_macro::TokenStream;
 use quote::quote;
+use syn::ext::IdentExt;
 use syn::{parse_macro_input, Data, DeriveInput, Fields, Meta};
 
 /// Derive macro that implements `FromRow` for structs
@@ -50,7 +51,8 @@ pub fn derive_from_row(input: TokenStream) -> TokenStream {
 
     for field in &fields.named {
         let field_name = &field.ident;
-        let mut column_name = field_name.as_ref().unwrap().to_string();
+        // Raw identifiers such as `r#type` map to the plain column name
+        let mut column_name = field_name.as_ref().unwrap().unraw().to_string();
 
         // Check for #[serde(rename = "...")] attribute
         for attr in &field.attrs {